@@ -0,0 +1,84 @@
+//! Animates 100,000 spheres, of which a few hundred fly off in all
+//! directions while the rest stay put, and times keeping the acceleration
+//! structure from frame to frame, refit where it can be, against building it
+//! anew every frame. Both must find the same hits. Run with
+//! `cargo run --release --example animation [bvh|bvh4|bvh8|kdtree|grid]`.
+
+mod common;
+
+use std::time::Instant;
+
+use jray::sphere::Sphere;
+use jray::*;
+
+use common::Random;
+
+const SPHERES: usize = 100_000;
+/// Every this many spheres, one moves.
+const MOVING: usize = 250;
+const FRAMES: usize = 40;
+const RAYS: usize = 10_000;
+
+const MATERIAL: Material = Material::opaque(WHITE);
+
+fn main() {
+    let kind = common::accelerator();
+
+    let mut random = Random(1);
+    let radius = 0.3 / (SPHERES as Float).cbrt();
+    let mut spheres: Vec<Sphere> = (0..SPHERES)
+        .map(|_| Sphere {
+            center: random.point(),
+            radius,
+        })
+        .collect();
+    let velocities: Vec<Direction> = (0..SPHERES).map(|_| random.direction()).collect();
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| Ray::from_points(Point(Vec3([0.5, -1.5, 0.5])), random.point()))
+        .collect();
+    let objects = |spheres: &[Sphere]| -> Vec<Object> {
+        spheres.iter().map(|s| Object::new(*s, MATERIAL)).collect()
+    };
+
+    println!(
+        "{:>5} {:>8} {:>10} {:>10} {:>10} {:>10}",
+        "frame", "", "update ms", "nodes/ray", "build ms", "nodes/ray"
+    );
+    let first = objects(&spheres);
+    let mut structure = ObjectTree::new(&first, kind).into_structure();
+    let (mut updated_total, mut built_total) = (0.0, 0.0);
+    for frame in 1..=FRAMES {
+        for (s, v) in spheres.iter_mut().zip(&velocities).step_by(MOVING) {
+            s.center = s.center + 0.1 * *v;
+        }
+        let objects = objects(&spheres);
+
+        let start = Instant::now();
+        let tree = structure.update(&objects);
+        let updated = start.elapsed().as_secs_f64() * 1e3;
+        let start = Instant::now();
+        let built = ObjectTree::new(&objects, kind);
+        let built_time = start.elapsed().as_secs_f64() * 1e3;
+        updated_total += updated;
+        built_total += built_time;
+
+        for r in &rays {
+            let distance =
+                |tree: &ObjectTree| tree.closest_intersection(r).map(|(_, i)| i.distance);
+            assert_eq!(distance(&built), distance(&tree));
+        }
+        let per_ray = |stats: &accel::Stats| stats.nodes_visited as Float / RAYS as Float;
+        let stats = tree.stats();
+        println!(
+            "{:>5} {:>8} {:>10.2} {:>10.1} {:>10.2} {:>10.1}",
+            frame,
+            if stats.refits > 0 { "refit" } else { "rebuilt" },
+            updated,
+            per_ray(&stats),
+            built_time,
+            per_ray(&built.stats())
+        );
+        structure = tree.into_structure();
+    }
+    println!("total {:>19.1} {:>21.1}", updated_total, built_total);
+}
@@ -0,0 +1,99 @@
+//! Times nearest-hit queries against scenes of 10 to 1,000,000 spheres, with
+//! an acceleration structure and, while it finishes in reasonable time, with
+//! a plain loop over every object. Run with
+//! `cargo run --release --example bvh_scaling [bvh|bvh4|bvh8|kdtree|grid]`.
+
+mod common;
+
+use std::time::Instant;
+
+use jray::plane::Plane;
+use jray::sphere::Sphere;
+use jray::*;
+
+use common::Random;
+
+const RAYS: usize = 100_000;
+/// Largest scene the linear search is timed on.
+const MAX_LINEAR: usize = 10_000;
+
+const MATERIAL: Material = Material::opaque(WHITE);
+
+fn main() {
+    let kind = common::accelerator();
+    let mut random = Random(1);
+    // rays from a ring around the unit cube towards points inside it
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| {
+            let angle = 2.0 * consts::PI * random.next();
+            let origin = Point(Vec3([
+                0.5 + 2.0 * angle.cos(),
+                0.5 + 2.0 * angle.sin(),
+                random.next(),
+            ]));
+            Ray::from_points(origin, random.point())
+        })
+        .collect();
+
+    println!(
+        "{:>9} {:>10} {:>8} {:>12} {:>12} {:>8}",
+        "spheres", "build ms", "nodes", "tree ns/ray", "loop ns/ray", "hits"
+    );
+    for n in [10, 100, 1_000, 10_000, 100_000, 1_000_000] {
+        // the same share of the cube filled at every size
+        let radius = 0.3 / (n as Float).cbrt();
+        let mut objects: Vec<Object> = (0..n)
+            .map(|_| {
+                Object::new(
+                    Sphere {
+                        center: random.point(),
+                        radius,
+                    },
+                    MATERIAL,
+                )
+            })
+            .collect();
+        // a floor, which the BVH cannot hold
+        objects.push(Object::new(
+            Plane {
+                point: Point(Vec3([0.0, 0.0, -1.0])),
+                normal: Direction(Vec3([0.0, 0.0, 1.0])),
+            },
+            MATERIAL,
+        ));
+
+        let start = Instant::now();
+        let tree = ObjectTree::new(&objects, kind);
+        let build = start.elapsed();
+
+        let start = Instant::now();
+        let hits = rays
+            .iter()
+            .filter(|r| tree.closest_intersection(r).is_some())
+            .count();
+        let accelerated = start.elapsed().as_nanos() as Float / RAYS as Float;
+
+        let linear = if n <= MAX_LINEAR {
+            let start = Instant::now();
+            for r in &rays {
+                objects
+                    .iter()
+                    .filter_map(|o| o.shape.find_intersection(r))
+                    .min_by(|a, b| a.distance.total_cmp(&b.distance));
+            }
+            format!("{:.0}", start.elapsed().as_nanos() as Float / RAYS as Float)
+        } else {
+            "-".to_owned()
+        };
+
+        println!(
+            "{:>9} {:>10.1} {:>8} {:>12.0} {:>12} {:>8}",
+            n,
+            build.as_secs_f64() * 1e3,
+            tree.stats().nodes,
+            accelerated,
+            linear,
+            hits
+        );
+    }
+}
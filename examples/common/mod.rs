@@ -0,0 +1,36 @@
+//! Helpers the benchmarking examples share. Each example uses some of them.
+#![allow(dead_code)]
+
+use jray::*;
+
+/// Deterministic numbers in [0, 1).
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn next(&mut self) -> Float {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as Float / (1u64 << 53) as Float
+    }
+
+    /// A point in the unit cube.
+    pub fn point(&mut self) -> Point {
+        Point(Vec3([self.next(), self.next(), self.next()]))
+    }
+
+    /// A direction out of the middle of the unit cube, not normalized.
+    pub fn direction(&mut self) -> Direction {
+        Direction(self.point().0 - Vec3([0.5; 3]))
+    }
+}
+
+/// The acceleration structure named by the first argument, the BVH without
+/// one.
+pub fn accelerator() -> accel::Kind {
+    std::env::args()
+        .nth(1)
+        .map_or(Ok(accel::Kind::Bvh), |name| name.parse())
+        .unwrap_or_else(|e| panic!("{}", e))
+}
@@ -0,0 +1,140 @@
+//! A shape defined outside jray: an open tube, standing on a floor around a
+//! sphere.
+//!
+//! Run with `cargo run --release --example custom_shape [out.png]`.
+
+use jray::aabb::Aabb;
+use jray::plane::Plane;
+use jray::sphere::Sphere;
+use jray::*;
+
+/// The side of an upright cylinder, without its ends.
+#[derive(Debug)]
+struct Tube {
+    /// Center of the bottom rim.
+    base: Point,
+    radius: Float,
+    height: Float,
+}
+
+impl Tube {
+    /// Outward normal at a point on or near the side.
+    fn normal(&self, p: Point) -> Direction {
+        let [x, y, _] = (p - self.base).0 .0;
+        Direction(Vec3([x, y, 0.0])).normalized()
+    }
+}
+
+impl Shape for Tube {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        // the ray seen from above, against a circle
+        let [ox, oy, _] = (r.0 - self.base).0 .0;
+        let [dx, dy, _] = r.1 .0 .0;
+        let a = dx * dx + dy * dy;
+        if a == 0.0 {
+            return None;
+        }
+        let b = ox * dx + oy * dy;
+        let c = ox * ox + oy * oy - self.radius * self.radius;
+        let discriminant = b * b - a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        // the nearer crossing, or the far one if that is cut off or behind
+        let distance = [(-b - root) / a, (-b + root) / a].into_iter().find(|t| {
+            let z = r.0 .0 .0[2] + t * r.1 .0 .0[2] - self.base.0 .0[2];
+            *t > 0.0 && (0.0..=self.height).contains(&z)
+        })?;
+        let point = r.0 + distance * r.1;
+        let normal = self.normal(point);
+        // back onto the side, so the error bound only has to cover this
+        let point = point + (self.radius - (point - self.base).dot(&normal)) * normal;
+        let [nx, ny, _] = normal.0 .0;
+        Some(Intersection {
+            distance,
+            point,
+            surface_normal: normal,
+            geometric_normal: normal,
+            front_face: r.1.dot(&normal) < 0.0,
+            tangent: None,
+            uv: (
+                (ny.atan2(nx) / (2.0 * consts::PI)).rem_euclid(1.0),
+                (point - self.base).0 .0[2] / self.height,
+            ),
+            dpdu: (2.0 * consts::PI * self.radius) * Direction(Vec3([-ny, nx, 0.0])),
+            dpdv: Direction(Vec3([0.0, 0.0, self.height])),
+            primitive: 0,
+            object: 0,
+            error: gamma(7) * (self.base.0.abs() + point.0.abs()),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb {
+            min: self.base - Direction(Vec3([self.radius, self.radius, 0.0])),
+            max: self.base + Direction(Vec3([self.radius, self.radius, self.height])),
+        })
+    }
+
+    fn sample_surface(&self, s: (Float, Float)) -> Option<(Point, Direction)> {
+        let angle = 2.0 * consts::PI * s.0;
+        let normal = Direction(Vec3([angle.cos(), angle.sin(), 0.0]));
+        let up = Direction(Vec3([0.0, 0.0, s.1 * self.height]));
+        Some((self.base + self.radius * normal + up, normal))
+    }
+}
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "tube.png".to_owned());
+
+    let scene = Scene {
+        camera: Camera {
+            ray: Ray(
+                Point(Vec3([0.0, -6.0, 2.5])),
+                Direction(Vec3([0.0, 1.0, -0.4])).normalized(),
+            ),
+            up: Direction(Vec3([0.0, 0.4, 1.0])).normalized(),
+            w_fov_degrees: 50.0,
+        },
+        imgx: 320,
+        imgy: 240,
+        objects: vec![
+            Object::new(
+                Plane {
+                    point: Point::origin(),
+                    normal: Direction(Vec3([0.0, 0.0, 1.0])),
+                },
+                Material::opaque(0.5 * WHITE),
+            ),
+            Object::new(
+                Tube {
+                    base: Point::origin(),
+                    radius: 1.4,
+                    height: 0.8,
+                },
+                Material::opaque(GREEN),
+            ),
+            Object::new(
+                Sphere {
+                    center: Point(Vec3([0.0, 0.0, 0.7])),
+                    radius: 0.7,
+                },
+                Material::opaque(BLUE),
+            ),
+        ],
+        lights: vec![Light {
+            point: Point(Vec3([-3.0, -3.0, 5.0])),
+            color: WHITE,
+            radius: 0.0,
+            intensity: 1.0,
+        }],
+        accelerator: accel::Kind::default(),
+        renderer: wavefront::Renderer::default(),
+        depth: 1,
+    };
+
+    scene.render(&path);
+}
@@ -0,0 +1,128 @@
+//! Places a rounded box mesh 1,000 to 8,000 times, turned and scaled, once
+//! as instances sharing the mesh and its BVH, and once as separate copies
+//! with the transform baked into their vertices. Times building and tracing
+//! both, and checks that they find the same hits. Run with
+//! `cargo run --release --example instancing [bvh|bvh4|bvh8|kdtree|grid]`.
+
+mod common;
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use jray::instance::{Instance, Transform};
+use jray::*;
+
+use common::Random;
+
+const RAYS: usize = 100_000;
+
+const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                    v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                    f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+const MATERIAL: Material = Material::opaque(WHITE);
+
+fn main() {
+    let kind = common::accelerator();
+
+    let cube = mesh::parse_obj(CUBE.as_bytes()).unwrap();
+    let rounded = subdivision::subdivide(&cube, subdivision::Target::Level(3));
+    let shared: Arc<dyn Shape> = Arc::new(rounded.triangulate());
+    let triangles = rounded.triangles().len();
+
+    println!(
+        "{:>6} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8}",
+        "copies", "triangles", "build ms", "ns/ray", "triangles", "build ms", "ns/ray", "hits"
+    );
+    println!(
+        "{:>6} {:>32} {:>32}",
+        "", "---------- instanced ----------", "----------- copied -----------"
+    );
+    let mut random = Random(1);
+    for side in [10, 15, 20] {
+        let n = side * side * side;
+        // a grid of boxes, each turned some way and stretched a little
+        let transforms: Vec<Transform> = (0..n)
+            .map(|k| {
+                let cell = [k % side, k / side % side, k / (side * side)];
+                let stretch = Vec3([0.2 + 0.1 * random.next(), 0.2, 0.3 * random.next() + 0.1]);
+                Transform::scaling(stretch)
+                    .then(&Transform::rotation(
+                        random.direction(),
+                        6.0 * random.next(),
+                    ))
+                    .then(&Transform::translation(Direction(Vec3(
+                        cell.map(|c| c as Float),
+                    ))))
+            })
+            .collect();
+
+        let start = Instant::now();
+        let instances: Vec<Object> = transforms
+            .iter()
+            .map(|t| Object::new(Instance::new(shared.clone(), *t), MATERIAL))
+            .collect();
+        let instanced_tree = ObjectTree::new(&instances, kind);
+        let instanced_build = start.elapsed().as_secs_f64() * 1e3;
+
+        let start = Instant::now();
+        let copies: Vec<Object> = transforms
+            .iter()
+            .map(|t| {
+                let mut copy = rounded.clone();
+                for v in copy.vertices.iter_mut() {
+                    *v = t.point(v);
+                }
+                Object::new(copy.triangulate(), MATERIAL)
+            })
+            .collect();
+        let copied_tree = ObjectTree::new(&copies, kind);
+        let copied_build = start.elapsed().as_secs_f64() * 1e3;
+
+        // from outside one corner, across the grid
+        let origin = Point(Vec3([-2.0, -3.0, -1.0]));
+        let rays: Vec<Ray> = (0..RAYS)
+            .map(|_| {
+                let target = Vec3([random.next(), random.next(), random.next()]);
+                Ray::from_points(origin, Point(target * side as Float))
+            })
+            .collect();
+        let trace = |tree: &ObjectTree| {
+            let start = Instant::now();
+            let hits: Vec<Option<(usize, Float)>> = rays
+                .iter()
+                .map(|r| {
+                    tree.closest_intersection(r)
+                        .map(|(_, i)| (i.object, i.distance))
+                })
+                .collect();
+            (hits, start.elapsed().as_nanos() as Float / RAYS as Float)
+        };
+        let (instanced_hits, instanced_time) = trace(&instanced_tree);
+        let (copied_hits, copied_time) = trace(&copied_tree);
+
+        // rounding differs between the two, which may tip rays that graze
+        // an edge either way, but only a few
+        let differ = instanced_hits
+            .iter()
+            .zip(&copied_hits)
+            .filter(|(a, b)| match (a, b) {
+                (Some(a), Some(b)) => a.0 != b.0 || (a.1 - b.1).abs() > per_precision(1e-6, 1e-3),
+                (a, b) => a.is_some() != b.is_some(),
+            })
+            .count();
+        assert!(differ * 1000 < RAYS, "{} rays disagree", differ);
+
+        println!(
+            "{:>6} {:>10} {:>10.1} {:>10.0} {:>10} {:>10.1} {:>10.0} {:>8}",
+            n,
+            triangles,
+            instanced_build,
+            instanced_time,
+            n * triangles,
+            copied_build,
+            copied_time,
+            instanced_hits.iter().flatten().count()
+        );
+    }
+}
@@ -0,0 +1,81 @@
+//! Times camera rays traced one at a time against the same rays traced in
+//! 8x8 packets, over scenes of 1,000 to 1,000,000 spheres, and checks that
+//! both find the same hits. Run with
+//! `cargo run --release --example packet_tracing [bvh|bvh4|bvh8|kdtree|grid]`.
+
+mod common;
+
+use std::time::Instant;
+
+use jray::packet::Packet;
+use jray::sphere::Sphere;
+use jray::*;
+
+use common::Random;
+
+const SIZE: usize = 512;
+const TILE: usize = 8;
+
+const MATERIAL: Material = Material::opaque(WHITE);
+
+fn main() {
+    let kind = common::accelerator();
+
+    // a camera looking into the unit cube from in front, a tile per packet
+    let origin = Point(Vec3([0.5, -1.5, 0.5]));
+    let packets: Vec<Packet> = (0..SIZE / TILE)
+        .flat_map(|ty| (0..SIZE / TILE).map(move |tx| (tx, ty)))
+        .map(|(tx, ty)| {
+            let rays = (0..TILE * TILE)
+                .map(|k| {
+                    let x = (tx * TILE + k % TILE) as Float / SIZE as Float;
+                    let z = (ty * TILE + k / TILE) as Float / SIZE as Float;
+                    Ray::from_points(origin, Point(Vec3([x, 0.0, z])))
+                })
+                .collect();
+            Packet::new(rays)
+        })
+        .collect();
+    let rays = (SIZE * SIZE) as Float;
+
+    let mut random = Random(1);
+    println!(
+        "{:>9} {:>14} {:>14} {:>8}",
+        "spheres", "single ns/ray", "packet ns/ray", "hits"
+    );
+    for n in [1_000, 10_000, 100_000, 1_000_000] {
+        let radius = 0.3 / (n as Float).cbrt();
+        let objects: Vec<Object> = (0..n)
+            .map(|_| {
+                let center = random.point();
+                Object::new(Sphere { center, radius }, MATERIAL)
+            })
+            .collect();
+        let tree = ObjectTree::new(&objects, kind);
+
+        let start = Instant::now();
+        let single: Vec<Option<Float>> = packets
+            .iter()
+            .flat_map(|p| p.rays())
+            .map(|r| tree.closest_intersection(r).map(|(_, i)| i.distance))
+            .collect();
+        let single_time = start.elapsed().as_nanos() as Float / rays;
+
+        let start = Instant::now();
+        let packed: Vec<Option<Float>> = packets
+            .iter()
+            .flat_map(|p| tree.closest_intersections(p))
+            .map(|hit| hit.map(|(_, i)| i.distance))
+            .collect();
+        let packet_time = start.elapsed().as_nanos() as Float / rays;
+
+        assert_eq!(single, packed);
+        println!(
+            "{:>9} {:>14.0} {:>14.0} {:>8}",
+            n,
+            single_time,
+            packet_time,
+            single.iter().flatten().count()
+        );
+    }
+}
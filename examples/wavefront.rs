@@ -0,0 +1,175 @@
+//! Renders the demo scene, five spheres in a box of mirrored walls, with
+//! the recursive renderer and the wavefront one, following reflections
+//! from 1 to 4 bounces deep; then again with a cloud of 100,000 small
+//! spheres added, where far more of the time goes into the acceleration
+//! structure. Times both renderers, keeping the fastest of a few runs, and
+//! checks that they draw the same image, up to rounding. The demo scene is
+//! bound by shading, and the wavefront renderer is about as fast on it,
+//! from 0.9 to 1.2 times by depth; with the cloud it is about twice as
+//! fast. Run with
+//! `cargo run --release --example wavefront [bvh|bvh4|bvh8|kdtree|grid]`.
+
+mod common;
+
+use std::time::Instant;
+
+use jray::plane::Plane;
+use jray::sphere::Sphere;
+use jray::wavefront::Renderer;
+use jray::*;
+
+use common::Random;
+
+/// Times each render is repeated, keeping the fastest.
+const RUNS: usize = 3;
+
+fn material(diffuse_color: Color, specular_color: Color, reflectivity: Float) -> Material {
+    Material {
+        specular_color,
+        reflectivity,
+        ..Material::opaque(diffuse_color)
+    }
+}
+
+/// The demo scene of the `jray` binary, at a quarter of the pixels, with
+/// `cloud` more spheres scattered through the middle of the box.
+fn scene(accelerator: accel::Kind, renderer: Renderer, depth: usize, cloud: usize) -> Scene {
+    let sphere = |center, radius, material| Object::new(Sphere { center, radius }, material);
+    let wall = |normal: Vec3, specular| {
+        Object::new(
+            Plane {
+                point: Point(-10.0 * normal),
+                normal: Direction(normal),
+            },
+            material(0.1 * WHITE, specular, 0.7),
+        )
+    };
+    let mut objects = vec![
+        wall(Vec3([0.0, 0.0, 1.0]), BLACK),
+        wall(Vec3([0.0, 0.0, -1.0]), 0.1 * WHITE),
+        wall(Vec3([-1.0, 0.0, 0.0]), 0.1 * WHITE),
+        wall(Vec3([1.0, 0.0, 0.0]), 0.1 * WHITE),
+        wall(Vec3([0.0, -1.0, 0.0]), 0.1 * WHITE),
+        wall(Vec3([0.0, 1.0, 0.0]), 0.5 * WHITE),
+        sphere(Point::origin(), 0.7, material(BLUE, WHITE, 0.0)),
+        sphere(
+            Point(Vec3([-0.7, 0.7, -1.0])),
+            1.0,
+            material(RED, BLACK, 0.0),
+        ),
+        sphere(
+            Point(Vec3([1.0, -1.0, 1.0])),
+            0.5,
+            material(GREEN, 0.5 * WHITE, 0.0),
+        ),
+        sphere(
+            Point(Vec3([-2.2, 0.6, 0.6])),
+            0.6,
+            Material {
+                transparency: 1.0,
+                refractive_index: 1.5,
+                ..Material::opaque(BLACK)
+            },
+        ),
+        sphere(
+            Point(Vec3([0.8, 1.6, -0.6])),
+            0.5,
+            Material {
+                conductor: Some(optics::Conductor::GOLD),
+                ..Material::opaque(BLACK)
+            },
+        ),
+    ];
+    let mut random = Random(1);
+    let radius = 0.5 / (cloud.max(1) as Float).cbrt();
+    for _ in 0..cloud {
+        let center = Vec3([random.next(), random.next(), random.next()]);
+        let center = Point(8.0 * center - Vec3([4.0; 3]));
+        objects.push(sphere(center, radius, material(WHITE, WHITE, 0.0)));
+    }
+    Scene {
+        camera: Camera {
+            ray: Ray::from_points(Point(Vec3([-4.9, 3.0, 3.0])), Point::origin()),
+            up: Direction(Vec3([0.0, 0.0, 1.0])),
+            w_fov_degrees: 90.0,
+        },
+        imgx: 400,
+        imgy: 400,
+        objects,
+        lights: vec![
+            Light {
+                point: Point(Vec3([-2.0, 1.0, 0.7])),
+                color: WHITE,
+                intensity: 0.6,
+                radius: 0.05,
+            },
+            Light {
+                point: Point(Vec3([-2.0, -2.0, 2.0])),
+                color: WHITE,
+                intensity: 0.7,
+                radius: 0.05,
+            },
+        ],
+        accelerator,
+        renderer,
+        depth,
+    }
+}
+
+fn main() {
+    let kind = common::accelerator();
+
+    println!(
+        "{:>7} {:>5} {:>13} {:>13} {:>13} {:>13} {:>8}",
+        "spheres", "depth", "recursive ms", "queries", "wavefront ms", "queries", "speedup"
+    );
+    for cloud in [0, 100_000] {
+        let (mut recursive_total, mut wavefront_total) = (0.0, 0.0);
+        for depth in 1..=4 {
+            let render = |renderer| {
+                let scene = scene(kind, renderer, depth, cloud);
+                let start = Instant::now();
+                let (image, stats) = scene.render_image();
+                (image, stats, start.elapsed().as_secs_f64() * 1e3)
+            };
+            // the renderers take turns, and each keeps its fastest run, so
+            // that whatever else the machine is doing weighs on both alike
+            let (recursive, recursive_stats, mut recursive_time) = render(Renderer::Recursive);
+            let (wavefront, wavefront_stats, mut wavefront_time) = render(Renderer::Wavefront);
+            for _ in 1..RUNS {
+                recursive_time = recursive_time.min(render(Renderer::Recursive).2);
+                wavefront_time = wavefront_time.min(render(Renderer::Wavefront).2);
+            }
+            recursive_total += recursive_time;
+            wavefront_total += wavefront_time;
+
+            // reflections are weighted before they are summed rather than
+            // after, which may round a channel the other way
+            for (a, b) in recursive.pixels().zip(wavefront.pixels()) {
+                for (a, b) in a.0.iter().zip(b.0) {
+                    assert!(a.abs_diff(b) <= 1, "{:?} against {:?}", a, b);
+                }
+            }
+            println!(
+                "{:>7} {:>5} {:>13.0} {:>13} {:>13.0} {:>13} {:>8.2}",
+                cloud + 5,
+                depth,
+                recursive_time,
+                recursive_stats.queries,
+                wavefront_time,
+                wavefront_stats.queries,
+                recursive_time / wavefront_time
+            );
+        }
+        println!(
+            "{:>7} {:>5} {:>13.0} {:>13} {:>13.0} {:>13} {:>8.2}",
+            "",
+            "total",
+            recursive_total,
+            "",
+            wavefront_total,
+            "",
+            recursive_total / wavefront_total
+        );
+    }
+}
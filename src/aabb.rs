@@ -0,0 +1,113 @@
+use crate::*;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub const fn empty() -> Aabb {
+        Aabb {
+            min: Point(Vec3([Float::INFINITY; 3])),
+            max: Point(Vec3([Float::NEG_INFINITY; 3])),
+        }
+    }
+
+    pub fn grow(&mut self, p: &Point) {
+        for axis in 0..3 {
+            self.min.0 .0[axis] = self.min.0 .0[axis].min(p.0 .0[axis]);
+            self.max.0 .0[axis] = self.max.0 .0[axis].max(p.0 .0[axis]);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut union = *self;
+        union.grow(&other.min);
+        union.grow(&other.max);
+        union
+    }
+
+    pub fn center(&self) -> Point {
+        Point(0.5 * (self.min.0 + self.max.0))
+    }
+
+    /// Zero for an empty box.
+    pub fn surface_area(&self) -> Float {
+        let [x, y, z] = (self.max - self.min).0 .0;
+        if x < 0.0 || y < 0.0 || z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    pub fn padded(mut self, amount: Float) -> Aabb {
+        for axis in 0..3 {
+            self.min.0 .0[axis] -= amount;
+            self.max.0 .0[axis] += amount;
+        }
+        self
+    }
+
+    pub fn closest_point(&self, p: &Point) -> Point {
+        let mut closest = *p;
+        for axis in 0..3 {
+            closest.0 .0[axis] = p.0 .0[axis].clamp(self.min.0 .0[axis], self.max.0 .0[axis]);
+        }
+        closest
+    }
+
+    /// Slab test. Returns the entry and exit distances along the ray, with the
+    /// entry clamped to zero when the origin is inside the box.
+    pub fn intersect(&self, r: &Ray) -> Option<(Float, Float)> {
+        let mut t_near: Float = 0.0;
+        let mut t_far = Float::INFINITY;
+        for axis in 0..3 {
+            let inv = 1.0 / r.1 .0 .0[axis];
+            let mut t0 = (self.min.0 .0[axis] - r.0 .0 .0[axis]) * inv;
+            let mut t1 = (self.max.0 .0[axis] - r.0 .0 .0[axis]) * inv;
+            if inv < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // written so that NaN (0 * inf) leaves the interval unchanged
+            t_near = if t0 > t_near { t0 } else { t_near };
+            t_far = if t1 < t_far { t1 } else { t_far };
+            if t_near > t_far {
+                return None;
+            }
+        }
+        Some((t_near, t_far))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        let mut b = Aabb::empty();
+        b.grow(&Point(Vec3([-1.0, -1.0, -1.0])));
+        b.grow(&Point(Vec3([1.0, 1.0, 1.0])));
+        let hit = b.intersect(&Ray(
+            Point(Vec3([-5.0, 0.0, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        ));
+        assert_eq!(Some((4.0, 6.0)), hit);
+
+        let inside = b.intersect(&Ray(Point::origin(), Direction(Vec3([0.0, 0.0, -1.0]))));
+        assert_eq!(Some((0.0, 1.0)), inside);
+
+        let miss = b.intersect(&Ray(
+            Point(Vec3([-5.0, 2.0, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        ));
+        assert_eq!(None, miss);
+
+        let behind = b.intersect(&Ray(
+            Point(Vec3([5.0, 0.0, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        ));
+        assert_eq!(None, behind);
+    }
+}
@@ -0,0 +1,417 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::grid::Grid;
+use crate::kdtree::KdTree;
+use crate::packet::Packet;
+use crate::wide::WideBvh;
+use crate::*;
+
+/// Spatial index over a list of boxes, for finding which of the primitives
+/// inside them a ray hits. Primitives are known only by their index in the
+/// list the structure was built from; testing them is left to a callback.
+pub trait Accelerator: fmt::Debug + Send + Sync {
+    /// Distance to the nearest hit closer than `max_distance`. `hit(i, max)`
+    /// tests primitive `i` and returns the distance to it only if that is
+    /// below `max`. Each such return is nearer than the one before, so the
+    /// caller can keep whatever else it needs from the last one.
+    fn closest_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) -> Option<Float>;
+
+    /// Whether `hit` holds for any primitive the ray reaches before
+    /// `max_distance`, stopping at the first one.
+    fn any_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize) -> bool,
+        traversal: &mut Traversal,
+    ) -> bool;
+
+    /// Nearest hits for every ray of a packet, as `closest_hit` finds them
+    /// one at a time. `max_distances[k]` starts as the limit for ray `k`, and
+    /// is lowered to each hit found; `hit(k, i, max)` tests ray `k` against
+    /// primitive `i` as `closest_hit`'s callback does. Unless a structure
+    /// knows better, the rays are traced one by one.
+    fn closest_hits(
+        &self,
+        packet: &Packet,
+        max_distances: &mut [Float],
+        hit: &mut dyn FnMut(usize, usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) {
+        for (k, r) in packet.rays().iter().enumerate() {
+            let found =
+                self.closest_hit(r, max_distances[k], &mut |i, max| hit(k, i, max), traversal);
+            if let Some(distance) = found {
+                max_distances[k] = distance;
+            }
+        }
+    }
+
+    /// Moves the boxes to `bounds`, one for each box the structure was built
+    /// over and in the same order, keeping its shape. Returns false when
+    /// that leaves it too slow to search, or when it cannot be refit at all;
+    /// it must then be built anew before the next query.
+    fn refit(&mut self, _bounds: &[Aabb]) -> bool {
+        false
+    }
+
+    fn bounds(&self) -> Option<Aabb>;
+
+    /// Shape of the structure. Timings and traversal counts are left for the
+    /// owner to fill in.
+    fn stats(&self) -> Stats;
+}
+
+/// The acceleration structures to choose from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Kind {
+    #[default]
+    Bvh,
+    /// The BVH collapsed to 4 children per node.
+    Bvh4,
+    /// The BVH collapsed to 8 children per node.
+    Bvh8,
+    KdTree,
+    Grid,
+}
+
+impl Kind {
+    pub fn build(self, bounds: &[Aabb]) -> Box<dyn Accelerator> {
+        match self {
+            Kind::Bvh => Box::new(Bvh::new(bounds)),
+            Kind::Bvh4 => Box::new(WideBvh::<4>::new(bounds)),
+            Kind::Bvh8 => Box::new(WideBvh::<8>::new(bounds)),
+            Kind::KdTree => Box::new(KdTree::new(bounds)),
+            Kind::Grid => Box::new(Grid::new(bounds)),
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Kind, String> {
+        match s {
+            "bvh" => Ok(Kind::Bvh),
+            "bvh4" => Ok(Kind::Bvh4),
+            "bvh8" => Ok(Kind::Bvh8),
+            "kdtree" => Ok(Kind::KdTree),
+            "grid" => Ok(Kind::Grid),
+            _ => Err(format!(
+                "unknown acceleration structure '{}': expected bvh, bvh4, bvh8, kdtree or grid",
+                s
+            )),
+        }
+    }
+}
+
+/// Work done by one query.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Traversal {
+    /// Nodes, or grid cells, visited.
+    pub nodes: u64,
+    /// Calls to the primitive callback.
+    pub primitives: u64,
+}
+
+/// Totals of many queries, kept per thread so that counting costs no
+/// contention.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct Counter {
+    queries: AtomicU64,
+    nodes: AtomicU64,
+    primitives: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct Counters(Vec<Counter>);
+
+impl Default for Counters {
+    fn default() -> Counters {
+        Counters(
+            (0..rayon::current_num_threads() + 1)
+                .map(|_| Counter::default())
+                .collect(),
+        )
+    }
+}
+
+impl Counters {
+    pub fn record(&self, t: &Traversal) {
+        self.record_queries(t, 1);
+    }
+
+    /// Records the work of `queries` queries done together, as for a packet.
+    pub fn record_queries(&self, t: &Traversal, queries: u64) {
+        // the last slot is for threads outside the pool
+        let slot = rayon::current_thread_index().map_or(self.0.len() - 1, |i| i % self.0.len());
+        let c = &self.0[slot];
+        c.queries.fetch_add(queries, Ordering::Relaxed);
+        c.nodes.fetch_add(t.nodes, Ordering::Relaxed);
+        c.primitives.fetch_add(t.primitives, Ordering::Relaxed);
+    }
+
+    /// Copies the totals into `stats`.
+    pub fn fill(&self, stats: &mut Stats) {
+        let sum = |f: fn(&Counter) -> &AtomicU64| {
+            self.0.iter().map(|c| f(c).load(Ordering::Relaxed)).sum()
+        };
+        stats.queries = sum(|c| &c.queries);
+        stats.nodes_visited = sum(|c| &c.nodes);
+        stats.primitives_tested = sum(|c| &c.primitives);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub name: &'static str,
+    pub primitives: usize,
+    /// Nodes, or grid cells.
+    pub nodes: usize,
+    /// Leaves, or grid cells with anything in them.
+    pub leaves: usize,
+    /// Primitive entries in the leaves; above `primitives` when primitives
+    /// are split between them.
+    pub references: usize,
+    pub depth: usize,
+    pub bytes: usize,
+    pub build_time: Duration,
+    /// Times the structure has been refit since it was built; `build_time`
+    /// is then how long the last refit took.
+    pub refits: usize,
+    pub queries: u64,
+    pub nodes_visited: u64,
+    pub primitives_tested: u64,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let time = self.build_time.as_secs_f64() * 1e3;
+        if self.refits > 0 {
+            writeln!(
+                f,
+                "{}: {} primitives, refit in {:.1} ms, {} times since built",
+                self.name, self.primitives, time, self.refits
+            )?;
+        } else {
+            writeln!(
+                f,
+                "{}: {} primitives, built in {:.1} ms",
+                self.name, self.primitives, time
+            )?;
+        }
+        writeln!(
+            f,
+            "  {} nodes, {} leaves, {} references, depth {}, {} KiB",
+            self.nodes,
+            self.leaves,
+            self.references,
+            self.depth,
+            self.bytes / 1024
+        )?;
+        let per_query = |n: u64| n as Float / self.queries.max(1) as Float;
+        write!(
+            f,
+            "  {} queries, {:.1} nodes and {:.1} primitives per query",
+            self.queries,
+            per_query(self.nodes_visited),
+            per_query(self.primitives_tested)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    /// Deterministic spheres scattered through a cube, in clumps and alone,
+    /// with a few big ones spanning many cells.
+    fn scatter(n: usize) -> Vec<Sphere> {
+        let mut random = Random(7);
+        (0..n)
+            .map(|k| {
+                let scale = if k % 3 == 0 { 0.1 } else { 10.0 };
+                let radius = if k % 50 == 7 { 3.0 } else { 0.2 };
+                Sphere {
+                    center: Point(scale * random.point().0),
+                    radius: radius * random.next(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn structures_agree() {
+        for n in [0, 1, 2, 5, 300] {
+            let spheres = scatter(n);
+            let bounds: Vec<Aabb> = spheres.iter().map(|s| s.bounds().unwrap()).collect();
+            for kind in [Kind::Bvh, Kind::Bvh4, Kind::Bvh8, Kind::KdTree, Kind::Grid] {
+                let accelerator = kind.build(&bounds);
+                assert_eq!(n == 0, accelerator.bounds().is_none());
+                let mut traversal = Traversal::default();
+                for (k, target) in scatter(100).into_iter().enumerate() {
+                    let origin = Point(Vec3([-5.0, k as Float * 0.1, 20.0]));
+                    // also from inside the boxes, and along an axis
+                    let rays = [
+                        Ray::from_points(origin, target.center),
+                        Ray::from_points(target.center, origin),
+                        Ray(target.center, Direction(Vec3([0.0, 1.0, 0.0]))),
+                    ];
+                    for r in rays {
+                        for max in [Float::INFINITY, 10.0] {
+                            let expected = spheres
+                                .iter()
+                                .enumerate()
+                                .filter_map(|(i, s)| {
+                                    s.find_intersection(&r).map(|h| (h.distance, i))
+                                })
+                                .filter(|(d, _)| *d < max)
+                                .min_by(|a, b| a.0.total_cmp(&b.0));
+                            let mut nearest = None;
+                            let found = accelerator.closest_hit(
+                                &r,
+                                max,
+                                &mut |i, max| {
+                                    let d = spheres[i].find_intersection(&r)?.distance;
+                                    (d < max).then(|| {
+                                        nearest = Some(i);
+                                        d
+                                    })
+                                },
+                                &mut traversal,
+                            );
+                            assert_eq!(expected, found.zip(nearest), "{:?}", kind);
+
+                            let blocked = accelerator.any_hit(
+                                &r,
+                                max,
+                                &mut |i| spheres[i].occluded(&r, max),
+                                &mut traversal,
+                            );
+                            assert_eq!(expected.is_some(), blocked, "{:?}", kind);
+                        }
+                    }
+                }
+
+                // packets find what their rays find alone, whether or not
+                // they share an origin
+                let origin = Point(Vec3([-5.0, 2.0, 20.0]));
+                let coherent: Vec<Ray> = scatter(64)
+                    .iter()
+                    .map(|s| Ray::from_points(origin, s.center))
+                    .collect();
+                let mut mixed = coherent.clone();
+                for (k, r) in mixed.iter_mut().enumerate().step_by(3) {
+                    *r = Ray(Point(Vec3([k as Float * 0.1, 1.0, 1.0])), r.1);
+                }
+                for rays in [coherent, mixed] {
+                    let packet = Packet::new(rays);
+                    let mut max_distances = vec![Float::INFINITY; packet.len()];
+                    max_distances[5] = 10.0;
+                    let mut nearest = vec![None; packet.len()];
+                    accelerator.closest_hits(
+                        &packet,
+                        &mut max_distances,
+                        &mut |k, i, max| {
+                            let d = spheres[i].find_intersection(&packet.rays()[k])?.distance;
+                            (d < max).then(|| {
+                                nearest[k] = Some(i);
+                                d
+                            })
+                        },
+                        &mut traversal,
+                    );
+                    for (k, r) in packet.rays().iter().enumerate() {
+                        let max = if k == 5 { 10.0 } else { Float::INFINITY };
+                        let mut alone = None;
+                        let found = accelerator.closest_hit(
+                            r,
+                            max,
+                            &mut |i, max| {
+                                let d = spheres[i].find_intersection(r)?.distance;
+                                (d < max).then(|| {
+                                    alone = Some(i);
+                                    d
+                                })
+                            },
+                            &mut traversal,
+                        );
+                        assert_eq!(found.unwrap_or(max), max_distances[k], "{:?}", kind);
+                        assert_eq!(alone, nearest[k], "{:?}", kind);
+                    }
+                }
+
+                let stats = accelerator.stats();
+                assert_eq!(n, stats.primitives);
+                assert!(stats.references >= n);
+                assert!(n == 0 || traversal.nodes > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn refit() {
+        let mut spheres = scatter(300);
+        let bounds = |spheres: &[Sphere]| -> Vec<Aabb> {
+            spheres.iter().map(|s| s.bounds().unwrap()).collect()
+        };
+        for kind in [Kind::Bvh, Kind::Bvh4, Kind::Bvh8, Kind::KdTree, Kind::Grid] {
+            let mut accelerator = kind.build(&bounds(&spheres));
+            let refits = !matches!(kind, Kind::KdTree | Kind::Grid);
+
+            // a small step each: the boxes are refit and still found
+            for (k, s) in spheres.iter_mut().enumerate() {
+                s.center.0 .0[k % 3] += 0.05;
+            }
+            assert_eq!(refits, accelerator.refit(&bounds(&spheres)), "{:?}", kind);
+            if !refits {
+                accelerator = kind.build(&bounds(&spheres));
+            }
+            assert_eq!(accelerator.bounds(), Bvh::new(&bounds(&spheres)).bounds());
+            for target in scatter(50) {
+                let r = Ray::from_points(Point(Vec3([-5.0, 1.0, 20.0])), target.center);
+                let expected = spheres
+                    .iter()
+                    .filter_map(|s| s.find_intersection(&r))
+                    .map(|h| h.distance)
+                    .min_by(|a, b| a.total_cmp(b));
+                let found = accelerator.closest_hit(
+                    &r,
+                    Float::INFINITY,
+                    &mut |i, max| {
+                        let d = spheres[i].find_intersection(&r)?.distance;
+                        (d < max).then_some(d)
+                    },
+                    &mut Traversal::default(),
+                );
+                assert_eq!(expected, found, "{:?}", kind);
+            }
+
+            // everything swapped places: the tree is no good any more
+            let mut shuffled = spheres.clone();
+            shuffled.rotate_left(1);
+            assert!(!accelerator.refit(&bounds(&shuffled)), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Kind::Bvh8), "bvh8".parse());
+        assert_eq!(Ok(Kind::KdTree), "kdtree".parse());
+        assert_eq!(Ok(Kind::Grid), "grid".parse());
+        assert!("octree".parse::<Kind>().is_err());
+    }
+}
@@ -0,0 +1,440 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Each patch is split into GRID x GRID cells whose control-point hulls seed
+/// the Newton iteration.
+const GRID: usize = 4;
+const MAX_NEWTON_ITERATIONS: usize = 16;
+const NEWTON_TOLERANCE: Float = per_precision(1e-9, 1e-5);
+const PARAMETER_SLACK: Float = per_precision(1e-6, 1e-4);
+
+#[derive(Clone, Copy, Debug)]
+struct Cell {
+    u: (Float, Float),
+    v: (Float, Float),
+    bounds: Aabb,
+}
+
+/// Bicubic Bézier patch. `control_points[i][j]` has `i` along `u` and `j` along `v`.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    control_points: [[Point; 4]; 4],
+    cells: Vec<Cell>,
+}
+
+pub(crate) fn bernstein(t: Float) -> [Float; 4] {
+    let s = 1.0 - t;
+    [s * s * s, 3.0 * s * s * t, 3.0 * s * t * t, t * t * t]
+}
+
+pub(crate) fn bernstein_derivative(t: Float) -> [Float; 4] {
+    let s = 1.0 - t;
+    [
+        -3.0 * s * s,
+        3.0 * s * s - 6.0 * s * t,
+        6.0 * s * t - 3.0 * t * t,
+        3.0 * t * t,
+    ]
+}
+
+fn lerp(a: Vec3, b: Vec3, t: Float) -> Vec3 {
+    a + t * (b - a)
+}
+
+/// de Casteljau split of a cubic at `t`.
+pub(crate) fn split(p: [Vec3; 4], t: Float) -> ([Vec3; 4], [Vec3; 4]) {
+    let p01 = lerp(p[0], p[1], t);
+    let p12 = lerp(p[1], p[2], t);
+    let p23 = lerp(p[2], p[3], t);
+    let p012 = lerp(p01, p12, t);
+    let p123 = lerp(p12, p23, t);
+    let p0123 = lerp(p012, p123, t);
+    ([p[0], p01, p012, p0123], [p0123, p123, p23, p[3]])
+}
+
+/// Control points of the cubic restricted to the parameter range `[a, b]`.
+fn restrict(p: [Vec3; 4], a: Float, b: Float) -> [Vec3; 4] {
+    let (left, _) = split(p, b);
+    if b == 0.0 {
+        return left;
+    }
+    split(left, a / b).1
+}
+
+impl Patch {
+    pub fn new(control_points: [[Point; 4]; 4]) -> Patch {
+        let mut cells = Vec::with_capacity(GRID * GRID);
+        for cu in 0..GRID {
+            let u = (
+                cu as Float / GRID as Float,
+                (cu + 1) as Float / GRID as Float,
+            );
+            for cv in 0..GRID {
+                let v = (
+                    cv as Float / GRID as Float,
+                    (cv + 1) as Float / GRID as Float,
+                );
+
+                let mut rows = [[Vec3([0.0; 3]); 4]; 4];
+                for (i, row) in rows.iter_mut().enumerate() {
+                    *row = restrict(control_points[i].map(|p| p.0), v.0, v.1);
+                }
+                let mut bounds = Aabb::empty();
+                for j in 0..4 {
+                    let column = restrict(rows.map(|row| row[j]), u.0, u.1);
+                    for p in column {
+                        bounds.grow(&Point(p));
+                    }
+                }
+
+                cells.push(Cell {
+                    u,
+                    v,
+                    bounds: bounds.padded(1e-6),
+                });
+            }
+        }
+
+        Patch {
+            control_points,
+            cells,
+        }
+    }
+
+    /// Returns the surface point and the partial derivatives along `u` and `v`.
+    pub fn evaluate(&self, u: Float, v: Float) -> (Point, Direction, Direction) {
+        let bu = bernstein(u);
+        let bv = bernstein(v);
+        let dbu = bernstein_derivative(u);
+        let dbv = bernstein_derivative(v);
+
+        let mut p = Vec3([0.0; 3]);
+        let mut du = Vec3([0.0; 3]);
+        let mut dv = Vec3([0.0; 3]);
+        for i in 0..4 {
+            for j in 0..4 {
+                let cp = self.control_points[i][j].0;
+                p += (bu[i] * bv[j]) * cp;
+                du += (dbu[i] * bv[j]) * cp;
+                dv += (bu[i] * dbv[j]) * cp;
+            }
+        }
+        (Point(p), Direction(du), Direction(dv))
+    }
+
+    pub fn normal(&self, u: Float, v: Float) -> Direction {
+        let (_, du, dv) = self.evaluate(u, v);
+        let n = du.cross(&dv);
+        if n.0.magnitude() > 1e-12 {
+            return n.normalized();
+        }
+
+        // degenerate corner (e.g. the teapot lid's pole): nudge toward the middle
+        let (_, du, dv) = self.evaluate(u + (0.5 - u) * 1e-4, v + (0.5 - v) * 1e-4);
+        du.cross(&dv).normalized()
+    }
+
+    /// Solves for the `(u, v)` where the surface meets both planes that contain the ray.
+    fn newton(
+        &self,
+        planes: &[(Direction, Float); 2],
+        mut u: Float,
+        mut v: Float,
+    ) -> Option<(Float, Float)> {
+        for _ in 0..MAX_NEWTON_ITERATIONS {
+            let (p, du, dv) = self.evaluate(u, v);
+            let p = Direction(p.0);
+            let f0 = planes[0].0.dot(&p) + planes[0].1;
+            let f1 = planes[1].0.dot(&p) + planes[1].1;
+            if f0.abs() < NEWTON_TOLERANCE && f1.abs() < NEWTON_TOLERANCE {
+                return Some((u, v));
+            }
+
+            let j00 = planes[0].0.dot(&du);
+            let j01 = planes[0].0.dot(&dv);
+            let j10 = planes[1].0.dot(&du);
+            let j11 = planes[1].0.dot(&dv);
+            let det = j00 * j11 - j01 * j10;
+            if det == 0.0 {
+                return None;
+            }
+
+            u -= (j11 * f0 - j01 * f1) / det;
+            v -= (j00 * f1 - j10 * f0) / det;
+            if !u.is_finite() || !v.is_finite() {
+                return None;
+            }
+        }
+        None
+    }
+}
+
+impl Shape for Patch {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let mut candidates: SmallVec<[(Float, &Cell); GRID * GRID]> = self
+            .cells
+            .iter()
+            .filter_map(|c| c.bounds.intersect(r).map(|(t, _)| (t, c)))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort_by(|(t1, _), (t2, _)| t1.partial_cmp(t2).unwrap());
+
+        // represent the ray as the intersection of two planes through it
+        let (n1, n2) = r.1.orthonormal_basis();
+        let o = Direction(r.0 .0);
+        let planes = [(n1, -n1.dot(&o)), (n2, -n2.dot(&o))];
+
+        let mut closest: Option<(Float, Float, Float)> = None;
+        for (t_near, cell) in candidates {
+            if let Some((t, _, _)) = closest {
+                if t_near > t {
+                    break;
+                }
+            }
+
+            let u0 = 0.5 * (cell.u.0 + cell.u.1);
+            let v0 = 0.5 * (cell.v.0 + cell.v.1);
+            let (u, v) = match self.newton(&planes, u0, v0) {
+                Some(uv) => uv,
+                None => continue,
+            };
+            if !(-PARAMETER_SLACK..=1.0 + PARAMETER_SLACK).contains(&u)
+                || !(-PARAMETER_SLACK..=1.0 + PARAMETER_SLACK).contains(&v)
+            {
+                continue;
+            }
+
+            let (p, _, _) = self.evaluate(u, v);
+            let t = (p - r.0).dot(&r.1);
+            if t <= 0.0 {
+                continue;
+            }
+            if closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                closest = Some((t, u.clamp(0.0, 1.0), v.clamp(0.0, 1.0)));
+            }
+        }
+
+        closest.map(|(t, u, v)| {
+            let (point, dpdu, dpdv) = self.evaluate(u, v);
+            let normal = self.normal(u, v);
+
+            // rounding in the weighted sum of control points, plus how far
+            // from the ray Newton may stop; spawned rays must clear the latter
+            // so they cannot converge back onto the point they left
+            let (bu, bv) = (bernstein(u), bernstein(v));
+            let mut magnitude = Vec3([0.0; 3]);
+            for (row, bu) in self.control_points.iter().zip(bu) {
+                for (cp, bv) in row.iter().zip(bv) {
+                    magnitude += (bu * bv) * cp.0.abs();
+                }
+            }
+            let error = gamma(24) * magnitude + Vec3([NEWTON_TOLERANCE; 3]);
+
+            Intersection {
+                distance: t,
+                point,
+                surface_normal: normal,
+                geometric_normal: normal,
+                // patches are open surfaces, so either side can be seen
+                front_face: normal.dot(&r.1) < 0.0,
+                tangent: None,
+                uv: (u, v),
+                dpdu,
+                dpdv,
+                primitive: 0,
+                object: 0,
+                error,
+            }
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let mut bounds = Aabb::empty();
+        for c in &self.cells {
+            bounds.grow(&c.bounds.min);
+            bounds.grow(&c.bounds.max);
+        }
+        Some(bounds)
+    }
+
+    /// Uniform in the patch parameters, which is not uniform in area unless
+    /// the patch is close to a parallelogram.
+    fn sample_surface(&self, s: (Float, Float)) -> Option<(Point, Direction)> {
+        let (p, _, _) = self.evaluate(s.0, s.1);
+        Some((p, self.normal(s.0, s.1)))
+    }
+}
+
+/// Parses the `.bpt` format used by the classic teapot data: a patch count,
+/// then for every patch its `u` and `v` degrees followed by the control points.
+pub fn parse_bpt(reader: impl Read) -> io::Result<Vec<Patch>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut tokens = Vec::new();
+    for line in BufReader::new(reader).lines() {
+        tokens.extend(line?.split_whitespace().map(str::to_owned));
+    }
+    let mut tokens = tokens.into_iter();
+    let mut token = |what: &str| -> io::Result<String> {
+        tokens
+            .next()
+            .ok_or_else(|| invalid(format!("unexpected end of file reading {}", what)))
+    };
+
+    let count = token("patch count")?;
+    let count = count
+        .parse::<usize>()
+        .map_err(|e| invalid(format!("bad patch count '{}': {}", count, e)))?;
+    let mut next = |what: &str| -> io::Result<Float> {
+        let token = token(what)?;
+        token
+            .parse::<Float>()
+            .map_err(|e| invalid(format!("bad {} '{}': {}", what, token, e)))
+    };
+
+    // grown as patches are read, not sized by a count the file may not bear out
+    let mut patches = Vec::new();
+    for patch in 0..count {
+        let degree_u = next("u degree")?;
+        let degree_v = next("v degree")?;
+        if degree_u != 3.0 || degree_v != 3.0 {
+            return Err(invalid(format!(
+                "patch {} has degree {}x{}; only bicubic patches are supported",
+                patch, degree_u, degree_v
+            )));
+        }
+
+        let mut control_points = [[Point::origin(); 4]; 4];
+        for row in control_points.iter_mut() {
+            for p in row.iter_mut() {
+                *p = Point(Vec3([
+                    next("control point")?,
+                    next("control point")?,
+                    next("control point")?,
+                ]));
+            }
+        }
+        patches.push(Patch::new(control_points));
+    }
+
+    Ok(patches)
+}
+
+pub fn load_bpt(path: &Path) -> io::Result<Vec<Patch>> {
+    parse_bpt(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn flat_patch() -> Patch {
+        let mut control_points = [[Point::origin(); 4]; 4];
+        for (i, row) in control_points.iter_mut().enumerate() {
+            for (j, p) in row.iter_mut().enumerate() {
+                *p = Point(Vec3([i as Float, j as Float, 0.0]));
+            }
+        }
+        Patch::new(control_points)
+    }
+
+    fn dome_patch() -> Patch {
+        let mut control_points = [[Point::origin(); 4]; 4];
+        for (i, row) in control_points.iter_mut().enumerate() {
+            for (j, p) in row.iter_mut().enumerate() {
+                let z = if (1..=2).contains(&i) && (1..=2).contains(&j) {
+                    2.0
+                } else {
+                    0.0
+                };
+                *p = Point(Vec3([i as Float, j as Float, z]));
+            }
+        }
+        Patch::new(control_points)
+    }
+
+    #[test]
+    fn flat() {
+        let patch = flat_patch();
+        let i = patch
+            .find_intersection(&Ray(
+                Point(Vec3([1.0, 2.0, 5.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 5.0).abs() < per_precision(1e-9, 1e-4));
+        assert!((i.point - Point(Vec3([1.0, 2.0, 0.0]))).0.magnitude() < per_precision(1e-9, 1e-4));
+        assert!((i.surface_normal.0 .0[2] - 1.0).abs() < per_precision(1e-9, 1e-4));
+        assert!(i.front_face);
+        assert_eq!((1.0 / 3.0, 2.0 / 3.0), (i.uv.0, i.uv.1));
+        assert!((i.dpdu.0 .0[0] - 3.0).abs() < per_precision(1e-9, 1e-4));
+        assert!((i.dpdv.0 .0[1] - 3.0).abs() < per_precision(1e-9, 1e-4));
+
+        // from below it is the back face
+        let i = patch
+            .find_intersection(&Ray(
+                Point(Vec3([1.0, 2.0, -5.0])),
+                Direction(Vec3([0.0, 0.0, 1.0])),
+            ))
+            .unwrap();
+        assert!(!i.front_face);
+        assert!((i.facing_normal().0 .0[2] + 1.0).abs() < per_precision(1e-9, 1e-4));
+
+        assert!(patch
+            .find_intersection(&Ray(
+                Point(Vec3([4.0, 2.0, 5.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn curved() {
+        let patch = dome_patch();
+        let (top, _, _) = patch.evaluate(0.5, 0.5);
+        let i = patch
+            .find_intersection(&Ray(
+                Point(Vec3([1.5, 1.5, 5.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.point - top).0.magnitude() < per_precision(1e-6, 1e-4));
+
+        // a grazing ray along x hits the dome's near side first
+        let r = Ray(
+            Point(Vec3([-5.0, 1.5, 0.5])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = patch.find_intersection(&r).unwrap();
+        assert!(i.point.0 .0[0] < 1.5);
+        assert!((i.point.0 .0[2] - 0.5).abs() < per_precision(1e-6, 1e-4));
+        assert!(i.facing_normal().dot(&r.1) < 0.0);
+    }
+
+    #[test]
+    fn parse() {
+        let mut text = String::from("1\n3 3\n");
+        for i in 0..4 {
+            for j in 0..4 {
+                text += &format!("{} {} 0\n", i, j);
+            }
+        }
+        let patches = parse_bpt(text.as_bytes()).unwrap();
+        assert_eq!(1, patches.len());
+        assert_eq!(
+            Point(Vec3([3.0, 2.0, 0.0])),
+            patches[0].control_points[3][2]
+        );
+
+        assert!(parse_bpt("1\n2 2\n".as_bytes()).is_err());
+        assert!(parse_bpt("1.5\n".as_bytes()).is_err());
+        assert!(parse_bpt("-1\n".as_bytes()).is_err());
+        assert!(parse_bpt("1e30\n".as_bytes()).is_err());
+        assert!(parse_bpt("2\n3 3\n0 0 0".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,376 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Root isolation stops once the interval is shorter than this along the ray.
+const ROOT_TOLERANCE: Float = per_precision(1e-9, 1e-5);
+
+/// One metaball. Its field is `weight * (1 - d²/radius²)²` inside `radius`
+/// and zero outside, so each ball only influences its bounding sphere.
+/// Negative weights carve material away.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Component {
+    pub center: Point,
+    pub radius: Float,
+    pub weight: Float,
+}
+
+/// Blobby implicit surface: the points where the summed field equals `threshold`.
+#[derive(Clone, Debug)]
+pub struct Blob {
+    components: Vec<Component>,
+    threshold: Float,
+}
+
+/// Bernstein coefficients of a quartic given in power form over `[0, 1]`.
+fn to_bernstein(a: [Float; 5]) -> [Float; 5] {
+    const BINOMIAL: [[Float; 5]; 5] = [
+        [1.0, 0.0, 0.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0, 0.0, 0.0],
+        [1.0, 2.0, 1.0, 0.0, 0.0],
+        [1.0, 3.0, 3.0, 1.0, 0.0],
+        [1.0, 4.0, 6.0, 4.0, 1.0],
+    ];
+    let mut b = [0.0; 5];
+    for (k, bk) in b.iter_mut().enumerate() {
+        for i in 0..=k {
+            *bk += BINOMIAL[k][i] / BINOMIAL[4][i] * a[i];
+        }
+    }
+    b
+}
+
+fn split(b: [Float; 5]) -> ([Float; 5], [Float; 5]) {
+    let mut left = [0.0; 5];
+    let mut right = [0.0; 5];
+    let mut work = b;
+    for level in 0..5 {
+        left[level] = work[0];
+        right[4 - level] = work[4 - level];
+        for i in 0..4 - level {
+            work[i] = 0.5 * (work[i] + work[i + 1]);
+        }
+    }
+    (left, right)
+}
+
+/// First root of the Bernstein polynomial `b` over `[s0, s1]`. Pieces whose
+/// coefficients all share a sign cannot contain a root (convex hull property).
+/// The control polygon can straddle zero where the polynomial only comes
+/// close, as it does along grazing rays, so the last piece must also have
+/// its ends, the values there, on either side of it.
+fn first_root(b: [Float; 5], s0: Float, s1: Float, tolerance: Float) -> Option<Float> {
+    if b.iter().all(|c| *c > 0.0) || b.iter().all(|c| *c < 0.0) {
+        return None;
+    }
+    let middle = 0.5 * (s0 + s1);
+    if s1 - s0 < tolerance {
+        return (b[0] * b[4] <= 0.0).then_some(middle);
+    }
+    let (left, right) = split(b);
+    first_root(left, s0, middle, tolerance).or_else(|| first_root(right, middle, s1, tolerance))
+}
+
+impl Blob {
+    /// Blob of `components` surfaced at `threshold`. Every component needs a
+    /// positive radius, which its field is scaled by.
+    pub fn new(components: Vec<Component>, threshold: Float) -> io::Result<Self> {
+        if let Some((n, c)) = components
+            .iter()
+            .enumerate()
+            .find(|(_, c)| c.radius.is_nan() || c.radius <= 0.0)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("component {} has radius {}, not above 0", n, c.radius),
+            ));
+        }
+        Ok(Blob {
+            components,
+            threshold,
+        })
+    }
+
+    /// Outward normal, which points down the field gradient.
+    pub fn normal(&self, p: Point) -> Direction {
+        let mut gradient = Direction::none();
+        for c in &self.components {
+            let d = p - c.center;
+            let r2 = c.radius * c.radius;
+            let q = 1.0 - d.dot(&d) / r2;
+            if q > 0.0 {
+                gradient = gradient + (-4.0 * c.weight * q / r2) * d;
+            }
+        }
+        (-1.0 * gradient).normalized()
+    }
+}
+
+impl Shape for Blob {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        // each component only contributes inside its bounding sphere, so the
+        // field along the ray is a piecewise quartic between the sphere crossings
+        let mut spans: SmallVec<[(Float, Float, &Component); 16]> = self
+            .components
+            .iter()
+            .filter_map(|c| {
+                sphere::interval(c.center, c.radius, r)
+                    .filter(|(_, t1)| *t1 > 0.0)
+                    .map(|(t0, t1)| (t0.max(0.0), t1, c))
+            })
+            .collect();
+        if spans.is_empty() {
+            return None;
+        }
+
+        let mut breakpoints: SmallVec<[Float; 32]> =
+            spans.iter().flat_map(|(t0, t1, _)| [*t0, *t1]).collect();
+        breakpoints.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        spans.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        for w in breakpoints.windows(2) {
+            let (t0, t1) = (w[0], w[1]);
+            let h = t1 - t0;
+            if h <= 0.0 {
+                continue;
+            }
+
+            // field - threshold as a quartic in s, where t = t0 + s * h
+            let mut poly = [-self.threshold, 0.0, 0.0, 0.0, 0.0];
+            let mut active = false;
+            let origin = r.0 + t0 * r.1;
+            for (span_t0, span_t1, c) in &spans {
+                if *span_t0 > t0 {
+                    break;
+                }
+                if *span_t1 < t1 {
+                    continue;
+                }
+                active = true;
+                let oc = origin - c.center;
+                let r2 = c.radius * c.radius;
+                let qa = -h * h / r2;
+                let qb = -2.0 * h * r.1.dot(&oc) / r2;
+                let qc = 1.0 - oc.dot(&oc) / r2;
+                poly[0] += c.weight * qc * qc;
+                poly[1] += c.weight * 2.0 * qb * qc;
+                poly[2] += c.weight * (qb * qb + 2.0 * qa * qc);
+                poly[3] += c.weight * 2.0 * qa * qb;
+                poly[4] += c.weight * qa * qa;
+            }
+            if !active {
+                continue;
+            }
+
+            if let Some(s) = first_root(to_bernstein(poly), 0.0, 1.0, ROOT_TOLERANCE / h) {
+                let distance = t0 + s * h;
+                if distance <= 0.0 {
+                    continue;
+                }
+                let point = r.0 + distance * r.1;
+                let surface_normal = self.normal(point);
+                // implicit surfaces have no parameterization; map the normal
+                // like a unit sphere's, so uv and its derivatives at least agree
+                let (dpdu, dpdv) = sphere::spherical_derivatives(&surface_normal);
+                return Some(Intersection {
+                    distance,
+                    point,
+                    surface_normal,
+                    geometric_normal: surface_normal,
+                    front_face: r.1.dot(&surface_normal) < 0.0,
+                    tangent: None,
+                    uv: sphere::spherical_uv(&surface_normal),
+                    dpdu,
+                    dpdv,
+                    primitive: 0,
+                    object: 0,
+                    // root isolation stops within the tolerance along the ray
+                    error: ROOT_TOLERANCE * r.1 .0.abs() + gamma(16) * point.0.abs(),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Covers every component that adds material; negative ones only carve
+    /// inside those.
+    fn bounds(&self) -> Option<Aabb> {
+        let mut bounds = Aabb::empty();
+        for c in self.components.iter().filter(|c| c.weight > 0.0) {
+            let r = Direction(Vec3([c.radius; 3]));
+            bounds.grow(&(c.center - r));
+            bounds.grow(&(c.center + r));
+        }
+        Some(bounds)
+    }
+}
+
+/// Parses a blob, one entry per line: `threshold <t>` once, and
+/// `<x> <y> <z> <radius> <weight>` for every component. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn parse_blob(reader: impl Read) -> io::Result<Blob> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut threshold = None;
+    let mut components = Vec::new();
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        let (is_threshold, tokens) = match tokens.first() {
+            None => continue,
+            Some(t) if t.starts_with('#') => continue,
+            Some(&"threshold") => (true, &tokens[1..]),
+            Some(_) => (false, &tokens[..]),
+        };
+        let numbers = tokens
+            .iter()
+            .map(|t| {
+                t.parse::<Float>()
+                    .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        match (is_threshold, numbers.as_slice()) {
+            (true, [t]) => threshold = Some(*t),
+            (false, [x, y, z, radius, weight]) => components.push(Component {
+                center: Point(Vec3([*x, *y, *z])),
+                radius: *radius,
+                weight: *weight,
+            }),
+            _ => {
+                return Err(invalid(
+                    line_number,
+                    "expected 'threshold <t>' or '<x> <y> <z> <radius> <weight>'".to_owned(),
+                ))
+            }
+        }
+    }
+
+    let threshold = threshold.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "missing threshold".to_owned())
+    })?;
+    Blob::new(components, threshold)
+}
+
+pub fn load_blob(path: &Path) -> io::Result<Blob> {
+    parse_blob(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn field(blob: &Blob, p: Point) -> Float {
+        blob.components
+            .iter()
+            .map(|c| {
+                let d = p - c.center;
+                let q = 1.0 - d.dot(&d) / (c.radius * c.radius);
+                if q > 0.0 {
+                    c.weight * q * q
+                } else {
+                    0.0
+                }
+            })
+            .sum()
+    }
+
+    #[test]
+    fn single_component_is_a_sphere() {
+        let blob = Blob::new(
+            vec![Component {
+                center: Point::origin(),
+                radius: 2.0,
+                weight: 1.0,
+            }],
+            0.25,
+        )
+        .unwrap();
+        // (1 - d²/4)² = 1/4  =>  d = sqrt(2)
+        let expected_radius = (2.0 as Float).sqrt();
+        let r = Ray(
+            Point(Vec3([-5.0, 0.0, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = blob.find_intersection(&r).unwrap();
+        assert!((i.distance - (5.0 - expected_radius)).abs() < per_precision(1e-6, 1e-4));
+        assert!((i.surface_normal.0 .0[0] + 1.0).abs() < per_precision(1e-6, 1e-4));
+        assert!(i.front_face);
+
+        // from the inside we find the far side
+        let r = Ray(Point::origin(), Direction(Vec3([0.0, 1.0, 0.0])));
+        let i = blob.find_intersection(&r).unwrap();
+        assert!((i.distance - expected_radius).abs() < per_precision(1e-6, 1e-4));
+        assert!((i.surface_normal.0 .0[1] - 1.0).abs() < per_precision(1e-6, 1e-4));
+        assert!(!i.front_face);
+
+        let r = Ray(
+            Point(Vec3([-5.0, 1.5, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        assert!(blob.find_intersection(&r).is_none());
+    }
+
+    #[test]
+    fn components_merge() {
+        let ball = |x| Component {
+            center: Point(Vec3([x, 0.0, 0.0])),
+            radius: 1.0,
+            weight: 1.0,
+        };
+        let alone = Blob::new(vec![ball(-0.6)], 0.5).unwrap();
+        let blob = Blob::new(vec![ball(-0.6), ball(0.6)], 0.5).unwrap();
+        // neither ball alone reaches the threshold at the midpoint, but together they do
+        assert!(field(&alone, Point::origin()) < 0.5);
+        assert!(field(&blob, Point::origin()) > 0.5);
+
+        let r = Ray(
+            Point(Vec3([0.0, -5.0, 0.0])),
+            Direction(Vec3([0.0, 1.0, 0.0])),
+        );
+        let i = blob.find_intersection(&r).unwrap();
+        assert!((field(&blob, i.point) - 0.5).abs() < per_precision(1e-6, 1e-4));
+        assert!((i.surface_normal.0 .0[1] + 1.0).abs() < per_precision(1e-6, 1e-4));
+    }
+
+    #[test]
+    fn parse() {
+        let text = "# two balls\nthreshold 0.3\n-0.6 0 0 1 1\n0.6 0 0 1 1\n";
+        let blob = parse_blob(text.as_bytes()).unwrap();
+        assert_eq!(0.3, blob.threshold);
+        assert_eq!(2, blob.components.len());
+        assert_eq!(Point(Vec3([0.6, 0.0, 0.0])), blob.components[1].center);
+
+        assert!(parse_blob("0 0 0 1 1".as_bytes()).is_err());
+        assert!(parse_blob("threshold 0.3\n0 0 0 1".as_bytes()).is_err());
+        assert!(parse_blob("threshold 0.3\n0 0 0 0 1".as_bytes()).is_err());
+        assert!(parse_blob("threshold 0.3\n0 0 0 NaN 1".as_bytes()).is_err());
+        let flat = Component {
+            center: Point::origin(),
+            radius: 0.0,
+            weight: 1.0,
+        };
+        assert!(Blob::new(vec![flat], 0.3).is_err());
+    }
+
+    #[test]
+    fn near_misses_are_not_roots() {
+        // (s - 0.3)² + 1e-6 comes close to zero without reaching it, while
+        // its control polygon still straddles zero when the pieces get short
+        let b = to_bernstein([0.09 + 1e-6, -0.6, 1.0, 0.0, 0.0]);
+        assert_eq!(None, first_root(b, 0.0, 1.0, 1e-2));
+        // and dipping under, it is found
+        let b = to_bernstein([0.09 - 1e-3, -0.6, 1.0, 0.0, 0.0]);
+        let s = first_root(b, 0.0, 1.0, 1e-2).unwrap();
+        assert!((s - (0.3 - (1e-3 as Float).sqrt())).abs() < 1e-2);
+    }
+}
@@ -0,0 +1,465 @@
+use smallvec::SmallVec;
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::accel::{Accelerator, Stats, Traversal};
+use crate::packet::Packet;
+use crate::*;
+
+/// Buckets the centers are sorted into when looking for the cheapest split.
+const BINS: usize = 16;
+/// Cost of visiting a node, relative to testing one primitive.
+pub(crate) const TRAVERSAL_COST: Float = 0.125;
+/// Most primitives a leaf takes when splitting would not pay off.
+pub(crate) const MAX_LEAF: usize = 4;
+/// How much the surface area cost may grow through refits before the tree
+/// is better built anew.
+pub(crate) const REFIT_LIMIT: Float = 1.5;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum NodeKind {
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    /// The left child follows its parent; the right one is elsewhere.
+    Interior {
+        right: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Node {
+    pub(crate) bounds: Aabb,
+    pub(crate) kind: NodeKind,
+}
+
+/// Bounding volume hierarchy over a list of boxes, split by the surface area
+/// heuristic. It knows nothing about what is in the boxes: queries hand each
+/// candidate's index, in the order the boxes were given, to a callback.
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    pub(crate) nodes: Vec<Node>,
+    /// Primitive indices in leaf order.
+    pub(crate) indices: Vec<usize>,
+    /// Surface area cost when built, which refits are held against.
+    built_cost: Float,
+}
+
+/// Slab test against the precomputed reciprocal of the ray direction.
+/// Returns where the ray enters and leaves the box, if it does so between
+/// the origin and `max`.
+pub(crate) fn slabs(
+    bounds: &Aabb,
+    origin: &[Float; 3],
+    inverse: &[Float; 3],
+    max: Float,
+) -> Option<(Float, Float)> {
+    let mut t_near: Float = 0.0;
+    let mut t_far = max;
+    for axis in 0..3 {
+        let mut t0 = (bounds.min.0 .0[axis] - origin[axis]) * inverse[axis];
+        let mut t1 = (bounds.max.0 .0[axis] - origin[axis]) * inverse[axis];
+        if inverse[axis] < 0.0 {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        // written so that NaN (0 * inf) leaves the interval unchanged
+        t_near = if t0 > t_near { t0 } else { t_near };
+        t_far = if t1 < t_far { t1 } else { t_far };
+        if t_near > t_far {
+            return None;
+        }
+    }
+    Some((t_near, t_far))
+}
+
+/// Total surface area of the boxes, the measure tree costs are taken in.
+pub(crate) fn primitive_area(bounds: &[Aabb]) -> Float {
+    bounds
+        .iter()
+        .map(Aabb::surface_area)
+        .sum::<Float>()
+        .max(Float::MIN_POSITIVE)
+}
+
+fn entry(bounds: &Aabb, origin: &[Float; 3], inverse: &[Float; 3], max: Float) -> Option<Float> {
+    slabs(bounds, origin, inverse, max).map(|(t, _)| t)
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Bvh {
+        let centers: Vec<Point> = bounds.iter().map(Aabb::center).collect();
+        let mut indices: Vec<usize> = (0..bounds.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * bounds.len());
+        if !bounds.is_empty() {
+            build(&mut nodes, bounds, &centers, &mut indices, 0);
+        }
+        let mut bvh = Bvh {
+            nodes,
+            indices,
+            built_cost: 0.0,
+        };
+        bvh.built_cost = bvh.cost(bounds);
+        bvh
+    }
+
+    /// Surface area cost of the tree over `bounds`: what searching every
+    /// node costs, weighed by its area. It is measured against the area of
+    /// the boxes themselves, which moving them leaves alone, rather than
+    /// against the root's, which grows with the nodes when they stray.
+    fn cost(&self, bounds: &[Aabb]) -> Float {
+        let total: Float = self
+            .nodes
+            .iter()
+            .map(|n| {
+                n.bounds.surface_area()
+                    * match n.kind {
+                        NodeKind::Leaf { start, end } => (end - start) as Float,
+                        NodeKind::Interior { .. } => TRAVERSAL_COST,
+                    }
+            })
+            .sum();
+        total / primitive_area(bounds)
+    }
+}
+
+impl Accelerator for Bvh {
+    /// Visits nodes near to far, skipping those behind the nearest hit so far.
+    fn closest_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) -> Option<Float> {
+        let origin = r.0 .0 .0;
+        let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+        let mut closest = None;
+        let mut max = max_distance;
+        let mut stack: SmallVec<[(usize, Float); 64]> = SmallVec::new();
+        if let Some(t) = entry(&self.nodes.first()?.bounds, &origin, &inverse, max) {
+            stack.push((0, t));
+        }
+        while let Some((index, t)) = stack.pop() {
+            if t > max {
+                continue;
+            }
+            traversal.nodes += 1;
+            match self.nodes[index].kind {
+                NodeKind::Leaf { start, end } => {
+                    for i in &self.indices[start..end] {
+                        traversal.primitives += 1;
+                        if let Some(distance) = hit(*i, max) {
+                            max = distance;
+                            closest = Some(distance);
+                        }
+                    }
+                }
+                NodeKind::Interior { right } => {
+                    let left = index + 1;
+                    let near = entry(&self.nodes[left].bounds, &origin, &inverse, max);
+                    let far = entry(&self.nodes[right].bounds, &origin, &inverse, max);
+                    let mut children = [(left, near), (right, far)];
+                    // the nearer child goes on top, to be searched first
+                    if near < far {
+                        children.swap(0, 1);
+                    }
+                    for (child, t) in children {
+                        if let Some(t) = t {
+                            stack.push((child, t));
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    fn any_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize) -> bool,
+        traversal: &mut Traversal,
+    ) -> bool {
+        let origin = r.0 .0 .0;
+        let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+        let mut stack: SmallVec<[usize; 64]> = SmallVec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if entry(&node.bounds, &origin, &inverse, max_distance).is_none() {
+                continue;
+            }
+            traversal.nodes += 1;
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    for i in &self.indices[start..end] {
+                        traversal.primitives += 1;
+                        if hit(*i) {
+                            return true;
+                        }
+                    }
+                }
+                NodeKind::Interior { right } => {
+                    stack.push(right);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        false
+    }
+
+    /// Takes the packet down the tree together. Each node is entered by the
+    /// rays from the first to the last one that reach it, and those outside
+    /// that range, which missed it or found something nearer, are not looked
+    /// at below it.
+    fn closest_hits(
+        &self,
+        packet: &Packet,
+        max_distances: &mut [Float],
+        hit: &mut dyn FnMut(usize, usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) {
+        let mut stack: SmallVec<[(usize, Range<usize>); 64]> = SmallVec::new();
+        if !self.nodes.is_empty() && !packet.is_empty() {
+            stack.push((0, 0..packet.len()));
+        }
+        while let Some((index, range)) = stack.pop() {
+            let node = &self.nodes[index];
+            let Some(active) = packet.active(&node.bounds, range, max_distances) else {
+                continue;
+            };
+            let first = active.start;
+            traversal.nodes += 1;
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    for (k, max) in max_distances
+                        .iter_mut()
+                        .enumerate()
+                        .take(active.end)
+                        .skip(first)
+                    {
+                        if k > first && packet.entry(k, &node.bounds, *max).is_none() {
+                            continue;
+                        }
+                        for i in &self.indices[start..end] {
+                            traversal.primitives += 1;
+                            if let Some(distance) = hit(k, *i, *max) {
+                                *max = distance;
+                            }
+                        }
+                    }
+                }
+                NodeKind::Interior { right } => {
+                    // nearer along the first ray goes on top
+                    let t = |child: usize| {
+                        packet
+                            .entry(first, &self.nodes[child].bounds, max_distances[first])
+                            .unwrap_or(Float::INFINITY)
+                    };
+                    let left = index + 1;
+                    if t(left) <= t(right) {
+                        stack.push((right, active.clone()));
+                        stack.push((left, active));
+                    } else {
+                        stack.push((left, active.clone()));
+                        stack.push((right, active));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Grows or shrinks each node around its children, from the leaves up,
+    /// and gives up once the tree costs `REFIT_LIMIT` times what it did when
+    /// built.
+    fn refit(&mut self, bounds: &[Aabb]) -> bool {
+        if bounds.len() != self.indices.len() {
+            return false;
+        }
+        // children come after their parents
+        for index in (0..self.nodes.len()).rev() {
+            self.nodes[index].bounds = match self.nodes[index].kind {
+                NodeKind::Leaf { start, end } => self.indices[start..end]
+                    .iter()
+                    .fold(Aabb::empty(), |b, i| b.union(&bounds[*i])),
+                NodeKind::Interior { right } => self.nodes[index + 1]
+                    .bounds
+                    .union(&self.nodes[right].bounds),
+            };
+        }
+        self.cost(bounds) <= REFIT_LIMIT * self.built_cost
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            name: "bvh",
+            primitives: self.indices.len(),
+            nodes: self.nodes.len(),
+            references: self.indices.len(),
+            bytes: self.nodes.len() * std::mem::size_of::<Node>()
+                + self.indices.len() * std::mem::size_of::<usize>(),
+            ..Stats::default()
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0, 1));
+        }
+        while let Some((index, depth)) = stack.pop() {
+            stats.depth = stats.depth.max(depth);
+            match self.nodes[index].kind {
+                NodeKind::Leaf { .. } => stats.leaves += 1,
+                NodeKind::Interior { right } => {
+                    stack.push((index + 1, depth + 1));
+                    stack.push((right, depth + 1));
+                }
+            }
+        }
+        stats
+    }
+}
+
+/// Appends the subtree over `order`, whose first entry is at `offset` in the
+/// whole ordering.
+fn build(
+    nodes: &mut Vec<Node>,
+    bounds: &[Aabb],
+    centers: &[Point],
+    order: &mut [usize],
+    offset: usize,
+) {
+    let mut node_bounds = Aabb::empty();
+    let mut center_bounds = Aabb::empty();
+    for i in order.iter() {
+        node_bounds = node_bounds.union(&bounds[*i]);
+        center_bounds.grow(&centers[*i]);
+    }
+    let index = nodes.len();
+    let leaf = Node {
+        bounds: node_bounds,
+        kind: NodeKind::Leaf {
+            start: offset,
+            end: offset + order.len(),
+        },
+    };
+    if order.len() == 1 {
+        nodes.push(leaf);
+        return;
+    }
+
+    let extent = (center_bounds.max - center_bounds.min).0 .0;
+    let axis = (0..3)
+        .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+        .unwrap();
+    let low = center_bounds.min.0 .0[axis];
+    let middle = if extent[axis] > 0.0 {
+        let bin = |i: usize| {
+            let b = ((centers[i].0 .0[axis] - low) / extent[axis] * BINS as Float) as usize;
+            b.min(BINS - 1)
+        };
+        let mut bins = [(Aabb::empty(), 0); BINS];
+        for i in order.iter() {
+            let b = &mut bins[bin(*i)];
+            b.0 = b.0.union(&bounds[*i]);
+            b.1 += 1;
+        }
+        // cost of splitting after each bin: sweep from the right for the
+        // right-hand sides, then from the left
+        let mut right_costs = [0.0; BINS];
+        let (mut area, mut count) = (Aabb::empty(), 0);
+        for k in (1..BINS).rev() {
+            area = area.union(&bins[k].0);
+            count += bins[k].1;
+            right_costs[k - 1] = area.surface_area() * count as Float;
+        }
+        let (mut area, mut count) = (Aabb::empty(), 0);
+        let mut best = (Float::INFINITY, 0);
+        for k in 0..BINS - 1 {
+            area = area.union(&bins[k].0);
+            count += bins[k].1;
+            let cost = area.surface_area() * count as Float + right_costs[k];
+            if cost < best.0 {
+                best = (cost, k);
+            }
+        }
+        let split_cost =
+            TRAVERSAL_COST + best.0 / node_bounds.surface_area().max(Float::MIN_POSITIVE);
+        if order.len() <= MAX_LEAF && split_cost >= order.len() as Float {
+            nodes.push(leaf);
+            return;
+        }
+        partition(order, |i| bin(i) <= best.1)
+    } else if order.len() <= MAX_LEAF {
+        nodes.push(leaf);
+        return;
+    } else {
+        // every center in the same place: nothing to go on, so halve the list
+        order.len() / 2
+    };
+
+    nodes.push(Node {
+        bounds: node_bounds,
+        kind: NodeKind::Interior { right: 0 },
+    });
+    let (left, right) = order.split_at_mut(middle);
+    build(nodes, bounds, centers, left, offset);
+    let right_index = nodes.len();
+    build(nodes, bounds, centers, right, offset + middle);
+    nodes[index].kind = NodeKind::Interior { right: right_index };
+}
+
+/// Moves the entries for which `left` holds to the front, returning how many
+/// there are.
+fn partition(order: &mut [usize], left: impl Fn(usize) -> bool) -> usize {
+    let mut middle = 0;
+    for k in 0..order.len() {
+        if left(order[k]) {
+            order.swap(middle, k);
+            middle += 1;
+        }
+    }
+    middle
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn coincident_boxes() {
+        // nothing for the heuristic to go on, yet every box must still be found
+        let bounds = vec![
+            Aabb {
+                min: Point(Vec3([-1.0; 3])),
+                max: Point(Vec3([1.0; 3])),
+            };
+            20
+        ];
+        let bvh = Bvh::new(&bounds);
+        let r = Ray(
+            Point(Vec3([0.0, 0.0, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+        let mut tested = Vec::new();
+        let hit = bvh.closest_hit(
+            &r,
+            Float::INFINITY,
+            &mut |i, max| {
+                tested.push(i);
+                let d = 4.0 + i as Float;
+                (d < max).then_some(d)
+            },
+            &mut Traversal::default(),
+        );
+        assert_eq!(Some(4.0), hit);
+        tested.sort();
+        assert_eq!((0..20).collect::<Vec<_>>(), tested);
+    }
+}
@@ -0,0 +1,359 @@
+use std::fmt::{self, Debug};
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use memmap2::Mmap;
+
+use crate::mesh::Mesh;
+use crate::*;
+
+/// Start of every cache file.
+const MAGIC: &[u8; 8] = b"jraymesh";
+/// Changed whenever the layout does, so that older files are rebuilt rather
+/// than misread.
+const VERSION: u32 = 2;
+
+/// Hash naming a cached asset: of its source file, and of the settings it
+/// was processed with. This is FNV-1a, which unlike the standard library's
+/// hasher stays the same from one build to the next.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key(u64);
+
+impl Key {
+    pub fn new(source: &[u8]) -> Key {
+        // files hold floats and indices as they are in memory, so a build
+        // that lays them out differently looks for files of its own
+        Key(0xcbf29ce484222325)
+            .with(&[std::mem::size_of::<Float>() as u8])
+            .with(&[cfg!(target_endian = "big") as u8])
+            .with(source)
+    }
+
+    /// Hashes `bytes` in too, such as settings that change the result.
+    pub fn with(self, bytes: &[u8]) -> Key {
+        let mut hash = self.0;
+        for b in bytes {
+            hash ^= *b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Key(hash)
+    }
+}
+
+/// Directory of processed meshes, with their acceleration structures, each
+/// in a file named for its key. A file is written to a temporary name and
+/// moved into place, and never changed after, so that loading can map it
+/// into memory and have the mesh use its vertices, triangles and tree nodes
+/// where they lie, once they are checked, rather than copies of them.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>) -> Cache {
+        Cache { dir: dir.into() }
+    }
+
+    fn path(&self, key: Key) -> PathBuf {
+        self.dir.join(format!("{:016x}.mesh", key.0))
+    }
+
+    /// The mesh stored under `key`. Fails with `NotFound` if there is none,
+    /// and with `InvalidData` if the file is damaged or from another version.
+    pub fn load_mesh(&self, key: Key) -> io::Result<Mesh> {
+        let file = fs::File::open(self.path(key))?;
+        // SAFETY: cache files are not written once in place, so the mapping
+        // does not change while the mesh uses it
+        let map = Arc::new(unsafe { Mmap::map(&file)? });
+        let mut r = Reader {
+            map: &map,
+            position: 0,
+        };
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a mesh cache file"));
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "version {} where {} was expected",
+                version, VERSION
+            )));
+        }
+        if r.take(8)? != key.0.to_ne_bytes() {
+            return Err(invalid("stored under the wrong key"));
+        }
+        let mesh = Mesh::read(&mut r)?;
+        if r.position != map.len() {
+            return Err(invalid("trailing data"));
+        }
+        Ok(mesh)
+    }
+
+    pub fn store_mesh(&self, key: Key, mesh: &Mesh) -> io::Result<()> {
+        let mut w = Writer::default();
+        w.0.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.0.extend_from_slice(&key.0.to_ne_bytes());
+        mesh.write(&mut w);
+
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, &w.0)?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// A type kept in cache files just as it is in memory, which the mesh can
+/// then use in place.
+///
+/// # Safety
+///
+/// Any bytes must make a valid value, and none of its bytes may be padding.
+pub(crate) unsafe trait Plain: Copy + 'static {}
+
+unsafe impl Plain for u32 {}
+unsafe impl Plain for Float {}
+unsafe impl<T: Plain, const N: usize> Plain for [T; N] {}
+// both wrap a `Vec3`, which wraps `[Float; 3]`
+unsafe impl Plain for Point {}
+unsafe impl Plain for Direction {}
+
+/// A list that was either built in memory or read in place from a mapped
+/// cache file, which it then keeps mapped.
+pub(crate) enum Array<T: Plain> {
+    Owned(Vec<T>),
+    Mapped {
+        map: Arc<Mmap>,
+        offset: usize,
+        len: usize,
+        items: PhantomData<T>,
+    },
+}
+
+impl<T: Plain> Array<T> {
+    #[cfg(test)]
+    pub(crate) fn is_mapped(&self) -> bool {
+        matches!(self, Array::Mapped { .. })
+    }
+
+    /// The list to change, copying it out of the file first if need be.
+    pub(crate) fn to_mut(&mut self) -> &mut Vec<T> {
+        if let Array::Mapped { .. } = self {
+            *self = Array::Owned(self.to_vec());
+        }
+        match self {
+            Array::Owned(items) => items,
+            Array::Mapped { .. } => unreachable!(),
+        }
+    }
+}
+
+impl<T: Plain> Deref for Array<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Array::Owned(items) => items,
+            // SAFETY: `Reader::array` checked that the items are in the map
+            // and aligned, and any bytes make a `T`
+            Array::Mapped {
+                map, offset, len, ..
+            } => unsafe { std::slice::from_raw_parts(map.as_ptr().add(*offset).cast(), *len) },
+        }
+    }
+}
+
+impl<T: Plain> From<Vec<T>> for Array<T> {
+    fn from(items: Vec<T>) -> Array<T> {
+        Array::Owned(items)
+    }
+}
+
+impl<T: Plain> Clone for Array<T> {
+    fn clone(&self) -> Array<T> {
+        match self {
+            Array::Owned(items) => Array::Owned(items.clone()),
+            Array::Mapped {
+                map, offset, len, ..
+            } => Array::Mapped {
+                map: map.clone(),
+                offset: *offset,
+                len: *len,
+                items: PhantomData,
+            },
+        }
+    }
+}
+
+impl<T: Plain + Debug> Debug for Array<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.deref().fmt(f)
+    }
+}
+
+/// Encodes the parts of a cache file, in the byte order of the machine.
+#[derive(Debug, Default)]
+pub(crate) struct Writer(Vec<u8>);
+
+impl Writer {
+    pub(crate) fn u32(&mut self, v: u32) {
+        self.0.extend_from_slice(&v.to_ne_bytes());
+    }
+
+    pub(crate) fn floats(&mut self, v: &[Float]) {
+        for f in v {
+            self.0.extend_from_slice(&f.to_ne_bytes());
+        }
+    }
+
+    /// A list as it is in memory, after its length and enough padding to
+    /// align it.
+    pub(crate) fn array<T: Plain>(&mut self, items: &[T]) {
+        self.0
+            .extend_from_slice(&(items.len() as u64).to_ne_bytes());
+        let padding = self.0.len().next_multiple_of(std::mem::align_of::<T>()) - self.0.len();
+        self.0.resize(self.0.len() + padding, 0);
+        // SAFETY: a `Plain` type has no padding, so all of its bytes are set
+        let bytes = unsafe {
+            std::slice::from_raw_parts(items.as_ptr().cast::<u8>(), std::mem::size_of_val(items))
+        };
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+/// Decodes what `Writer` encoded from a mapped file, failing rather than
+/// panicking on anything out of place.
+#[derive(Debug)]
+pub(crate) struct Reader<'a> {
+    map: &'a Arc<Mmap>,
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.map.len() - self.position {
+            return Err(invalid("file ends early"));
+        }
+        let taken = &self.map[self.position..self.position + n];
+        self.position += n;
+        Ok(taken)
+    }
+
+    pub(crate) fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn float(&mut self) -> io::Result<Float> {
+        let bytes = self.take(std::mem::size_of::<Float>())?;
+        Ok(Float::from_ne_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// A list that `Writer::array` wrote, left where it is in the file.
+    /// Only its size and place are checked; its contents are the caller's
+    /// to check.
+    pub(crate) fn array<T: Plain>(&mut self) -> io::Result<Array<T>> {
+        let len = u64::from_ne_bytes(self.take(8)?.try_into().unwrap());
+        let padding = self.position.next_multiple_of(std::mem::align_of::<T>()) - self.position;
+        self.take(padding)?;
+        let offset = self.position;
+        let size = usize::try_from(len)
+            .ok()
+            .and_then(|len| len.checked_mul(std::mem::size_of::<T>()));
+        match size {
+            Some(size) if size <= self.map.len() - offset => self.take(size)?,
+            _ => return Err(invalid("list runs past the end of the file")),
+        };
+        // the map starts on a page, so this only fails if that changes
+        if !self.map[offset..].as_ptr().cast::<T>().is_aligned() {
+            return Err(invalid("misaligned list"));
+        }
+        Ok(Array::Mapped {
+            map: self.map.clone(),
+            offset,
+            len: len as usize,
+            items: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::parse_obj;
+    use crate::subdivision::{subdivide, Target};
+
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                        f 1/1 4/2 3/3 2/4\nf 5/1 6/2 7/3 8/4\nf 1/1 2/2 6/3 5/4\n\
+                        f 2/1 3/2 7/3 6/4\nf 3/1 4/2 8/3 7/4\nf 4/1 1/2 5/3 8/4\n";
+
+    #[test]
+    fn round_trip() {
+        let cube = parse_obj(CUBE.as_bytes()).unwrap();
+        let mesh = subdivide(&cube, Target::Level(3)).triangulate();
+        let dir = std::env::temp_dir().join(format!("jray-cache-{}", std::process::id()));
+        let cache = Cache::new(&dir);
+        let key = Key::new(CUBE.as_bytes()).with(b"level 3");
+        assert_ne!(key, Key::new(CUBE.as_bytes()).with(b"level 2"));
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            cache.load_mesh(key).unwrap_err().kind()
+        );
+
+        cache.store_mesh(key, &mesh).unwrap();
+        let loaded = cache.load_mesh(key).unwrap();
+        assert!(loaded.is_mapped());
+        assert_eq!(mesh.bounds(), loaded.bounds());
+        for k in 0..200 {
+            let angle = k as Float * 0.1;
+            let origin = Point(Vec3([
+                5.0 * angle.cos(),
+                5.0 * angle.sin(),
+                0.02 * k as Float - 2.0,
+            ]));
+            let r = Ray::from_points(origin, Point(Vec3([0.1, -0.2, 0.3])));
+            let hit = |m: &Mesh| {
+                m.find_intersection(&r)
+                    .map(|i| (i.distance, i.primitive, i.surface_normal.0, i.uv))
+            };
+            assert!(hit(&mesh).is_some());
+            assert_eq!(hit(&mesh), hit(&loaded));
+            let s = (k as Float / 200.0, 0.3);
+            assert_eq!(
+                mesh.sample_surface(s).map(|(p, n)| (p.0, n.0)),
+                loaded.sample_surface(s).map(|(p, n)| (p.0, n.0))
+            );
+        }
+
+        // damage anywhere is either caught or harmless, never a panic later
+        let path = cache.path(key);
+        let bytes = fs::read(&path).unwrap();
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            cache.load_mesh(key).unwrap_err().kind()
+        );
+        for at in (0..bytes.len()).step_by(bytes.len() / 300 + 1) {
+            let mut damaged = bytes.clone();
+            damaged[at] ^= 0xa5;
+            fs::write(&path, &damaged).unwrap();
+            if let Ok(m) = cache.load_mesh(key) {
+                let r = Ray::from_points(Point(Vec3([5.0, 1.0, 2.0])), Point::origin());
+                m.find_intersection(&r);
+                m.sample_surface((0.5, 0.5));
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 use std::ops::{Add, AddAssign, Mul, MulAssign};
 
+use crate::Float;
+
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub struct Color(pub f64, pub f64, pub f64);
+pub struct Color(pub Float, pub Float, pub Float);
 
 pub const RED: Color = Color(1.0, 0.0, 0.0);
 pub const GREEN: Color = Color(0.0, 1.0, 0.0);
@@ -10,7 +12,7 @@ pub const WHITE: Color = Color(1.0, 1.0, 1.0);
 pub const BLACK: Color = Color(0.0, 0.0, 0.0);
 
 impl Color {
-    pub fn to_rgb(&self) -> [u8; 3] {
+    pub fn to_rgb(self) -> [u8; 3] {
         [
             (256.0 * self.0).clamp(0.0, 255.0) as u8,
             (256.0 * self.1).clamp(0.0, 255.0) as u8,
@@ -37,7 +39,7 @@ impl Add<Color> for Color {
     }
 }
 
-impl Mul<Color> for f64 {
+impl Mul<Color> for Float {
     type Output = Color;
 
     fn mul(self, mut rhs: Color) -> Self::Output {
@@ -46,18 +48,18 @@ impl Mul<Color> for f64 {
     }
 }
 
-impl MulAssign<f64> for Color {
-    fn mul_assign(&mut self, rhs: f64) {
+impl MulAssign<Float> for Color {
+    fn mul_assign(&mut self, rhs: Float) {
         self.0 *= rhs;
         self.1 *= rhs;
         self.2 *= rhs;
     }
 }
 
-impl Mul<f64> for Color {
+impl Mul<Float> for Color {
     type Output = Color;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         rhs * self
     }
 }
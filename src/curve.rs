@@ -0,0 +1,375 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::bezier::{bernstein, bernstein_derivative, split};
+use crate::*;
+
+const MAX_DEPTH: i32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CurveKind {
+    /// Flat strip that always faces the incoming ray.
+    Ribbon,
+    /// Ribbon shaded as if it had a round cross-section.
+    Tube,
+}
+
+/// Cubic Bézier segment whose width varies linearly from start to end.
+#[derive(Clone, Debug)]
+pub struct Curve {
+    control_points: [Point; 4],
+    width: (Float, Float),
+    kind: CurveKind,
+    bounds: Aabb,
+}
+
+fn evaluate(cp: &[Vec3; 4], u: Float) -> (Vec3, Vec3) {
+    let b = bernstein(u);
+    let db = bernstein_derivative(u);
+    let mut p = Vec3([0.0; 3]);
+    let mut dp = Vec3([0.0; 3]);
+    for i in 0..4 {
+        p += b[i] * cp[i];
+        dp += db[i] * cp[i];
+    }
+    (p, dp)
+}
+
+fn lerp(t: Float, a: Float, b: Float) -> Float {
+    (1.0 - t) * a + t * b
+}
+
+impl Curve {
+    pub fn new(control_points: [Point; 4], width: (Float, Float), kind: CurveKind) -> Curve {
+        let mut bounds = Aabb::empty();
+        for p in &control_points {
+            bounds.grow(p);
+        }
+        Curve {
+            control_points,
+            width,
+            kind,
+            bounds: bounds.padded(0.5 * width.0.max(width.1)),
+        }
+    }
+
+    /// Converts one segment of a uniform cubic B-spline to its Bézier form.
+    pub fn from_b_spline(p: [Point; 4], width: (Float, Float), kind: CurveKind) -> Curve {
+        let [p0, p1, p2, p3] = p.map(|p| p.0);
+        let control_points = [
+            (1.0 / 6.0) * (p0 + 4.0 * p1 + p2),
+            (1.0 / 3.0) * (2.0 * p1 + p2),
+            (1.0 / 3.0) * (p1 + 2.0 * p2),
+            (1.0 / 6.0) * (p1 + 4.0 * p2 + p3),
+        ];
+        Curve::new(control_points.map(Point), width, kind)
+    }
+
+    fn width_at(&self, u: Float) -> Float {
+        lerp(u, self.width.0, self.width.1)
+    }
+
+    /// Subdivides the curve (already transformed so the ray runs down +z from
+    /// the origin) until the pieces are nearly straight, then tests those.
+    /// Returns the ray distance and curve parameter of the closest hit.
+    fn recursive_intersect(
+        &self,
+        cp: [Vec3; 4],
+        u: (Float, Float),
+        depth: i32,
+        t_max: Float,
+    ) -> Option<(Float, Float)> {
+        let half_width = 0.5 * self.width_at(u.0).max(self.width_at(u.1));
+        let mut bounds = Aabb::empty();
+        for p in &cp {
+            bounds.grow(&Point(*p));
+        }
+        let bounds = bounds.padded(half_width);
+        let (min, max) = (bounds.min.0 .0, bounds.max.0 .0);
+        if min[0] > 0.0 || max[0] < 0.0 || min[1] > 0.0 || max[1] < 0.0 {
+            return None;
+        }
+        if max[2] < 0.0 || min[2] > t_max {
+            return None;
+        }
+
+        if depth > 0 {
+            let (left, right) = split(cp, 0.5);
+            let middle = 0.5 * (u.0 + u.1);
+            let left_hit = self.recursive_intersect(left, (u.0, middle), depth - 1, t_max);
+            let t_max = left_hit.map_or(t_max, |(t, _)| t);
+            let right_hit = self.recursive_intersect(right, (middle, u.1), depth - 1, t_max);
+            return right_hit.or(left_hit);
+        }
+
+        // treat the piece as a line and find where it passes the ray in xy
+        let start = cp[0].0;
+        let end = cp[3].0;
+        let dx = end[0] - start[0];
+        let dy = end[1] - start[1];
+        let length_squared = dx * dx + dy * dy;
+        if length_squared == 0.0 {
+            return None;
+        }
+        let w = -(start[0] * dx + start[1] * dy) / length_squared;
+        if !(0.0..=1.0).contains(&w) {
+            return None;
+        }
+
+        let (p, _) = evaluate(&cp, w);
+        let hit_u = lerp(w, u.0, u.1);
+        let hit_width = self.width_at(hit_u);
+        if p.0[0] * p.0[0] + p.0[1] * p.0[1] > 0.25 * hit_width * hit_width {
+            return None;
+        }
+        let t = p.0[2];
+        if t <= 0.0 || t > t_max {
+            return None;
+        }
+        Some((t, hit_u))
+    }
+}
+
+impl Shape for Curve {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let (_, t_max) = self.bounds.intersect(r)?;
+
+        let (dx, dy) = r.1.orthonormal_basis();
+        let cp = self.control_points.map(|p| {
+            let v = p - r.0;
+            Vec3([v.dot(&dx), v.dot(&dy), v.dot(&r.1)])
+        });
+
+        // pick a depth at which the pieces deviate from lines by a fraction of the width
+        let mut l0: Float = 0.0;
+        for i in 0..2 {
+            let second_difference = cp[i] - 2.0 * cp[i + 1] + cp[i + 2];
+            for axis in 0..3 {
+                l0 = l0.max(second_difference.0[axis].abs());
+            }
+        }
+        let epsilon = 0.05 * self.width.0.max(self.width.1);
+        let depth = if l0 > 0.0 && epsilon > 0.0 {
+            ((consts::SQRT_2 * 6.0 * l0 / (8.0 * epsilon)).log2() / 2.0).ceil() as i32
+        } else {
+            0
+        };
+        let depth = depth.clamp(0, MAX_DEPTH);
+
+        let (t, u) = self.recursive_intersect(cp, (0.0, 1.0), depth, t_max)?;
+
+        let (center, dpdu) = evaluate(&self.control_points.map(|p| p.0), u);
+        let dpdu = Direction(dpdu);
+        let tangent = dpdu.normalized();
+        // face the ribbon toward the ray, perpendicular to the tangent
+        let facing = -1.0 * (r.1 - r.1.dot(&tangent) * tangent);
+        let facing = if facing.0.magnitude() > 0.0 {
+            facing.normalized()
+        } else {
+            tangent.orthonormal_basis().0
+        };
+
+        // where across the width the ray landed, from -1 to 1
+        let radius = 0.5 * self.width_at(u);
+        let side = tangent.cross(&facing);
+        let v = if radius > 0.0 {
+            ((r.0 + t * r.1) - Point(center))
+                .dot(&side)
+                .clamp(-radius, radius)
+                / radius
+        } else {
+            0.0
+        };
+
+        let (point, surface_normal) = match self.kind {
+            CurveKind::Ribbon => (r.0 + t * r.1, facing),
+            CurveKind::Tube => {
+                // which also picks the angle around the tube
+                let normal = (1.0 - v * v).sqrt() * facing + v * side;
+                (Point(center) + radius * normal, normal)
+            }
+        };
+
+        Some(Intersection {
+            distance: (point - r.0).dot(&r.1),
+            point,
+            surface_normal,
+            geometric_normal: surface_normal,
+            // the ribbon turns to face every ray, and the tube shows its outside
+            front_face: true,
+            tangent: Some(tangent),
+            uv: (u, 0.5 * (v + 1.0)),
+            dpdu,
+            dpdv: (2.0 * radius) * side,
+            primitive: 0,
+            object: 0,
+            // the hit is only found to within the flattened pieces, so use a
+            // generous bound: twice the width, as pbrt does
+            error: Vec3([2.0 * self.width_at(u); 3]),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+}
+
+/// Parses strands, one per line: `ribbon|tube <root width> <tip width>` followed
+/// by at least four `x y z` B-spline control points. Width varies linearly
+/// along the strand. Blank lines and lines starting with `#` are skipped.
+pub fn parse_strands(reader: impl Read) -> io::Result<Vec<Curve>> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut curves = Vec::new();
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+        let mut tokens = line.split_whitespace();
+        let kind = match tokens.next() {
+            None => continue,
+            Some(t) if t.starts_with('#') => continue,
+            Some("ribbon") => CurveKind::Ribbon,
+            Some("tube") => CurveKind::Tube,
+            Some(t) => return Err(invalid(line_number, format!("unknown curve kind '{}'", t))),
+        };
+
+        let numbers = tokens
+            .map(|t| {
+                t.parse::<Float>()
+                    .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if numbers.len() < 2 + 4 * 3 || (numbers.len() - 2) % 3 != 0 {
+            return Err(invalid(
+                line_number,
+                "expected two widths and at least four control points".to_owned(),
+            ));
+        }
+
+        let (root, tip) = (numbers[0], numbers[1]);
+        let points: Vec<_> = numbers[2..]
+            .chunks(3)
+            .map(|c| Point(Vec3([c[0], c[1], c[2]])))
+            .collect();
+        let segments = points.len() - 3;
+        for (s, p) in points.windows(4).enumerate() {
+            let width = (
+                lerp(s as Float / segments as Float, root, tip),
+                lerp((s + 1) as Float / segments as Float, root, tip),
+            );
+            curves.push(Curve::from_b_spline([p[0], p[1], p[2], p[3]], width, kind));
+        }
+    }
+
+    Ok(curves)
+}
+
+pub fn load_strands(path: &Path) -> io::Result<Vec<Curve>> {
+    parse_strands(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn straight(kind: CurveKind) -> Curve {
+        Curve::new(
+            [0.0, 1.0, 2.0, 3.0].map(|z| Point(Vec3([0.0, 0.0, z]))),
+            (0.2, 0.2),
+            kind,
+        )
+    }
+
+    #[test]
+    fn ribbon() {
+        let curve = straight(CurveKind::Ribbon);
+        let r = Ray(
+            Point(Vec3([-5.0, 0.05, 1.5])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = curve.find_intersection(&r).unwrap();
+        assert!((i.distance - 5.0).abs() < 1e-9);
+        assert!((i.surface_normal.0 .0[0] + 1.0).abs() < 1e-9);
+        assert!((i.tangent.unwrap().0 .0[2] - 1.0).abs() < 1e-9);
+        assert!((i.uv.0 - 0.5).abs() < 1e-9);
+        assert!((i.dpdu.0 .0[2] - 3.0).abs() < 1e-9);
+        // a quarter of the way across, measured along dpdv
+        assert!((i.dpdv.0.magnitude() - 0.2).abs() < 1e-9);
+        assert!((i.uv.1 - 0.25).abs() < 1e-9);
+        assert!((i.dpdv.0 .0[1] + 0.2).abs() < 1e-9);
+
+        // outside the width, and past the end
+        let r = Ray(
+            Point(Vec3([-5.0, 0.15, 1.5])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        assert!(curve.find_intersection(&r).is_none());
+        let r = Ray(
+            Point(Vec3([-5.0, 0.0, 3.5])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        assert!(curve.find_intersection(&r).is_none());
+    }
+
+    #[test]
+    fn tube() {
+        let curve = straight(CurveKind::Tube);
+        let r = Ray(
+            Point(Vec3([-5.0, 0.0, 1.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = curve.find_intersection(&r).unwrap();
+        assert!((i.distance - 4.9).abs() < 1e-9);
+        assert!((i.surface_normal.0 .0[0] + 1.0).abs() < 1e-9);
+
+        // at the edge of the tube the normal turns sideways
+        let r = Ray(
+            Point(Vec3([-5.0, 0.0999, 1.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = curve.find_intersection(&r).unwrap();
+        assert!(i.surface_normal.0 .0[1].abs() > 0.9);
+    }
+
+    #[test]
+    fn bent() {
+        let curve = Curve::new(
+            [
+                Point(Vec3([0.0, 0.0, 0.0])),
+                Point(Vec3([0.0, 0.0, 1.0])),
+                Point(Vec3([1.0, 0.0, 2.0])),
+                Point(Vec3([2.0, 0.0, 2.0])),
+            ],
+            (0.1, 0.01),
+            CurveKind::Ribbon,
+        );
+        let (p, _) = evaluate(&curve.control_points.map(|p| p.0), 0.5);
+        let r = Ray(
+            Point(Vec3([p.0[0], -5.0, p.0[2]])),
+            Direction(Vec3([0.0, 1.0, 0.0])),
+        );
+        let i = curve.find_intersection(&r).unwrap();
+        assert!((i.distance - 5.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parse() {
+        let text = "# grass\n\nribbon 0.1 0.0 0 0 0 0 0 1 0 0 2 0.5 0 3 1 0 3\ntube 0.1 0.1 0 0 0 0 0 1 0 0 2 0 0 3\n";
+        let curves = parse_strands(text.as_bytes()).unwrap();
+        assert_eq!(3, curves.len());
+        assert_eq!(CurveKind::Ribbon, curves[0].kind);
+        assert_eq!((0.1, 0.05), curves[0].width);
+        assert_eq!(CurveKind::Tube, curves[2].kind);
+        // B-spline segments join up
+        assert_eq!(curves[0].control_points[3], curves[1].control_points[0]);
+
+        assert!(parse_strands("fur 0.1 0.1 0 0 0".as_bytes()).is_err());
+        assert!(parse_strands("tube 0.1 0.1 0 0 0 0 0 1".as_bytes()).is_err());
+    }
+}
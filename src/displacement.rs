@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use crate::mesh::{self, Mesh, PolygonMesh};
+use crate::texture::Texture;
+use crate::*;
+
+/// Never split triangles more than this many times, whatever the edge length asks for.
+pub const MAX_LEVEL: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// Move each vertex along its normal by the texture's brightness times `scale`.
+    Scalar { scale: Float },
+    /// Move each vertex by the texture's color read as an object-space
+    /// offset, with 0.5 meaning no movement on that axis.
+    Vector { scale: Float },
+}
+
+#[derive(Clone, Debug)]
+pub struct Displacement {
+    pub texture: Texture,
+    pub mode: Mode,
+    /// The mesh is tessellated until no edge is longer than this before
+    /// displacing, which sets how fine the added detail can be.
+    pub edge_length: Float,
+}
+
+/// Splits every triangle into four at its edge midpoints. Shared edges get a
+/// single midpoint, so displacing the result does not open cracks.
+fn split(mesh: &PolygonMesh) -> PolygonMesh {
+    let mut vertices = mesh.vertices.clone();
+    let mut uvs = mesh.uvs.clone();
+    let mut midpoints = HashMap::new();
+    let mut midpoint = |a: usize, b: usize| {
+        *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+            vertices.push(Point(0.5 * (vertices[a].0 + vertices[b].0)));
+            if !uvs.is_empty() {
+                let (ua, ub): ((Float, Float), (Float, Float)) = (uvs[a], uvs[b]);
+                uvs.push((0.5 * (ua.0 + ub.0), 0.5 * (ua.1 + ub.1)));
+            }
+            vertices.len() - 1
+        })
+    };
+
+    let mut faces = Vec::with_capacity(4 * mesh.faces.len());
+    for [a, b, c] in mesh.triangles() {
+        let ab = midpoint(a, b);
+        let bc = midpoint(b, c);
+        let ca = midpoint(c, a);
+        faces.push(vec![a, ab, ca]);
+        faces.push(vec![ab, b, bc]);
+        faces.push(vec![ca, bc, c]);
+        faces.push(vec![ab, bc, ca]);
+    }
+
+    PolygonMesh {
+        vertices,
+        faces,
+        creases: Vec::new(),
+        uvs,
+    }
+}
+
+/// Triangulates `mesh` and splits it until its edges are no longer than `edge_length`.
+pub fn tessellate(mesh: &PolygonMesh, edge_length: Float) -> PolygonMesh {
+    let mut mesh = PolygonMesh {
+        faces: mesh.triangles().into_iter().map(Vec::from).collect(),
+        creases: Vec::new(),
+        ..mesh.clone()
+    };
+    for _ in 0..MAX_LEVEL {
+        if mesh.max_edge_length() <= edge_length {
+            break;
+        }
+        mesh = split(&mesh);
+    }
+    mesh
+}
+
+/// Tessellates and displaces `mesh`. Meshes without texture coordinates look
+/// image textures up by their x and y coordinates instead. The result gets
+/// fresh normals and bounds from the displaced vertices.
+pub fn displace(mesh: &PolygonMesh, displacement: &Displacement) -> Mesh {
+    let mesh = tessellate(mesh, displacement.edge_length);
+    let triangles = mesh.triangles();
+    let normals = mesh::vertex_normals(&mesh.vertices, &triangles);
+
+    let vertices = mesh
+        .vertices
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let uv = match mesh.uvs.get(i) {
+                Some(uv) => *uv,
+                None => (p.0 .0[0], p.0 .0[1]),
+            };
+            match displacement.mode {
+                Mode::Scalar { scale } => {
+                    let height = displacement.texture.sample_scalar(uv, *p);
+                    *p + (scale * height) * normals[i]
+                }
+                Mode::Vector { scale } => {
+                    let c = displacement.texture.sample(uv, *p);
+                    let offset = Direction(Vec3([c.0 - 0.5, c.1 - 0.5, c.2 - 0.5]));
+                    *p + (2.0 * scale) * offset
+                }
+            }
+        })
+        .collect();
+
+    Mesh::new(vertices, mesh.uvs, triangles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::parse_obj;
+
+    const QUAD: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                        vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n";
+
+    #[test]
+    fn tessellation() {
+        let quad = parse_obj(QUAD.as_bytes()).unwrap();
+        let fine = tessellate(&quad, 0.3);
+        assert!(fine.max_edge_length() <= 0.3);
+        // two triangles, split three times
+        assert_eq!(2 * 4 * 4 * 4, fine.faces.len());
+        assert_eq!(9 * 9, fine.vertices.len());
+        assert_eq!(fine.vertices.len(), fine.uvs.len());
+        for (p, uv) in fine.vertices.iter().zip(&fine.uvs) {
+            assert_eq!((p.0 .0[0], p.0 .0[1]), *uv);
+        }
+    }
+
+    #[test]
+    fn scalar() {
+        let mut img = image::Rgb32FImage::new(2, 1);
+        img.put_pixel(1, 0, image::Rgb([1.0, 1.0, 1.0]));
+        let quad = parse_obj(QUAD.as_bytes()).unwrap();
+        let mesh = displace(
+            &quad,
+            &Displacement {
+                texture: Texture::Image(img),
+                mode: Mode::Scalar { scale: 0.5 },
+                edge_length: 0.2,
+            },
+        );
+
+        // the right half is raised, so the bounds grow to cover it
+        let down = Direction(Vec3([0.0, 0.0, -1.0]));
+        let left = mesh
+            .find_intersection(&Ray(Point(Vec3([0.25, 0.45, 2.0])), down))
+            .unwrap();
+        let right = mesh
+            .find_intersection(&Ray(Point(Vec3([0.75, 0.45, 2.0])), down))
+            .unwrap();
+        assert!(left.point.0 .0[2].abs() < 1e-9);
+        assert!((right.point.0 .0[2] - 0.5).abs() < 1e-9);
+
+        // the slope between them tilts the normals
+        let middle = mesh
+            .find_intersection(&Ray(Point(Vec3([0.5, 0.45, 2.0])), down))
+            .unwrap();
+        assert!(middle.surface_normal.0 .0[0] < -0.1);
+    }
+
+    #[test]
+    fn vector() {
+        let quad = parse_obj(QUAD.as_bytes()).unwrap();
+        let mut img = image::Rgb32FImage::new(1, 1);
+        img.put_pixel(0, 0, image::Rgb([0.5, 0.5, 1.0]));
+        let mesh = displace(
+            &quad,
+            &Displacement {
+                texture: Texture::Image(img),
+                mode: Mode::Vector { scale: 0.25 },
+                edge_length: 1.0,
+            },
+        );
+        let i = mesh
+            .find_intersection(&Ray(
+                Point(Vec3([0.5, 0.5, 2.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.point.0 .0[2] - 0.25).abs() < 1e-9);
+    }
+}
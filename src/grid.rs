@@ -0,0 +1,226 @@
+use crate::aabb::Aabb;
+use crate::accel::{Accelerator, Stats, Traversal};
+use crate::bvh::slabs;
+use crate::*;
+
+/// Cells per primitive the grid aims for.
+const DENSITY: Float = 2.0;
+/// Most cells along any one axis.
+const MAX_RESOLUTION: usize = 256;
+
+/// Uniform grid over a list of boxes, each listed in every cell it overlaps,
+/// walked cell by cell with a 3D-DDA. Cheap to build, and good when the
+/// primitives are spread evenly; one dense clump in a big empty scene puts
+/// everything in a few cells.
+#[derive(Clone, Debug, Default)]
+pub struct Grid {
+    bounds: Option<Aabb>,
+    resolution: [usize; 3],
+    cell_size: [Float; 3],
+    /// Where each cell's entries start in `entries`, with one more at the end.
+    starts: Vec<usize>,
+    entries: Vec<usize>,
+    primitives: usize,
+}
+
+impl Grid {
+    pub fn new(bounds: &[Aabb]) -> Grid {
+        let mut grid = Grid {
+            primitives: bounds.len(),
+            ..Grid::default()
+        };
+        if bounds.is_empty() {
+            return grid;
+        }
+        let mut all = Aabb::empty();
+        for b in bounds {
+            all = all.union(b);
+        }
+        let extent = (all.max - all.min).0 .0;
+        // cubic cells, as far as flat or thin scenes allow
+        let longest = extent.into_iter().fold(0.0, Float::max);
+        let volume: Float = extent.iter().map(|e| e.max(longest * 1e-3)).product();
+        let per_unit = (DENSITY * bounds.len() as Float / volume.max(Float::MIN_POSITIVE)).cbrt();
+        grid.resolution =
+            extent.map(|e| ((e * per_unit).round() as usize).clamp(1, MAX_RESOLUTION));
+        grid.cell_size = [0, 1, 2].map(|a| extent[a] / grid.resolution[a] as Float);
+        grid.bounds = Some(all);
+
+        // count, then place, each box in the cells it overlaps
+        let cells = grid.resolution.iter().product::<usize>();
+        let mut counts = vec![0; cells + 1];
+        for b in bounds {
+            grid.for_each_cell(b, |cell| counts[cell] += 1);
+        }
+        let mut start = 0;
+        for count in counts.iter_mut() {
+            let c = *count;
+            *count = start;
+            start += c;
+        }
+        let mut next = counts.clone();
+        let mut entries = vec![0; start];
+        for (i, b) in bounds.iter().enumerate() {
+            grid.for_each_cell(b, |cell| {
+                entries[next[cell]] = i;
+                next[cell] += 1;
+            });
+        }
+        grid.entries = entries;
+        grid.starts = counts;
+        grid
+    }
+
+    /// Cell holding `position` along `axis`, clamped into the grid.
+    fn cell_of(&self, position: Float, axis: usize) -> usize {
+        let min = self.bounds.unwrap().min.0 .0[axis];
+        let cell = ((position - min) / self.cell_size[axis]).floor();
+        // NaN, for a flat axis, goes to 0
+        (cell.max(0.0) as usize).min(self.resolution[axis] - 1)
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+        x + self.resolution[0] * (y + self.resolution[1] * z)
+    }
+
+    fn for_each_cell(&self, b: &Aabb, mut f: impl FnMut(usize)) {
+        let low = [0, 1, 2].map(|a| self.cell_of(b.min.0 .0[a], a));
+        let high = [0, 1, 2].map(|a| self.cell_of(b.max.0 .0[a], a));
+        for z in low[2]..=high[2] {
+            for y in low[1]..=high[1] {
+                for x in low[0]..=high[0] {
+                    f(self.index([x, y, z]));
+                }
+            }
+        }
+    }
+
+    /// Walks the cells the ray passes through before `max_distance`, near to
+    /// far, handing each cell's entries to `cell`, which may lower the
+    /// distance still worth looking at, or end the walk with `None`.
+    fn walk(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        traversal: &mut Traversal,
+        mut cell: impl FnMut(&[usize], Float) -> Option<Float>,
+    ) {
+        let Some(bounds) = self.bounds else {
+            return;
+        };
+        let origin = r.0 .0 .0;
+        let direction = r.1 .0 .0;
+        let inverse = direction.map(|d| 1.0 / d);
+        let Some((t_near, t_far)) = slabs(&bounds, &origin, &inverse, max_distance) else {
+            return;
+        };
+
+        let mut position =
+            [0, 1, 2].map(|a| self.cell_of(origin[a] + t_near * direction[a], a) as i64);
+        let step = direction.map(|d| if d > 0.0 { 1 } else { -1 });
+        // where the ray leaves the current cell along each axis; a single
+        // layer is left only through the box, which the slabs already cover
+        let mut t_next = [0, 1, 2].map(|a| {
+            if direction[a] == 0.0 || self.resolution[a] == 1 {
+                return Float::INFINITY;
+            }
+            let boundary = position[a] + if step[a] > 0 { 1 } else { 0 };
+            (bounds.min.0 .0[a] + boundary as Float * self.cell_size[a] - origin[a]) * inverse[a]
+        });
+        let t_delta = [0, 1, 2].map(|a| self.cell_size[a] * inverse[a].abs());
+
+        let mut max = max_distance;
+        loop {
+            traversal.nodes += 1;
+            let index = self.index(position.map(|p| p as usize));
+            let entries = &self.entries[self.starts[index]..self.starts[index + 1]];
+            match cell(entries, max) {
+                Some(m) => max = m.min(max),
+                None => return,
+            }
+
+            let a = (0..3)
+                .min_by(|a, b| t_next[*a].total_cmp(&t_next[*b]))
+                .unwrap();
+            // the next cell starts past anything still worth finding
+            if t_next[a] > max || t_next[a] > t_far {
+                return;
+            }
+            position[a] += step[a];
+            if position[a] < 0 || position[a] >= self.resolution[a] as i64 {
+                return;
+            }
+            t_next[a] += t_delta[a];
+        }
+    }
+}
+
+impl Accelerator for Grid {
+    fn closest_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) -> Option<Float> {
+        let mut closest = None;
+        let mut primitives = 0;
+        // boxes in several cells are tested in each; a repeat can only find
+        // the same distance again, which is not below the one already found
+        self.walk(r, max_distance, traversal, |entries, mut max| {
+            for i in entries {
+                primitives += 1;
+                if let Some(distance) = hit(*i, max) {
+                    max = distance;
+                    closest = Some(distance);
+                }
+            }
+            Some(max)
+        });
+        traversal.primitives += primitives;
+        closest
+    }
+
+    fn any_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize) -> bool,
+        traversal: &mut Traversal,
+    ) -> bool {
+        let mut blocked = false;
+        let mut primitives = 0;
+        self.walk(r, max_distance, traversal, |entries, max| {
+            for i in entries {
+                primitives += 1;
+                if hit(*i) {
+                    blocked = true;
+                    return None;
+                }
+            }
+            Some(max)
+        });
+        traversal.primitives += primitives;
+        blocked
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    fn stats(&self) -> Stats {
+        let cells = self.starts.len().saturating_sub(1);
+        Stats {
+            name: "grid",
+            primitives: self.primitives,
+            nodes: cells,
+            leaves: (0..cells)
+                .filter(|c| self.starts[c + 1] > self.starts[*c])
+                .count(),
+            references: self.entries.len(),
+            depth: 1,
+            bytes: (self.starts.len() + self.entries.len()) * std::mem::size_of::<usize>(),
+            ..Stats::default()
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Affine map of space, kept together with its inverse: rows of a 3x3
+/// matrix, each with the translation along that axis at the end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    matrix: [[Float; 4]; 3],
+    inverse: [[Float; 4]; 3],
+}
+
+const IDENTITY: [[Float; 4]; 3] = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+];
+
+/// `a` after `b`.
+fn compose(a: &[[Float; 4]; 3], b: &[[Float; 4]; 3]) -> [[Float; 4]; 3] {
+    let mut m = [[0.0; 4]; 3];
+    for (row, a) in m.iter_mut().zip(a) {
+        for (col, entry) in row.iter_mut().enumerate() {
+            *entry = (0..3).map(|k| a[k] * b[k][col]).sum::<Float>();
+        }
+        row[3] += a[3];
+    }
+    m
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        matrix: IDENTITY,
+        inverse: IDENTITY,
+    };
+
+    pub fn translation(by: Direction) -> Transform {
+        let (mut matrix, mut inverse) = (IDENTITY, IDENTITY);
+        for axis in 0..3 {
+            matrix[axis][3] = by.0 .0[axis];
+            inverse[axis][3] = -by.0 .0[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Scales each axis by its entry of `by`, none of which may be zero.
+    pub fn scaling(by: Vec3) -> Transform {
+        assert!(by.0.iter().all(|s| *s != 0.0), "cannot scale by zero");
+        let (mut matrix, mut inverse) = (IDENTITY, IDENTITY);
+        for axis in 0..3 {
+            matrix[axis][axis] = by.0[axis];
+            inverse[axis][axis] = 1.0 / by.0[axis];
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// Turns `radians` counterclockwise around `axis`, seen from where it
+    /// points to.
+    pub fn rotation(axis: Direction, radians: Float) -> Transform {
+        let [x, y, z] = axis.normalized().0 .0;
+        let (sin, cos) = radians.sin_cos();
+        let c = 1.0 - cos;
+        let matrix = [
+            [
+                cos + x * x * c,
+                x * y * c - z * sin,
+                x * z * c + y * sin,
+                0.0,
+            ],
+            [
+                y * x * c + z * sin,
+                cos + y * y * c,
+                y * z * c - x * sin,
+                0.0,
+            ],
+            [
+                z * x * c - y * sin,
+                z * y * c + x * sin,
+                cos + z * z * c,
+                0.0,
+            ],
+        ];
+        // the inverse of a rotation is its transpose
+        let mut inverse = IDENTITY;
+        for (row, inverse) in inverse.iter_mut().enumerate() {
+            for (col, entry) in inverse.iter_mut().take(3).enumerate() {
+                *entry = matrix[col][row];
+            }
+        }
+        Transform { matrix, inverse }
+    }
+
+    /// This transform followed by `next`.
+    pub fn then(&self, next: &Transform) -> Transform {
+        Transform {
+            matrix: compose(&next.matrix, &self.matrix),
+            inverse: compose(&self.inverse, &next.inverse),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: &Point) -> Point {
+        Point(Vec3(self.matrix.map(|row| {
+            row[0] * p.0 .0[0] + row[1] * p.0 .0[1] + row[2] * p.0 .0[2] + row[3]
+        })))
+    }
+
+    /// Applies the linear part alone, as to an offset between points.
+    pub fn direction(&self, d: &Direction) -> Direction {
+        Direction(Vec3(self.matrix.map(|row| {
+            row[0] * d.0 .0[0] + row[1] * d.0 .0[1] + row[2] * d.0 .0[2]
+        })))
+    }
+
+    /// Where a surface normal goes: by the transpose of the inverse, which
+    /// keeps it square to the transformed surface. Not normalized.
+    pub fn normal(&self, n: &Direction) -> Direction {
+        let m = &self.inverse;
+        Direction(Vec3([0, 1, 2].map(|col| {
+            m[0][col] * n.0 .0[0] + m[1][col] * n.0 .0[1] + m[2][col] * n.0 .0[2]
+        })))
+    }
+
+    /// Box around the transformed corners of `bounds`.
+    pub fn bounds(&self, bounds: &Aabb) -> Aabb {
+        let mut transformed = Aabb::empty();
+        for corner in 0..8 {
+            let p = Point(Vec3([0, 1, 2].map(|axis| {
+                if corner & (1 << axis) == 0 {
+                    bounds.min.0 .0[axis]
+                } else {
+                    bounds.max.0 .0[axis]
+                }
+            })));
+            transformed.grow(&self.point(&p));
+        }
+        // pad by the rounding of the corners, so that nothing grazes out
+        let size = transformed.max.0.abs() + transformed.min.0.abs();
+        transformed.padded(gamma(3) * size.0.into_iter().fold(0.0, Float::max))
+    }
+
+    /// Bound on the error in `self.point(p)`, given that in `p`: the error
+    /// carried through, and that of the arithmetic.
+    fn point_error(&self, p: &Point, error: &Vec3) -> Vec3 {
+        Vec3(self.matrix.map(|row| {
+            let carried: Float = (0..3).map(|k| row[k].abs() * error.0[k]).sum();
+            let rounding: Float =
+                (0..3).map(|k| (row[k] * p.0 .0[k]).abs()).sum::<Float>() + row[3].abs();
+            (gamma(3) + 1.0) * carried + gamma(3) * rounding
+        }))
+    }
+}
+
+/// A shape placed in the scene by a transform. Instances of one shape share
+/// it, acceleration structure and all, so that a thousand copies of a mesh
+/// take the memory of one. The scene's structure holds the instances, and
+/// rays are taken into each one's space to search the shape's own.
+#[derive(Clone, Debug)]
+pub struct Instance {
+    pub shape: Arc<dyn Shape>,
+    /// From the shape's space to the scene's.
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn new(shape: Arc<dyn Shape>, transform: Transform) -> Instance {
+        Instance { shape, transform }
+    }
+
+    /// The ray in the shape's space with unit direction, and how much longer
+    /// distances are there.
+    fn local_ray(&self, r: &Ray) -> (Ray, Float) {
+        let inverse = self.transform.inverse();
+        let direction = inverse.direction(&r.1);
+        let stretch = direction.0.magnitude();
+        (
+            Ray(
+                inverse.point(&r.0),
+                Direction(direction.0 * (1.0 / stretch)),
+            ),
+            stretch,
+        )
+    }
+}
+
+impl Shape for Instance {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let (local, stretch) = self.local_ray(r);
+        let i = self.shape.find_intersection(&local)?;
+        let t = &self.transform;
+        Some(Intersection {
+            distance: i.distance / stretch,
+            point: t.point(&i.point),
+            surface_normal: t.normal(&i.surface_normal).normalized(),
+            geometric_normal: t.normal(&i.geometric_normal).normalized(),
+            tangent: i.tangent.map(|d| t.direction(&d).normalized()),
+            dpdu: t.direction(&i.dpdu),
+            dpdv: t.direction(&i.dpdv),
+            error: t.point_error(&i.point, &i.error),
+            ..i
+        })
+    }
+
+    fn occluded(&self, r: &Ray, max_distance: Float) -> bool {
+        let (local, stretch) = self.local_ray(r);
+        self.shape.occluded(&local, max_distance * stretch)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.shape.bounds().map(|b| self.transform.bounds(&b))
+    }
+
+    /// Uniform over the area only where the transform stretches every way
+    /// alike.
+    fn sample_surface(&self, s: (Float, Float)) -> Option<(Point, Direction)> {
+        let (p, n) = self.shape.sample_surface(s)?;
+        Some((
+            self.transform.point(&p),
+            self.transform.normal(&n).normalized(),
+        ))
+    }
+
+    fn albedo(&self, i: &Intersection) -> Option<Color> {
+        let point = self.transform.inverse().point(&i.point);
+        self.shape.albedo(&Intersection { point, ..*i })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::{parse_obj, Mesh};
+    use crate::sphere::Sphere;
+    use crate::subdivision::{subdivide, Target};
+
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+    fn skewed() -> Transform {
+        Transform::scaling(Vec3([1.0, 2.0, 0.5]))
+            .then(&Transform::rotation(Direction(Vec3([1.0, 1.0, 0.0])), 0.7))
+            .then(&Transform::translation(Direction(Vec3([3.0, -1.0, 2.0]))))
+    }
+
+    #[test]
+    fn transforms() {
+        let t = skewed();
+        let p = Point(Vec3([0.3, -0.7, 1.1]));
+        let back = t.inverse().point(&t.point(&p));
+        assert!((back - p).0.magnitude() < per_precision(1e-12, 1e-5));
+        // normals stay square to the surface they belong to
+        let (n, along) = (
+            Direction(Vec3([0.0, 0.0, 1.0])),
+            Direction(Vec3([1.0, 2.0, 0.0])),
+        );
+        assert!(t.normal(&n).dot(&t.direction(&along)).abs() < per_precision(1e-12, 1e-5));
+        let quarter = Transform::rotation(Direction(Vec3([0.0, 0.0, 1.0])), consts::PI / 2.0);
+        let turned = quarter.point(&Point(Vec3([1.0, 0.0, 0.0])));
+        assert!((turned - Point(Vec3([0.0, 1.0, 0.0]))).0.magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn instance_matches_transformed_copy() {
+        let mut cube = subdivide(&parse_obj(CUBE.as_bytes()).unwrap(), Target::Level(2));
+        let mesh: Arc<dyn Shape> = Arc::new(cube.triangulate());
+        let t = skewed();
+        let instance = Instance::new(mesh, t);
+        for v in cube.vertices.iter_mut() {
+            *v = t.point(v);
+        }
+        let copy: Mesh = cube.triangulate();
+
+        let bounds = instance.bounds().unwrap();
+        let copy_bounds = copy.bounds().unwrap();
+        assert!((0..3).all(|a| bounds.min.0 .0[a] <= copy_bounds.min.0 .0[a]
+            && bounds.max.0 .0[a] >= copy_bounds.max.0 .0[a]));
+
+        let tolerance = per_precision(1e-9, 1e-3);
+        for k in 0..200 {
+            let angle = k as Float * 0.1;
+            let origin = Point(Vec3([
+                3.0 + 6.0 * angle.cos(),
+                6.0 * angle.sin(),
+                0.03 * k as Float,
+            ]));
+            let r = Ray::from_points(origin, Point(Vec3([3.2, -1.1, 2.3])));
+            let (a, b) = (instance.find_intersection(&r), copy.find_intersection(&r));
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert!((a.distance - b.distance).abs() < tolerance);
+            assert!((a.point - b.point).0.magnitude() < tolerance);
+            assert!((a.geometric_normal - b.geometric_normal).0.magnitude() < tolerance);
+            // vertex normals are normalized before they are blended, which
+            // uneven scaling does not commute with
+            assert!(a.surface_normal.dot(&b.surface_normal) > 0.95);
+            assert_eq!(b.primitive, a.primitive);
+            assert_eq!(b.front_face, a.front_face);
+            assert!(instance.occluded(&r, a.distance + 0.01));
+            assert!(!instance.occluded(&r, a.distance - 0.01));
+        }
+    }
+
+    #[test]
+    fn spawned_rays_clear_the_surface() {
+        let sphere: Arc<dyn Shape> = Arc::new(Sphere {
+            center: Point::origin(),
+            radius: 1.0,
+        });
+        let instance = Instance::new(sphere, skewed());
+        for k in 0..100 {
+            let angle = k as Float * 0.37;
+            let target = Point(Vec3([3.0, -1.0, 2.0]));
+            let origin = target + Direction(Vec3([8.0 * angle.cos(), 8.0 * angle.sin(), 1.0]));
+            let i = instance
+                .find_intersection(&Ray::from_points(origin, target))
+                .unwrap();
+            // straight out along the normal, and skimming off at a slant
+            let (x, _) = i.geometric_normal.orthonormal_basis();
+            for tilt in [1.0, 0.05] {
+                let out = (tilt * i.geometric_normal + x).normalized();
+                assert!(instance.find_intersection(&i.spawn_ray(out)).is_none());
+            }
+        }
+    }
+}
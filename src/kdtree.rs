@@ -0,0 +1,292 @@
+use smallvec::SmallVec;
+
+use crate::aabb::Aabb;
+use crate::accel::{Accelerator, Stats, Traversal};
+use crate::bvh::slabs;
+use crate::*;
+
+/// Cost of visiting a node, relative to testing one primitive.
+const TRAVERSAL_COST: Float = 0.125;
+/// Discount on splits that leave one side empty, which rays cross for free.
+const EMPTY_BONUS: Float = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+enum Node {
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    /// The part below `split` follows its parent; the part above is elsewhere.
+    Interior {
+        axis: usize,
+        split: Float,
+        above: usize,
+    },
+}
+
+/// Kd-tree over a list of boxes, with split planes placed by the surface area
+/// heuristic among the box faces (Havran). Unlike a BVH, space is cut rather
+/// than the list, so boxes crossing a plane are listed on both sides.
+#[derive(Clone, Debug, Default)]
+pub struct KdTree {
+    nodes: Vec<Node>,
+    /// Primitive indices, leaf by leaf.
+    references: Vec<usize>,
+    bounds: Option<Aabb>,
+    primitives: usize,
+}
+
+impl KdTree {
+    pub fn new(bounds: &[Aabb]) -> KdTree {
+        let mut tree = KdTree {
+            primitives: bounds.len(),
+            ..KdTree::default()
+        };
+        if bounds.is_empty() {
+            return tree;
+        }
+        let mut root = Aabb::empty();
+        for b in bounds {
+            root = root.union(b);
+        }
+        tree.bounds = Some(root);
+        let depth = 8 + (1.3 * (bounds.len() as Float).log2()) as usize;
+        tree.build(bounds, (0..bounds.len()).collect(), root, depth);
+        tree
+    }
+
+    fn leaf(&mut self, primitives: Vec<usize>) {
+        let start = self.references.len();
+        self.references.extend(primitives);
+        self.nodes.push(Node::Leaf {
+            start,
+            end: self.references.len(),
+        });
+    }
+
+    fn build(&mut self, bounds: &[Aabb], primitives: Vec<usize>, node: Aabb, depth: usize) {
+        let n = primitives.len();
+        if n <= 1 || depth == 0 {
+            return self.leaf(primitives);
+        }
+
+        let extent = (node.max - node.min).0 .0;
+        let area = node.surface_area();
+        let mut best: Option<(Float, usize, Float)> = None;
+        let mut edges: Vec<(Float, bool)> = Vec::with_capacity(2 * n);
+        for axis in 0..3 {
+            edges.clear();
+            for p in &primitives {
+                edges.push((bounds[*p].min.0 .0[axis], false));
+                edges.push((bounds[*p].max.0 .0[axis], true));
+            }
+            // starts before ends where they meet, so touching boxes can part
+            edges.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+            let (other1, other2) = (extent[(axis + 1) % 3], extent[(axis + 2) % 3]);
+            let side_area = |length: Float| 2.0 * (other1 * other2 + length * (other1 + other2));
+            let (low, high) = (node.min.0 .0[axis], node.max.0 .0[axis]);
+            let (mut below, mut above) = (0, n);
+            for (position, end) in &edges {
+                if *end {
+                    above -= 1;
+                }
+                if *position > low && *position < high {
+                    let below_share = side_area(position - low) / area;
+                    let above_share = side_area(high - position) / area;
+                    let bonus = if below == 0 || above == 0 {
+                        1.0 - EMPTY_BONUS
+                    } else {
+                        1.0
+                    };
+                    let cost = TRAVERSAL_COST
+                        + bonus * (below_share * below as Float + above_share * above as Float);
+                    if best.is_none_or(|(c, _, _)| cost < c) {
+                        best = Some((cost, axis, *position));
+                    }
+                }
+                if !*end {
+                    below += 1;
+                }
+            }
+        }
+
+        let Some((cost, axis, split)) = best.filter(|(cost, _, _)| *cost < n as Float) else {
+            return self.leaf(primitives);
+        };
+        debug_assert!(cost.is_finite());
+        // boxes lying in the plane go both ways
+        let below: Vec<usize> = primitives
+            .iter()
+            .copied()
+            .filter(|p| bounds[*p].min.0 .0[axis] < split || bounds[*p].max.0 .0[axis] <= split)
+            .collect();
+        let above: Vec<usize> = primitives
+            .into_iter()
+            .filter(|p| bounds[*p].max.0 .0[axis] > split || bounds[*p].min.0 .0[axis] >= split)
+            .collect();
+
+        let index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            axis,
+            split,
+            above: 0,
+        });
+        let (mut below_bounds, mut above_bounds) = (node, node);
+        below_bounds.max.0 .0[axis] = split;
+        above_bounds.min.0 .0[axis] = split;
+        self.build(bounds, below, below_bounds, depth - 1);
+        let above_index = self.nodes.len();
+        self.build(bounds, above, above_bounds, depth - 1);
+        self.nodes[index] = Node::Interior {
+            axis,
+            split,
+            above: above_index,
+        };
+    }
+
+    /// Walks the leaves the ray passes through before `max_distance`, near to
+    /// far, handing each with the span of the ray inside it to `leaf`, which
+    /// may lower the distance still worth looking at, or end the walk with
+    /// `None`.
+    fn walk(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        traversal: &mut Traversal,
+        mut leaf: impl FnMut(&[usize], Float) -> Option<Float>,
+    ) {
+        let Some(bounds) = self.bounds else {
+            return;
+        };
+        let origin = r.0 .0 .0;
+        let direction = r.1 .0 .0;
+        let inverse = direction.map(|d| 1.0 / d);
+        let Some((mut t_min, mut t_max)) = slabs(&bounds, &origin, &inverse, max_distance) else {
+            return;
+        };
+        let mut max = max_distance;
+        let mut stack: SmallVec<[(usize, Float, Float); 64]> = SmallVec::new();
+        let mut index = 0;
+        loop {
+            if t_min > max {
+                // everything left is further along than what was found
+                return;
+            }
+            traversal.nodes += 1;
+            match self.nodes[index] {
+                Node::Interior { axis, split, above } => {
+                    let t_plane = (split - origin[axis]) * inverse[axis];
+                    let below_first =
+                        origin[axis] < split || (origin[axis] == split && direction[axis] <= 0.0);
+                    let (first, second) = if below_first {
+                        (index + 1, above)
+                    } else {
+                        (above, index + 1)
+                    };
+                    // NaN, for rays lying in the plane, falls through to both
+                    if t_plane > t_max || t_plane <= 0.0 {
+                        index = first;
+                    } else if t_plane < t_min {
+                        index = second;
+                    } else {
+                        stack.push((second, t_plane.max(t_min), t_max));
+                        index = first;
+                        t_max = if t_plane.is_nan() { t_max } else { t_plane };
+                    }
+                }
+                Node::Leaf { start, end } => {
+                    match leaf(&self.references[start..end], max) {
+                        Some(m) => max = m,
+                        None => return,
+                    }
+                    match stack.pop() {
+                        Some((next, near, far)) => {
+                            (index, t_min, t_max) = (next, near, far);
+                        }
+                        None => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Accelerator for KdTree {
+    fn closest_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) -> Option<Float> {
+        let mut closest = None;
+        let mut primitives = 0;
+        self.walk(r, max_distance, traversal, |leaf, mut max| {
+            for i in leaf {
+                primitives += 1;
+                if let Some(distance) = hit(*i, max) {
+                    max = distance;
+                    closest = Some(distance);
+                }
+            }
+            Some(max)
+        });
+        traversal.primitives += primitives;
+        closest
+    }
+
+    fn any_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize) -> bool,
+        traversal: &mut Traversal,
+    ) -> bool {
+        let mut blocked = false;
+        let mut primitives = 0;
+        self.walk(r, max_distance, traversal, |leaf, max| {
+            for i in leaf {
+                primitives += 1;
+                if hit(*i) {
+                    blocked = true;
+                    return None;
+                }
+            }
+            Some(max)
+        });
+        traversal.primitives += primitives;
+        blocked
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            name: "kdtree",
+            primitives: self.primitives,
+            nodes: self.nodes.len(),
+            references: self.references.len(),
+            bytes: self.nodes.len() * std::mem::size_of::<Node>()
+                + self.references.len() * std::mem::size_of::<usize>(),
+            ..Stats::default()
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0, 1));
+        }
+        while let Some((index, depth)) = stack.pop() {
+            stats.depth = stats.depth.max(depth);
+            match self.nodes[index] {
+                Node::Leaf { .. } => stats.leaves += 1,
+                Node::Interior { above, .. } => {
+                    stack.push((index + 1, depth + 1));
+                    stack.push((above, depth + 1));
+                }
+            }
+        }
+        stats
+    }
+}
@@ -0,0 +1,1074 @@
+use rayon::prelude::*;
+
+pub mod vec3;
+use smallvec::*;
+pub use vec3::*;
+
+pub mod aa;
+use aa::*;
+
+pub mod color;
+pub use color::*;
+
+pub mod aabb;
+pub mod accel;
+pub mod bezier;
+pub mod blob;
+pub mod bvh;
+pub mod cache;
+pub mod curve;
+pub mod displacement;
+pub mod grid;
+pub mod instance;
+pub mod kdtree;
+pub mod mesh;
+pub mod optics;
+pub mod packet;
+pub mod plane;
+pub mod pointcloud;
+mod simd;
+pub mod sphere;
+pub mod subdivision;
+pub mod svg;
+pub mod sweep;
+pub mod texture;
+pub mod voxel;
+pub mod wavefront;
+pub mod wide;
+
+use aabb::Aabb;
+use accel::{Accelerator, Counters, Stats, Traversal};
+use packet::Packet;
+use std::time::{Duration, Instant};
+use wavefront::Renderer;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Material {
+    pub diffuse_color: Color,
+    pub specular_color: Color,
+    pub shininess: Float,
+    pub reflectivity: Float,
+    /// Fraction of the light that meets a smooth boundary into clear
+    /// material, like glass or water, where it is reflected or refracted in
+    /// the proportions the Fresnel equations give. Refracted rays go on
+    /// through the object; shadow rays are blocked by it all the same.
+    pub transparency: Float,
+    /// Index of refraction of the material within, against a vacuum (or
+    /// air) without. Only used when `transparency` is above 0.
+    pub refractive_index: Float,
+    /// How much of each channel the material within takes away, per unit of
+    /// distance that light travels through it, so that thick colored glass
+    /// is darker than thin. `optics::absorption` finds it from the color
+    /// light is left with after some distance.
+    pub absorption: Color,
+    /// Metal to reflect light like, tinted and stronger toward grazing
+    /// angles, in place of the constant `reflectivity`.
+    pub conductor: Option<optics::Conductor>,
+}
+
+impl Material {
+    /// Solid `diffuse_color` with white highlights, that neither mirrors
+    /// nor lets light through: what the optical fields are without being
+    /// set, as in `Material { reflectivity: 0.5, ..Material::opaque(RED) }`.
+    pub const fn opaque(diffuse_color: Color) -> Material {
+        Material {
+            diffuse_color,
+            specular_color: WHITE,
+            shininess: 50.0,
+            reflectivity: 0.0,
+            transparency: 0.0,
+            refractive_index: 1.0,
+            absorption: BLACK,
+            conductor: None,
+        }
+    }
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material::opaque(WHITE)
+    }
+}
+
+pub struct Light {
+    pub point: Point,
+    pub color: Color,
+    pub radius: Float,
+    pub intensity: Float,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Intersection {
+    pub distance: Float,
+    pub point: Point,
+    /// Normal used for shading, e.g. interpolated across a mesh. Like
+    /// `geometric_normal` it points to the shape's outside, whichever side
+    /// the ray came from.
+    pub surface_normal: Direction,
+    /// Normal of the actual surface that was hit, pointing to its outside.
+    pub geometric_normal: Direction,
+    /// Whether the ray arrived on the side `geometric_normal` points to.
+    pub front_face: bool,
+    /// Direction along a strand-like surface; when present, shading treats the
+    /// hit as a fiber (Kajiya-Kay) rather than as a surface with a normal.
+    pub tangent: Option<Direction>,
+    /// Surface coordinates of the hit; what they measure depends on the shape.
+    pub uv: (Float, Float),
+    /// Rate of change of the point with `uv.0`.
+    pub dpdu: Direction,
+    /// Rate of change of the point with `uv.1`.
+    pub dpdv: Direction,
+    /// Which part of the shape was hit, e.g. the triangle of a mesh; 0 for
+    /// shapes made of a single piece.
+    pub primitive: usize,
+    /// Index of the hit object in `Scene::objects`. Shapes leave this at 0
+    /// and the scene fills it in.
+    pub object: usize,
+    /// Bound on how far `point` may be from the true surface along each axis,
+    /// from rounding and from iterative solvers stopping short.
+    pub error: Vec3,
+}
+
+impl Intersection {
+    /// Shading normal turned toward the side the ray came from, which is the
+    /// one to light for two-sided surfaces.
+    pub fn facing_normal(&self) -> Direction {
+        if self.front_face {
+            self.surface_normal
+        } else {
+            -1.0 * self.surface_normal
+        }
+    }
+
+    /// Origin for a ray leaving the surface in `direction`: `point` pushed
+    /// along the geometric normal, to the side the ray leaves by, just far
+    /// enough to clear the error bounds. The ray then cannot find the surface
+    /// it started on at the hit it came from, whatever the scene's scale.
+    fn offset_origin(&self, direction: &Direction) -> Point {
+        let n = self.geometric_normal;
+        let d =
+            n.0.abs()
+                .0
+                .iter()
+                .zip(self.error.0)
+                .map(|(n, e)| n * e)
+                .sum::<Float>();
+        let mut offset = d * n;
+        if direction.dot(&n) < 0.0 {
+            offset = -1.0 * offset;
+        }
+        let mut origin = (self.point + offset).0;
+        // the addition rounds too, so step one more ulp away from the surface
+        for (o, d) in origin.0.iter_mut().zip(offset.0 .0) {
+            if d > 0.0 {
+                *o = o.next_up();
+            } else if d < 0.0 {
+                *o = o.next_down();
+            }
+        }
+        Point(origin)
+    }
+
+    /// Ray leaving the surface in the (unit) `direction`, for reflection and
+    /// refraction.
+    pub fn spawn_ray(&self, direction: Direction) -> Ray {
+        Ray(self.offset_origin(&direction), direction)
+    }
+
+    /// Ray leaving the surface toward `target`, and the distance to it.
+    /// Shadow rays use this; only hits closer than the distance block the target.
+    pub fn spawn_ray_to(&self, target: Point) -> (Ray, Float) {
+        let origin = self.offset_origin(&(target - self.point));
+        let to_target = target - origin;
+        let distance = to_target.0.magnitude();
+        (Ray(origin, to_target.normalized()), distance)
+    }
+}
+
+/// Geometry that can be placed in a scene. Implement this to add a new kind
+/// of primitive; `Object` holds any shape as a trait object.
+pub trait Shape: std::fmt::Debug + Send + Sync {
+    /// Closest hit in front of the ray origin, if any. Rays have unit direction.
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection>;
+
+    /// Axis-aligned box containing the whole shape, or `None` if it is unbounded.
+    fn bounds(&self) -> Option<Aabb>;
+
+    /// Whether anything is hit closer than `max_distance`. Shadow rays only
+    /// need this, so shapes that can stop at the first hit they find, rather
+    /// than the nearest, should.
+    fn occluded(&self, r: &Ray, max_distance: Float) -> bool {
+        self.find_intersection(r)
+            .is_some_and(|i| i.distance < max_distance)
+    }
+
+    /// Maps `s`, uniform on the unit square, to a point on the surface and the
+    /// normal there. Shapes that cannot be sampled (e.g. infinite ones) return `None`.
+    fn sample_surface(&self, _s: (Float, Float)) -> Option<(Point, Direction)> {
+        None
+    }
+
+    /// Color of the surface at a hit on this shape, for shapes that carry
+    /// their own (like a voxel palette). `None` leaves it to the material.
+    fn albedo(&self, _i: &Intersection) -> Option<Color> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Object {
+    pub material: Material,
+    pub shape: Box<dyn Shape>,
+}
+
+impl Object {
+    pub fn new(shape: impl Shape + 'static, material: Material) -> Object {
+        Object {
+            material,
+            shape: Box::new(shape),
+        }
+    }
+}
+
+pub struct Camera {
+    pub ray: Ray,
+    pub up: Direction,
+    pub w_fov_degrees: Float,
+}
+
+impl Camera {
+    /// World-space width covered by one pixel at the part of `bounds` nearest the camera.
+    pub fn pixel_footprint(&self, bounds: &Aabb, imgx: u32) -> Float {
+        let distance = (bounds.closest_point(&self.ray.0) - self.ray.0)
+            .0
+            .magnitude();
+        distance * self.w_fov_degrees.to_radians() / imgx as Float
+    }
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub imgx: u32,
+    pub imgy: u32,
+    pub objects: Vec<Object>,
+    pub lights: Vec<Light>,
+    pub accelerator: accel::Kind,
+    pub renderer: Renderer,
+    /// Most rays traced along a path from the camera: 1 shades what camera
+    /// rays hit, and each one more follows reflections and refractions one
+    /// bounce further.
+    pub depth: usize,
+}
+
+/// The objects of a scene arranged for ray queries: an acceleration
+/// structure over those with bounds, and the rest, like infinite planes,
+/// tested one by one.
+pub struct ObjectTree<'a> {
+    objects: &'a [Object],
+    structure: Structure,
+    build_time: Duration,
+    counters: Counters,
+}
+
+/// What an `ObjectTree` keeps of the objects: the acceleration structure
+/// and which objects are in it. Between the frames of an animation it is
+/// held on its own, while the objects move.
+pub struct Structure {
+    kind: accel::Kind,
+    accelerator: Box<dyn Accelerator>,
+    /// Index into `objects` of each box in the acceleration structure.
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    /// Refits since the accelerator was built.
+    refits: usize,
+}
+
+/// The boxes of the objects that have them, which objects those are, and
+/// which are unbounded.
+fn bounds_of(objects: &[Object]) -> (Vec<Aabb>, Vec<usize>, Vec<usize>) {
+    let mut bounds = Vec::new();
+    let mut bounded = Vec::new();
+    let mut unbounded = Vec::new();
+    for (index, o) in objects.iter().enumerate() {
+        match o.shape.bounds() {
+            Some(b) => {
+                bounds.push(b);
+                bounded.push(index);
+            }
+            None => unbounded.push(index),
+        }
+    }
+    (bounds, bounded, unbounded)
+}
+
+impl Structure {
+    /// The tree over `objects`, which are the ones this was made for after
+    /// moving. While the same objects have bounds, the structure is refit to
+    /// their new boxes, and only built anew when that leaves it too slow to
+    /// search.
+    pub fn update(mut self, objects: &[Object]) -> ObjectTree<'_> {
+        let (bounds, bounded, unbounded) = bounds_of(objects);
+        let start = Instant::now();
+        let refit = bounded == self.bounded
+            && unbounded == self.unbounded
+            && self.accelerator.refit(&bounds);
+        if refit {
+            self.refits += 1;
+        } else {
+            self = Structure {
+                kind: self.kind,
+                accelerator: self.kind.build(&bounds),
+                bounded,
+                unbounded,
+                refits: 0,
+            };
+        }
+        ObjectTree {
+            objects,
+            structure: self,
+            build_time: start.elapsed(),
+            counters: Counters::default(),
+        }
+    }
+}
+
+impl<'a> ObjectTree<'a> {
+    pub fn new(objects: &'a [Object], kind: accel::Kind) -> ObjectTree<'a> {
+        let (bounds, bounded, unbounded) = bounds_of(objects);
+        let start = Instant::now();
+        let accelerator = kind.build(&bounds);
+        ObjectTree {
+            objects,
+            structure: Structure {
+                kind,
+                accelerator,
+                bounded,
+                unbounded,
+                refits: 0,
+            },
+            build_time: start.elapsed(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Lets go of the objects, so that they can be moved, keeping the
+    /// structure to be updated for them afterwards.
+    pub fn into_structure(self) -> Structure {
+        self.structure
+    }
+
+    /// Shape of the acceleration structure, its build time, and the work
+    /// done by the queries so far.
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.structure.accelerator.stats();
+        stats.build_time = self.build_time;
+        stats.refits = self.structure.refits;
+        self.counters.fill(&mut stats);
+        stats
+    }
+
+    /// Whether any object is hit closer than `max_distance`.
+    pub fn occluded(&self, ray: &Ray, max_distance: Float) -> bool {
+        let blocks = |index: usize| self.objects[index].shape.occluded(ray, max_distance);
+        if self.structure.unbounded.iter().any(|index| blocks(*index)) {
+            return true;
+        }
+        let mut traversal = Traversal::default();
+        let blocked = self.structure.accelerator.any_hit(
+            ray,
+            max_distance,
+            &mut |b| blocks(self.structure.bounded[b]),
+            &mut traversal,
+        );
+        self.counters.record(&traversal);
+        blocked
+    }
+
+    pub fn closest_intersection(&self, ray: &Ray) -> Option<(&'a Object, Intersection)> {
+        let hit = |index: usize| {
+            self.objects[index]
+                .shape
+                .find_intersection(ray)
+                .map(|i| Intersection { object: index, ..i })
+        };
+        // the unbounded objects first, so the structure can skip anything behind them
+        let mut closest: Option<Intersection> = None;
+        for index in &self.structure.unbounded {
+            if let Some(i) = hit(*index) {
+                if closest.is_none_or(|c| i.distance < c.distance) {
+                    closest = Some(i);
+                }
+            }
+        }
+        let max = closest.map_or(Float::INFINITY, |c| c.distance);
+        let mut traversal = Traversal::default();
+        self.structure.accelerator.closest_hit(
+            ray,
+            max,
+            &mut |b, max| {
+                let i = hit(self.structure.bounded[b])?;
+                (i.distance < max).then(|| {
+                    closest = Some(i);
+                    i.distance
+                })
+            },
+            &mut traversal,
+        );
+        self.counters.record(&traversal);
+        closest.map(|i| {
+            assert!(i.distance >= 0.0);
+            (&self.objects[i.object], i)
+        })
+    }
+
+    /// `closest_intersection` for each ray of a packet, with the structure
+    /// walked once for all of them.
+    pub fn closest_intersections(
+        &self,
+        packet: &Packet,
+    ) -> Vec<Option<(&'a Object, Intersection)>> {
+        let hit = |index: usize, ray: &Ray| {
+            self.objects[index]
+                .shape
+                .find_intersection(ray)
+                .map(|i| Intersection { object: index, ..i })
+        };
+        let mut closest: Vec<Option<Intersection>> = packet
+            .rays()
+            .iter()
+            .map(|ray| {
+                self.structure
+                    .unbounded
+                    .iter()
+                    .filter_map(|index| hit(*index, ray))
+                    .min_by(|a, b| a.distance.total_cmp(&b.distance))
+            })
+            .collect();
+        let mut max_distances: Vec<Float> = closest
+            .iter()
+            .map(|c| c.map_or(Float::INFINITY, |c| c.distance))
+            .collect();
+        let mut traversal = Traversal::default();
+        self.structure.accelerator.closest_hits(
+            packet,
+            &mut max_distances,
+            &mut |k, b, max| {
+                let i = hit(self.structure.bounded[b], &packet.rays()[k])?;
+                (i.distance < max).then(|| {
+                    closest[k] = Some(i);
+                    i.distance
+                })
+            },
+            &mut traversal,
+        );
+        self.counters
+            .record_queries(&traversal, packet.len() as u64);
+        closest
+            .into_iter()
+            .map(|c| {
+                c.map(|i| {
+                    assert!(i.distance >= 0.0);
+                    (&self.objects[i.object], i)
+                })
+            })
+            .collect()
+    }
+}
+
+impl Scene {
+    fn light_positions(
+        center_ray: &Ray,
+        light_radius: Float,
+        count: usize,
+        positions: &mut SmallVec<[Point; Self::MAX_LIGHT_POINTS]>,
+    ) {
+        positions.clear();
+        positions.push(center_ray.0);
+        if light_radius > 0.0 {
+            let up = Direction(Vec3([0.0, 0.0, 1.0]));
+            let right = center_ray.1.cross(&up).normalized();
+
+            let revolutions_in_spiral = 2.0;
+
+            for i in 0..count {
+                let scaler = (i as Float) / (count as Float);
+                let theta = revolutions_in_spiral * scaler * 2.0 * consts::PI;
+                let spiral_radius = scaler * light_radius;
+                let up = spiral_radius * up;
+                let right = spiral_radius * right;
+                positions.push(center_ray.0 + theta.cos() * right + theta.sin() * up);
+            }
+        }
+    }
+
+    const MAX_LIGHT_POINTS: usize = 10;
+
+    /// Points spread over each light, besides its center, to aim shadow rays at.
+    const LIGHT_POINTS: usize = 1;
+
+    /// Side of the square of pixels whose camera rays are traced together.
+    const TILE: u32 = 8;
+
+    /// Camera rays per pixel are this many squared.
+    const ANTI_ALIASING: u8 = 3;
+
+    #[inline(never)]
+    fn render_ray(&self, tree: &ObjectTree, ray: &Ray, recursion_limit: usize) -> Color {
+        if recursion_limit == 0 {
+            return BLACK;
+        }
+        self.shade(tree, ray, tree.closest_intersection(ray), recursion_limit)
+    }
+
+    /// Color seen along `ray`, given what it hits first.
+    fn shade(
+        &self,
+        tree: &ObjectTree,
+        ray: &Ray,
+        hit: Option<(&Object, Intersection)>,
+        mut recursion_limit: usize,
+    ) -> Color {
+        let mut color = BLACK;
+        recursion_limit -= 1;
+
+        let mut light_positions: SmallVec<[_; Self::MAX_LIGHT_POINTS]> =
+            smallvec![Point::origin(); Self::MAX_LIGHT_POINTS];
+
+        if let Some((object, i)) = hit {
+            color += Color(0.0, 0.0, 0.0); // ambient
+
+            // lighting and shadows
+            let normal = i.facing_normal();
+            let diffuse_color = Self::diffuse_color(object, &i);
+            for l in &self.lights {
+                let mut shaded = 0;
+                let mut total = 0;
+                Self::shadow_targets(&i, l, &mut light_positions);
+                for light_position in &light_positions {
+                    let (ray_to_light, light_distance) = i.spawn_ray_to(*light_position);
+                    if tree.occluded(&ray_to_light, light_distance) {
+                        shaded += 1;
+                    }
+                    total += 1;
+                }
+
+                if shaded == total {
+                    continue;
+                }
+
+                let unblocked = 1.0 - shaded as Float / total as Float;
+                let (response, light_distance) =
+                    Self::light_response(ray, &object.material, &i, normal, diffuse_color, l);
+                color += Self::direct_light(l, light_distance, response, unblocked);
+            }
+
+            // reflection and refraction
+            for (secondary, weight) in Self::secondary_rays(ray, &object.material, &i) {
+                let secondary_color = self.render_ray(tree, &secondary, recursion_limit);
+                if secondary_color != BLACK {
+                    color += weight * secondary_color;
+                }
+            }
+
+            // absorbed on the way from within
+            color = Self::transmittance(&object.material, &i) * color;
+        }
+
+        color
+    }
+
+    /// Color that `object` scatters light with at hit `i`.
+    fn diffuse_color(object: &Object, i: &Intersection) -> Color {
+        object
+            .shape
+            .albedo(i)
+            .unwrap_or(object.material.diffuse_color)
+    }
+
+    /// Points on light `l` that shadow rays from hit `i` are aimed at.
+    fn shadow_targets(
+        i: &Intersection,
+        l: &Light,
+        positions: &mut SmallVec<[Point; Self::MAX_LIGHT_POINTS]>,
+    ) {
+        let center_ray = Ray(l.point, i.point - l.point);
+        Self::light_positions(&center_ray, l.radius, Self::LIGHT_POINTS, positions);
+    }
+
+    /// How much of the light from `l` hit `i` scatters back along `ray`,
+    /// per unit arriving, and how far away the light is.
+    fn light_response(
+        ray: &Ray,
+        material: &Material,
+        i: &Intersection,
+        normal: Direction,
+        diffuse_color: Color,
+        l: &Light,
+    ) -> (Color, Float) {
+        let light_dir = i.point - l.point;
+        let light_distance = light_dir.0.magnitude();
+        let light_dir = light_dir.normalized();
+        let dir_to_light = -1.0 * light_dir;
+        let (diffuse, specular) = match i.tangent {
+            None => {
+                let diffuse = normal.dot(&dir_to_light).clamp(0.0, 1.0);
+                let light_reflect = light_dir.reflect(&normal);
+                let light_reflect = -1.0 * light_reflect;
+                let specular = light_reflect
+                    .dot(&ray.1)
+                    .clamp(0.0, 1.0)
+                    .powf(material.shininess);
+                (diffuse, specular)
+            }
+            Some(tangent) => {
+                // Kajiya-Kay: light scatters in a cone around the fiber
+                let cos_light = tangent.dot(&dir_to_light).clamp(-1.0, 1.0);
+                let cos_view = tangent.dot(&(-1.0 * ray.1)).clamp(-1.0, 1.0);
+                let sin_light = (1.0 - cos_light * cos_light).sqrt();
+                let sin_view = (1.0 - cos_view * cos_view).sqrt();
+                let diffuse = sin_light;
+                let specular = (sin_light * sin_view - cos_light * cos_view)
+                    .clamp(0.0, 1.0)
+                    .powf(material.shininess);
+                (diffuse, specular)
+            }
+        };
+        assert!(diffuse >= 0.0);
+        assert!(specular >= 0.0);
+        (
+            diffuse * diffuse_color + specular * material.specular_color,
+            light_distance,
+        )
+    }
+
+    /// Light from `l`, `light_distance` away, scattered with `response`, of
+    /// which the fraction `unblocked` got past whatever casts shadows.
+    fn direct_light(l: &Light, light_distance: Float, response: Color, unblocked: Float) -> Color {
+        let apparent_brightness = unblocked * l.intensity / light_distance * light_distance;
+        assert!(apparent_brightness >= 0.0);
+        let c = l.color * apparent_brightness * response;
+        assert!(c.0 >= 0.0);
+        assert!(c.1 >= 0.0);
+        assert!(c.2 >= 0.0);
+        c
+    }
+
+    /// Share of each channel left of the light that comes along a ray to hit
+    /// `i` on `material`, after the way through it from within.
+    fn transmittance(material: &Material, i: &Intersection) -> Color {
+        if i.front_face || material.absorption == BLACK {
+            return WHITE;
+        }
+        optics::transmittance(material.absorption, i.distance)
+    }
+
+    /// Rays that carry on from hit `i` on `material`, each with the weight of
+    /// the light it brings back: the mirror reflection, as strong as a metal
+    /// reflects, to which clear material adds the share Fresnel reflects, and
+    /// the refraction through the boundary, unless it is totally internally
+    /// reflected.
+    fn secondary_rays(
+        ray: &Ray,
+        material: &Material,
+        i: &Intersection,
+    ) -> SmallVec<[(Ray, Color); 2]> {
+        let mut rays = SmallVec::new();
+        let normal = i.facing_normal();
+        let mut reflectivity = match &material.conductor {
+            Some(metal) => metal.reflectance(-ray.1.dot(&normal)),
+            None => material.reflectivity * WHITE,
+        };
+        if material.transparency > 0.0 {
+            // into the object, or out of it from within
+            let eta = if i.front_face {
+                1.0 / material.refractive_index
+            } else {
+                material.refractive_index
+            };
+            let reflected = match ray.1.refract(&normal, eta) {
+                Some(direction) => {
+                    let reflected = optics::fresnel_dielectric(-ray.1.dot(&normal), eta);
+                    let transmitted = material.transparency * (1.0 - reflected) * WHITE;
+                    rays.push((i.spawn_ray(direction.normalized()), transmitted));
+                    reflected
+                }
+                None => 1.0,
+            };
+            reflectivity += material.transparency * reflected * WHITE;
+        }
+        if reflectivity != BLACK {
+            rays.insert(0, (Self::reflected_ray(ray, i), reflectivity));
+        }
+        rays
+    }
+
+    /// Mirror reflection of `ray` off hit `i`.
+    fn reflected_ray(ray: &Ray, i: &Intersection) -> Ray {
+        let reflected_dir = ray.1.reflect(&i.facing_normal()).normalized();
+        i.spawn_ray(reflected_dir)
+    }
+
+    /// Renders to the image at `path`, returning what the acceleration
+    /// structure did along the way.
+    pub fn render(&self, path: &str) -> Stats {
+        let (image, stats) = self.render_image();
+        image.save(path).unwrap();
+        stats
+    }
+
+    /// The rendered image, and what the acceleration structure did along the
+    /// way.
+    pub fn render_image(&self) -> (image::RgbImage, Stats) {
+        let tree = ObjectTree::new(&self.objects, self.accelerator);
+        let colors = match self.renderer {
+            Renderer::Recursive => self.render_recursive(&tree),
+            Renderer::Wavefront => self.render_wavefront(&tree),
+        };
+        let mut imgbuf = image::ImageBuffer::new(self.imgx, self.imgy);
+        for (pixel, color) in imgbuf.pixels_mut().zip(colors) {
+            *pixel = image::Rgb(color.to_rgb());
+        }
+        (imgbuf, tree.stats())
+    }
+
+    /// Camera ray through the point `(x, y)` of the image, in pixels.
+    fn pixel_ray(&self, x: Float, y: Float) -> Ray {
+        let camera_right = self.camera.ray.1.cross(&self.camera.up);
+        let camera_w_fov_radians: Float = self.camera.w_fov_degrees.to_radians();
+        let camera_h_fov_radians =
+            camera_w_fov_radians * (self.imgy as Float) / (self.imgx as Float);
+        let center_x = self.imgx as Float / 2.0;
+        let center_y = self.imgy as Float / 2.0;
+
+        let radians_x = (x - center_x) / (self.imgx as Float) * camera_w_fov_radians;
+        let radians_y = (center_y - y) / (self.imgy as Float) * camera_h_fov_radians;
+        let pixel_dir =
+            self.camera.ray.1 .0 + camera_right.0 * radians_x + self.camera.up.0 * radians_y;
+        let pixel_dir = Direction(pixel_dir.normalized());
+        Ray(self.camera.ray.0, pixel_dir)
+    }
+
+    /// Colors of the pixels, row by row, with each camera ray followed to the
+    /// end before the next: the rays it spawns are traced as they come up.
+    fn render_recursive(&self, tree: &ObjectTree) -> Vec<Color> {
+        let aa = AntiAliasing::create(Self::ANTI_ALIASING);
+
+        // camera rays go through the structure a tile at a time, one packet
+        // per anti-aliasing offset; what they spawn is traced ray by ray
+        let tiles: Vec<(u32, u32)> = (0..self.imgy)
+            .step_by(Self::TILE as usize)
+            .flat_map(|y| {
+                (0..self.imgx)
+                    .step_by(Self::TILE as usize)
+                    .map(move |x| (x, y))
+            })
+            .collect();
+        let rendered: Vec<Vec<(u32, u32, Color)>> = tiles
+            .par_iter()
+            .map(|(x0, y0)| {
+                let pixels: Vec<(u32, u32)> = (*y0..(y0 + Self::TILE).min(self.imgy))
+                    .flat_map(|y| (*x0..(x0 + Self::TILE).min(self.imgx)).map(move |x| (x, y)))
+                    .collect();
+                let mut colors = vec![BLACK; pixels.len()];
+                for (xx, yy) in aa.offsets() {
+                    let packet = Packet::new(
+                        pixels
+                            .iter()
+                            .map(|(x, y)| self.pixel_ray(xx + *x as Float, yy + *y as Float))
+                            .collect(),
+                    );
+                    let hits = tree.closest_intersections(&packet);
+                    for ((color, ray), hit) in colors.iter_mut().zip(packet.rays()).zip(hits) {
+                        *color += self.shade(tree, ray, hit, self.depth);
+                    }
+                }
+                let count = aa.offsets().len();
+                pixels
+                    .into_iter()
+                    .zip(colors)
+                    .map(|((x, y), mut color)| {
+                        color *= 1.0 / count as Float;
+                        (x, y, color)
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut colors = vec![BLACK; self.imgx as usize * self.imgy as usize];
+        for (x, y, color) in rendered.into_iter().flatten() {
+            colors[y as usize * self.imgx as usize + x as usize] = color;
+        }
+        colors
+    }
+}
+
+/// Deterministic numbers in [0, 1), for tests that need many unlike inputs.
+#[cfg(test)]
+pub(crate) struct Random(pub u64);
+
+#[cfg(test)]
+impl Random {
+    pub fn next(&mut self) -> Float {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 11) as Float / (1u64 << 53) as Float
+    }
+
+    /// A point in the unit cube.
+    pub fn point(&mut self) -> Point {
+        Point(Vec3([self.next(), self.next(), self.next()]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    /// Rays spawned off a hit in every direction around it must not find the
+    /// same spot again: those leaving outward miss entirely, and those going
+    /// inward travel through the shape.
+    fn assert_no_self_hits(shape: &dyn Shape, i: &Intersection, inside: bool) {
+        let (x, y) = i.geometric_normal.orthonormal_basis();
+        for k in 0..64 {
+            let angle = k as Float * 0.1;
+            for tilt in [0.01, 0.5, 1.0, -0.01, -0.5, -1.0] {
+                let dir =
+                    (tilt * i.geometric_normal + angle.cos() * x + angle.sin() * y).normalized();
+                let outward = dir.dot(&i.geometric_normal) > 0.0;
+                match shape.find_intersection(&i.spawn_ray(dir)) {
+                    None => assert!(outward || !inside),
+                    Some(hit) => {
+                        assert!(!outward, "outward ray hit again at {}", hit.distance);
+                        // f32 this far out has errors the size of the shapes
+                        let clearance = per_precision(1e3, 2.0) * i.error.magnitude();
+                        assert!((hit.point - i.point).0.magnitude() > clearance);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spawned_rays_clear_the_surface() {
+        // a big sphere far from the origin, where a fixed offset is lost in
+        // rounding, and a tiny one that a fixed offset would jump right over
+        for (center, radius) in [(1e6, 1e3), (1.0, 1e-5)] {
+            let sphere = Sphere {
+                center: Point(Vec3([center; 3])),
+                radius,
+            };
+            let origin = Point(Vec3([
+                center + 0.3 * radius,
+                center,
+                center + 10.0 * radius,
+            ]));
+            let i = sphere
+                .find_intersection(&Ray(origin, Direction(Vec3([0.0, 0.0, -1.0]))))
+                .unwrap();
+            assert_no_self_hits(&sphere, &i, true);
+
+            // rays leaving through the back of the surface start inside
+            let through = i.spawn_ray(Direction(Vec3([0.0, 0.0, -1.0])));
+            let far_side = sphere.find_intersection(&through).unwrap();
+            assert!(!far_side.front_face);
+            assert!(far_side.distance > radius);
+        }
+    }
+
+    #[test]
+    fn spawned_rays_clear_a_mesh() {
+        // far out, yet with f32 still tens of thousands of steps across it
+        let offset = per_precision(1.0, 1e-3) * Vec3([1e5, -2e5, 3e5]);
+        let corners = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ];
+        let tetrahedron = mesh::Mesh::new(
+            corners.map(|c| Point(Vec3(c) + offset)).to_vec(),
+            Vec::new(),
+            vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+        );
+        let origin = Point(Vec3([0.3, 0.2, 5.0]) + offset);
+        let i = tetrahedron
+            .find_intersection(&Ray(origin, Direction(Vec3([0.0, 0.0, -1.0]))))
+            .unwrap();
+        assert!(i.front_face);
+        assert_no_self_hits(&tetrahedron, &i, true);
+    }
+
+    #[test]
+    fn occlusion() {
+        let material = Material {
+            shininess: 1.0,
+            ..Material::opaque(WHITE)
+        };
+        let objects = vec![
+            Object::new(
+                plane::Plane {
+                    point: Point(Vec3([0.0, 0.0, -10.0])),
+                    normal: Direction(Vec3([0.0, 0.0, 1.0])),
+                },
+                material,
+            ),
+            Object::new(
+                Sphere {
+                    center: Point::origin(),
+                    radius: 1.0,
+                },
+                material,
+            ),
+        ];
+        let tree = ObjectTree::new(&objects, accel::Kind::default());
+        let down = Ray(
+            Point(Vec3([0.0, 0.0, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+        assert!(!tree.occluded(&down, 3.9));
+        assert!(tree.occluded(&down, 4.1));
+        let beside = Ray(
+            Point(Vec3([2.0, 0.0, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+        assert!(!tree.occluded(&beside, 14.9));
+        assert!(tree.occluded(&beside, 15.1));
+        assert_eq!(1, tree.closest_intersection(&down).unwrap().1.object);
+    }
+
+    #[test]
+    fn spawned_ray_to_target() {
+        let sphere = Sphere {
+            center: Point::origin(),
+            radius: 1.0,
+        };
+        let i = sphere
+            .find_intersection(&Ray(
+                Point(Vec3([0.0, 0.0, 5.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        let target = Point(Vec3([0.0, 3.0, 4.0]));
+        let (r, distance) = i.spawn_ray_to(target);
+        assert!((r.0 + distance * r.1 - target).0.magnitude() < 1e-9);
+        assert!(sphere.find_intersection(&r).is_none());
+    }
+
+    #[test]
+    fn refraction() {
+        let glass = Material {
+            specular_color: BLACK,
+            shininess: 1.0,
+            transparency: 1.0,
+            refractive_index: 1.5,
+            ..Material::opaque(BLACK)
+        };
+        let tolerance = per_precision(1e-9, 1e-4);
+        let ball = Sphere {
+            center: Point::origin(),
+            radius: 1.0,
+        };
+
+        // head on, 4% is reflected and the rest goes in, unbent
+        let toward = Ray(
+            Point(Vec3([0.0, -5.0, 0.0])),
+            Direction(Vec3([0.0, 1.0, 0.0])),
+        );
+        let i = ball.find_intersection(&toward).unwrap();
+        let rays = Scene::secondary_rays(&toward, &glass, &i);
+        assert_eq!(2, rays.len());
+        assert!((rays[0].1 .0 - 0.04).abs() < tolerance);
+        assert!((rays[1].1 .0 - 0.96).abs() < tolerance);
+        assert!((rays[1].0 .1 - toward.1).0.magnitude() < tolerance);
+        // and comes out the same way
+        let exit = ball.find_intersection(&rays[1].0).unwrap();
+        assert!(!exit.front_face);
+        let out = Scene::secondary_rays(&rays[1].0, &glass, &exit);
+        assert!((out[1].0 .1 - toward.1).0.magnitude() < tolerance);
+        assert!(out[1].0 .0 .0 .0[1] > 1.0);
+
+        // from within, beyond the critical angle, all of it is reflected
+        let inside = Ray(
+            Point(Vec3([0.0, 0.8, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = ball.find_intersection(&inside).unwrap();
+        let rays = Scene::secondary_rays(&inside, &glass, &i);
+        assert_eq!(1, rays.len());
+        assert_eq!(WHITE, rays[0].1);
+        assert!(rays[0].0 .1 .0 .0[1] < 0.0);
+        assert!(ball
+            .find_intersection(&rays[0].0)
+            .is_some_and(|i| !i.front_face));
+        // while a steeper ray gets out, bent away from the normal
+        let steeper = Ray(
+            Point(Vec3([0.0, 0.5, 0.0])),
+            Direction(Vec3([1.0, 0.0, 0.0])),
+        );
+        let i = ball.find_intersection(&steeper).unwrap();
+        let rays = Scene::secondary_rays(&steeper, &glass, &i);
+        assert_eq!(2, rays.len());
+        let cos_t = rays[1].0 .1.dot(&i.surface_normal).abs();
+        assert!((cos_t - (1.0 - 0.75 * 0.75 as Float).sqrt()).abs() < tolerance);
+
+        // a lit wall seen through a ball of glass, dimmed by the two surfaces
+        let wall = Material {
+            specular_color: BLACK,
+            shininess: 1.0,
+            ..Material::opaque(RED)
+        };
+        let scene = |ball_material: Option<Material>| {
+            let mut objects = vec![Object::new(
+                plane::Plane {
+                    point: Point(Vec3([0.0, 5.0, 0.0])),
+                    normal: Direction(Vec3([0.0, -1.0, 0.0])),
+                },
+                wall,
+            )];
+            if let Some(material) = ball_material {
+                objects.push(Object::new(ball, material));
+            }
+            Scene {
+                camera: Camera {
+                    ray: toward,
+                    up: Direction(Vec3([0.0, 0.0, 1.0])),
+                    w_fov_degrees: 30.0,
+                },
+                imgx: 1,
+                imgy: 1,
+                objects,
+                lights: vec![Light {
+                    point: Point(Vec3([0.0, 3.0, 3.0])),
+                    color: WHITE,
+                    intensity: 1.0,
+                    radius: 0.0,
+                }],
+                accelerator: accel::Kind::default(),
+                renderer: Renderer::Recursive,
+                depth: 4,
+            }
+        };
+        let seen = |scene: &Scene| {
+            let tree = ObjectTree::new(&scene.objects, scene.accelerator);
+            scene.render_ray(&tree, &toward, scene.depth)
+        };
+        let bare = seen(&scene(None));
+        let through = seen(&scene(Some(glass)));
+        assert!(bare.0 > 0.1);
+        assert!(
+            (through.0 - 0.96 * 0.96 * bare.0).abs() < tolerance,
+            "{:?}",
+            through
+        );
+
+        // and tinted by the way through it, two units long
+        let tinted = Material {
+            absorption: optics::absorption(Color(0.5, 1.0, 1.0), 2.0),
+            ..glass
+        };
+        let through_tint = seen(&scene(Some(tinted)));
+        assert!((through_tint.0 - 0.5 * through.0).abs() < tolerance);
+    }
+}
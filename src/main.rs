@@ -1,395 +1,479 @@
-use rayon::prelude::*;
+use std::path::Path;
 
-mod vec3;
-use smallvec::*;
-use vec3::*;
+use jray::plane::Plane;
+use jray::sphere::Sphere;
+use jray::*;
 
-mod aa;
-use aa::*;
+const MODEL_MATERIAL: Material = Material::opaque(Color(0.8, 0.8, 0.7));
 
-mod color;
-use color::*;
-
-mod sphere;
-
-#[derive(Copy, Clone, Debug)]
-pub struct Material {
-    pub diffuse_color: Color,
-    pub specular_color: Color,
-    pub shininess: f64,
-    pub reflectivity: f64,
-}
-
-struct Light {
-    pub point: Point,
-    pub color: Color,
-    pub radius: f64,
-    pub intensity: f64,
-}
-
-#[derive(Debug)]
-pub struct Intersection {
-    pub distance: f64,
-    pub point: Point,
-    pub surface_normal: Direction,
-}
-
-#[derive(Debug)]
-enum Shape {
-    Sphere { center: Point, radius: f64 },
-    Plane { point: Point, normal: Direction },
+/// `noise[:frequency]`, `checker[:size]`, or the path of an image.
+fn parse_texture(spec: &str) -> texture::Texture {
+    let (name, parameter) = match spec.split_once(':') {
+        Some((name, parameter)) => (name, parameter.parse::<Float>().ok()),
+        None => (spec, None),
+    };
+    match name {
+        "noise" => texture::Texture::Noise {
+            frequency: parameter.unwrap_or(4.0),
+        },
+        "checker" => texture::Texture::Checker {
+            size: parameter.unwrap_or(0.25),
+        },
+        _ => texture::Texture::load(Path::new(spec))
+            .unwrap_or_else(|e| panic!("failed to load {}: {}", spec, e)),
+    }
 }
 
-impl Shape {
-    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
-        match self {
-            Shape::Plane { point, normal } => {
-                // https://en.wikipedia.org/wiki/Line%E2%80%93plane_intersection#Algebraic_form
-                let p0 = *point;
-                let n = normal;
-                let l0 = r.0;
-                let l = r.1;
-
-                let l_dot_n = l.dot(n);
-
-                if l_dot_n == 0.0 {
-                    return None;
-                }
-
-                let distance = (p0 - l0).dot(n) / l_dot_n;
-
-                if distance <= 0.0 {
-                    return None;
+fn main() {
+    let mut output = String::from("out.png");
+    let mut models = Vec::new();
+    let mut obj_paths = Vec::new();
+    let mut subdivide_levels = None;
+    let mut subdivide_pixels = None;
+    let mut displacement = None;
+    let mut displacement_spec = String::new();
+    let mut displace_scale = 0.1;
+    let mut displace_vector = false;
+    let mut displace_edge = 0.1;
+    let mut svg_paths = Vec::new();
+    let mut extrusion = svg::Extrusion {
+        depth: 0.3,
+        bevel: 0.0,
+    };
+    let mut svg_material = MODEL_MATERIAL;
+    let mut voxel_grids = Vec::new();
+    let mut accelerator = accel::Kind::default();
+    let mut renderer = wavefront::Renderer::default();
+    let mut depth = 4;
+    let mut cache = None;
+    let mut point_paths = Vec::new();
+    let mut point_radius = None;
+    let mut splat = pointcloud::Splat::Sphere;
+    let mut metal = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bpt" => {
+                let path = args.next().expect("--bpt requires a path");
+                let patches = bezier::load_bpt(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                models.extend(patches.into_iter().map(|p| Object::new(p, MODEL_MATERIAL)));
+            }
+            "--curves" => {
+                let path = args.next().expect("--curves requires a path");
+                let curves = curve::load_strands(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                models.extend(curves.into_iter().map(|c| Object::new(c, MODEL_MATERIAL)));
+            }
+            "--blob" => {
+                let path = args.next().expect("--blob requires a path");
+                let blob = blob::load_blob(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                models.push(Object::new(blob, MODEL_MATERIAL));
+            }
+            "--lathe" => {
+                let path = args.next().expect("--lathe requires a path");
+                let (profile, _) = sweep::load_sweep(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                let mesh = sweep::lathe(&profile, sweep::STEPS, sweep::SEGMENTS);
+                models.push(Object::new(mesh, MODEL_MATERIAL));
+            }
+            "--sweep" => {
+                let path = args.next().expect("--sweep requires a path");
+                let (profile, along) = sweep::load_sweep(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                if along.len() < 2 {
+                    panic!("{} needs at least two 'path' points", path);
                 }
-
-                let point = l0 + l * distance;
-                Some(Intersection {
-                    distance,
-                    point,
-                    surface_normal: *normal,
-                })
+                let mesh = sweep::sweep(&profile, &along, sweep::STEPS);
+                models.push(Object::new(mesh, MODEL_MATERIAL));
+            }
+            "--svg" => svg_paths.push(args.next().expect("--svg requires a path")),
+            "--svg-depth" => {
+                extrusion.depth = args
+                    .next()
+                    .and_then(|n| n.parse::<Float>().ok())
+                    .filter(|d| *d > 0.0)
+                    .expect("--svg-depth requires a number above 0");
+            }
+            "--svg-bevel" => {
+                let bevel = args.next().and_then(|n| n.parse::<Float>().ok());
+                extrusion.bevel = bevel.expect("--svg-bevel requires a number");
+            }
+            "--svg-color" => {
+                let rgb: Vec<_> = args
+                    .next()
+                    .expect("--svg-color requires r,g,b")
+                    .split(',')
+                    .map(|c| c.parse::<Float>().expect("--svg-color requires r,g,b"))
+                    .collect();
+                assert!(rgb.len() == 3, "--svg-color requires r,g,b");
+                svg_material.diffuse_color = Color(rgb[0], rgb[1], rgb[2]);
+            }
+            "--vox" => {
+                let path = args.next().expect("--vox requires a path");
+                let grid = voxel::load_vox(Path::new(&path))
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                voxel_grids.push(grid);
+            }
+            "--raw" => {
+                let path = args.next().expect("--raw requires a path and a size");
+                let size = args
+                    .next()
+                    .and_then(|s| {
+                        let s = s
+                            .split('x')
+                            .map(|n| n.parse::<usize>().ok())
+                            .collect::<Option<Vec<_>>>()?;
+                        <[usize; 3]>::try_from(s).ok()
+                    })
+                    .expect("--raw requires a size like 64x64x64");
+                let grid = voxel::load_raw(Path::new(&path), size)
+                    .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+                voxel_grids.push(grid);
             }
-            Shape::Sphere { center, radius } => sphere::find_intersection(*center, *radius, r),
+            "--points" => point_paths.push(args.next().expect("--points requires a path")),
+            "--point-radius" => {
+                let radius = args.next().and_then(|n| n.parse::<Float>().ok());
+                point_radius = Some(radius.expect("--point-radius requires a length"));
+            }
+            "--splats" => splat = pointcloud::Splat::Disk,
+            "--accel" => {
+                let name = args
+                    .next()
+                    .expect("--accel requires bvh, bvh4, bvh8, kdtree or grid");
+                accelerator = name.parse().unwrap_or_else(|e| panic!("{}", e));
+            }
+            "--renderer" => {
+                let name = args
+                    .next()
+                    .expect("--renderer requires recursive or wavefront");
+                renderer = name.parse().unwrap_or_else(|e| panic!("{}", e));
+            }
+            "--metal" => {
+                let name = args
+                    .next()
+                    .expect("--metal requires gold, silver, copper, aluminium or chromium");
+                metal = Some(name.parse().unwrap_or_else(|e| panic!("{}", e)));
+            }
+            "--depth" => {
+                depth = args
+                    .next()
+                    .and_then(|n| n.parse::<usize>().ok())
+                    .filter(|d| *d > 0)
+                    .expect("--depth requires a count of 1 or more");
+            }
+            "--obj" => obj_paths.push(args.next().expect("--obj requires a path")),
+            "--cache" => {
+                let dir = args.next().expect("--cache requires a directory");
+                cache = Some(cache::Cache::new(dir));
+            }
+            "--subdivide" => {
+                let levels = args.next().and_then(|n| n.parse::<u32>().ok());
+                subdivide_levels = Some(levels.expect("--subdivide requires a level"));
+            }
+            "--subdivide-pixels" => {
+                let pixels = args.next().and_then(|n| n.parse::<Float>().ok());
+                subdivide_pixels = Some(pixels.expect("--subdivide-pixels requires a length"));
+            }
+            "--displace" => {
+                let spec = args.next().expect("--displace requires a texture");
+                displacement = Some(parse_texture(&spec));
+                displacement_spec = spec;
+            }
+            "--displace-scale" => {
+                let scale = args.next().and_then(|n| n.parse::<Float>().ok());
+                displace_scale = scale.expect("--displace-scale requires a number");
+            }
+            "--displace-edge" => {
+                let length = args.next().and_then(|n| n.parse::<Float>().ok());
+                displace_edge = length.expect("--displace-edge requires a length");
+            }
+            "--displace-vector" => displace_vector = true,
+            _ => output = arg,
         }
     }
-}
-
-#[derive(Debug)]
-struct Object {
-    pub material: Material,
-    pub shape: Shape,
-}
 
-struct Camera {
-    ray: Ray,
-    up: Direction,
-    w_fov_degrees: f64,
-}
-
-struct Scene {
-    camera: Camera,
-    imgx: u32,
-    imgy: u32,
-    objects: Vec<Object>,
-    lights: Vec<Light>,
-}
-
-impl Scene {
-    fn closest_intersection(&self, ray: &Ray) -> Option<(&Object, Intersection)> {
-        let intersections = self
-            .objects
-            .iter()
-            .filter_map(|o| o.shape.find_intersection(&ray).map(|i| (o, i)));
-        let closest =
-            intersections.min_by(|(_, i1), (_, i2)| i1.distance.partial_cmp(&i2.distance).unwrap());
-        if let Some((_, i)) = &closest {
-            assert!(i.distance >= 0.0);
+    let camera = Camera {
+        ray: Ray::from_points(Point(Vec3([-4.9, 3.0, 3.0])), Point(Vec3([0.0, 0.0, 0.0]))),
+        up: Direction(Vec3([0.0, 0.0, 1.0])),
+        w_fov_degrees: 90.0,
+    };
+    let (imgx, imgy) = (800, 800);
+
+    for path in svg_paths {
+        let mut outlines = svg::load_svg(Path::new(&path))
+            .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+        // fit the drawing into a 3 unit square, centered on the origin
+        let points = || outlines.iter().flat_map(|o| o.contours.iter().flatten());
+        let (mut min, mut max) = (Vec3([Float::INFINITY; 3]), Vec3([Float::NEG_INFINITY; 3]));
+        for p in points() {
+            min = Vec3([min.0[0].min(p.0), min.0[1].min(p.1), 0.0]);
+            max = Vec3([max.0[0].max(p.0), max.0[1].max(p.1), 0.0]);
         }
-        closest
-    }
-
-    fn light_positions(
-        center_ray: &Ray,
-        light_radius: f64,
-        count: usize,
-        positions: &mut SmallVec<[Point; Self::MAX_LIGHT_POINTS]>,
-    ) {
-        positions.clear();
-        positions.push(center_ray.0);
-        if light_radius > 0.0 {
-            let up = Direction(Vec3([0.0, 0.0, 1.0]));
-            let right = center_ray.1.cross(&up).normalized();
-
-            let revolutions_in_spiral = 2.0;
-
-            for i in 0..count {
-                let scaler = (i as f64) / (count as f64);
-                let theta = revolutions_in_spiral * scaler * 2.0 * std::f64::consts::PI;
-                let spiral_radius = scaler * light_radius;
-                let up = spiral_radius * up;
-                let right = spiral_radius * right;
-                positions.push(center_ray.0 + theta.cos() * right + theta.sin() * up);
-            }
+        let center = 0.5 * (min + max);
+        let scale = 3.0 / (max.0[0] - min.0[0]).max(max.0[1] - min.0[1]);
+        for p in outlines
+            .iter_mut()
+            .flat_map(|o| o.contours.iter_mut().flatten())
+        {
+            *p = (scale * (p.0 - center.0[0]), scale * (p.1 - center.0[1]));
         }
+        let mesh = svg::extrude(&outlines, &extrusion);
+        models.push(Object::new(mesh, svg_material));
     }
 
-    const MAX_LIGHT_POINTS: usize = 10;
-
-    #[inline(never)]
-    fn render_ray(&self, ray: &Ray, mut recursion_limit: usize) -> Color {
-        let mut color = BLACK;
+    for mut grid in voxel_grids {
+        // fit the grid into a 3 unit cube, centered on the origin
+        let longest = grid.size.into_iter().max().unwrap_or(1).max(1);
+        grid.voxel_size = 3.0 / longest as Float;
+        grid.origin = Point(Vec3(grid.size.map(|s| -0.5 * s as Float * grid.voxel_size)));
+        models.push(Object::new(grid, MODEL_MATERIAL));
+    }
 
-        if recursion_limit == 0 {
-            return color;
+    for path in point_paths {
+        let path = Path::new(&path);
+        let mut points = if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("ply"))
+        {
+            pointcloud::load_ply(path)
+        } else {
+            pointcloud::load_xyz(path)
         }
+        .unwrap_or_else(|e| panic!("failed to load {}: {}", path.display(), e));
+        // fit the points into a 3 unit cube, centered on the origin
+        let mut bounds = aabb::Aabb::empty();
+        for p in &points.positions {
+            bounds.grow(p);
+        }
+        let center = Point(0.5 * (bounds.min.0 + bounds.max.0));
+        let extent = bounds.max - bounds.min;
+        let scale = 3.0
+            / extent
+                .0
+                 .0
+                .into_iter()
+                .fold(Float::MIN_POSITIVE, Float::max);
+        for p in points.positions.iter_mut() {
+            *p = Point::origin() + scale * (*p - center);
+        }
+        // about the spacing of points spread over a surface this size
+        let radius =
+            point_radius.unwrap_or_else(|| 3.0 / (points.positions.len().max(1) as Float).sqrt());
+        let cloud = pointcloud::PointCloud::new(points, radius, splat)
+            .unwrap_or_else(|e| panic!("failed to load {}: {}", path.display(), e));
+        models.push(Object::new(cloud, MODEL_MATERIAL));
+    }
 
-        recursion_limit -= 1;
-
-        let light_points = 1;
-
-        assert!(light_points <= Self::MAX_LIGHT_POINTS);
-
-        let mut light_positions: SmallVec<[_; Self::MAX_LIGHT_POINTS]> =
-            smallvec![Point::origin(); Self::MAX_LIGHT_POINTS];
-
-        if let Some((object, i)) = self.closest_intersection(&ray) {
-            color += Color(0.0, 0.0, 0.0); // ambient
-
-            // lighting and shadows
-            let slightly_off_surface = Point(i.point.0 + i.surface_normal.0 * 0.001);
-            for l in &self.lights {
-                let mut shaded = 0;
-                let mut total = 0;
-                let center_ray = Ray(l.point, i.point - l.point);
-                Self::light_positions(&center_ray, l.radius, light_points, &mut light_positions);
-                for light_position in &light_positions {
-                    let light_dir = *light_position - i.point;
-                    let light_distance = light_dir.0.magnitude();
-                    let ray_to_light = Ray(slightly_off_surface, light_dir.normalized());
-                    if let Some((_shadow_obj, shadow_i)) = self.closest_intersection(&ray_to_light)
-                    {
-                        // intersection with shadow object happens ...
-                        if shadow_i.distance > light_distance {
-                            // ... behind the light
-                        } else {
-                            // ... between the light and the target
-                            shaded += 1;
-                        }
-                    }
-                    total += 1;
-                }
-
-                if shaded == total {
+    for path in obj_paths {
+        let source =
+            std::fs::read(&path).unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+        // everything below that changes the mesh, including the camera the
+        // subdivision may be sized for, and the displacement image if any
+        let settings = format!(
+            "{:?} {:?} {} {:?} {:?} {:?} {} {} {}",
+            camera.ray,
+            camera.w_fov_degrees,
+            imgx,
+            subdivide_levels,
+            subdivide_pixels,
+            displacement_spec,
+            displace_scale,
+            displace_vector,
+            displace_edge
+        );
+        let texture = std::fs::read(&displacement_spec).unwrap_or_default();
+        let key = cache::Key::new(&source)
+            .with(settings.as_bytes())
+            .with(&texture);
+        if let Some(cache) = &cache {
+            match cache.load_mesh(key) {
+                Ok(mesh) => {
+                    models.push(Object::new(mesh, MODEL_MATERIAL));
                     continue;
                 }
-
-                let unblocked = 1.0 - shaded as f64 / total as f64;
-                let light_dir = i.point - l.point;
-                let light_distance = light_dir.0.magnitude();
-                let apparent_brightness = unblocked * l.intensity / light_distance * light_distance;
-                assert!(apparent_brightness >= 0.0);
-                let light_dir = light_dir.normalized();
-                let dir_to_light = -1.0 * light_dir;
-                let diffuse = i.surface_normal.dot(&dir_to_light).clamp(0.0, 1.0);
-                assert!(diffuse >= 0.0);
-                let light_reflect = light_dir.reflect(&i.surface_normal);
-                let light_reflect = -1.0 * light_reflect;
-                let specular = light_reflect
-                    .dot(&ray.1)
-                    .clamp(0.0, 1.0)
-                    .powf(object.material.shininess);
-                assert!(specular >= 0.0);
-
-                let c = l.color
-                    * apparent_brightness
-                    * (diffuse * object.material.diffuse_color
-                        + specular * object.material.specular_color);
-                assert!(c.0 >= 0.0);
-                assert!(c.1 >= 0.0);
-                assert!(c.2 >= 0.0);
-                color += c;
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("rebuilding {}: cached copy unusable: {}", path, e),
             }
+        }
 
-            // reflection
-            if object.material.reflectivity > 0.0 {
-                let reflected_dir = ray.1.reflect(&i.surface_normal).normalized();
-                let reflected_ray = Ray(slightly_off_surface, reflected_dir);
-                let reflected_color = self.render_ray(&reflected_ray, recursion_limit);
-                if reflected_color != BLACK {
-                    // dbg!(&ray);
-                    // dbg!(&object);
-                    // dbg!(&i);
-                    // dbg!(reflected_dir);
-                    // dbg!(reflected_color);
-                    color += object.material.reflectivity * reflected_color;
-                    // assert!(false);
-                }
+        let mut mesh = mesh::parse_obj(source.as_slice())
+            .unwrap_or_else(|e| panic!("failed to load {}: {}", path, e));
+        let target = match (subdivide_levels, subdivide_pixels) {
+            (_, Some(pixels)) => Some(subdivision::Target::EdgeLength(
+                pixels * camera.pixel_footprint(&mesh.bounds(), imgx),
+            )),
+            (Some(levels), None) => Some(subdivision::Target::Level(levels)),
+            (None, None) => None,
+        };
+        if let Some(target) = target {
+            mesh = subdivision::subdivide(&mesh, target);
+        }
+        let mesh = match &displacement {
+            Some(texture) => displacement::displace(
+                &mesh,
+                &displacement::Displacement {
+                    texture: texture.clone(),
+                    mode: if displace_vector {
+                        displacement::Mode::Vector {
+                            scale: displace_scale,
+                        }
+                    } else {
+                        displacement::Mode::Scalar {
+                            scale: displace_scale,
+                        }
+                    },
+                    edge_length: displace_edge,
+                },
+            ),
+            None => mesh.triangulate(),
+        };
+        if let Some(cache) = &cache {
+            if let Err(e) = cache.store_mesh(key, &mesh) {
+                eprintln!("failed to cache {}: {}", path, e);
             }
         }
-
-        color
+        models.push(Object::new(mesh, MODEL_MATERIAL));
     }
 
-    fn render(&self, path: &str) {
-        let camera_right = self.camera.ray.1.cross(&self.camera.up);
-        // println!("camera ray:{:?} right:{:?} up:{:?}", &camera_ray, &camera_right, &camera_up);
-
-        let camera_w_fov_radians: f64 = self.camera.w_fov_degrees.to_radians();
-        let caemra_h_fov_radians = camera_w_fov_radians * (self.imgy as f64) / (self.imgx as f64);
-
-        // Create a new ImgBuf with width: imgx and height: imgy
-        let mut imgbuf = image::ImageBuffer::new(self.imgx, self.imgy);
-
-        let center_x = self.imgx as f64 / 2.0;
-        let center_y = self.imgy as f64 / 2.0;
-
-        let aa = AntiAliasing::create(3);
-
-        // Iterate over the coordinates and pixels of the image
-        let mut pixels: Vec<_> = imgbuf.enumerate_pixels_mut().collect();
-        pixels //.iter_mut()
-            // .par_iter_mut()
-            .iter_mut()
-            .for_each(|(x, y, pixel)| {
-                let mut color = BLACK;
-
-                let mut count = 0;
-                for (xx, yy) in aa.offsets() {
-                    let x = xx + *x as f64;
-                    let y = yy + *y as f64;
-                    let radians_x = (x - center_x) / (self.imgx as f64) * camera_w_fov_radians;
-                    let radians_y = (center_y - y) / (self.imgy as f64) * caemra_h_fov_radians;
-                    let pixel_dir = self.camera.ray.1 .0
-                        + camera_right.0 * radians_x
-                        + self.camera.up.0 * radians_y;
-                    let pixel_dir = Direction(pixel_dir.normalized());
-                    let pixel_ray = Ray(self.camera.ray.0, pixel_dir);
-
-                    color += self.render_ray(&pixel_ray, 1);
-                    count += 1;
-                }
-
-                color *= 1.0 / count as f64;
-
-                **pixel = image::Rgb(color.to_rgb());
-            });
-
-        imgbuf.save(path).unwrap();
-    }
-}
-
-fn main() {
-    let shapes: Vec<_> = vec![
-        Object {
-            shape: Shape::Sphere {
+    let spheres: Vec<_> = vec![
+        Object::new(
+            Sphere {
                 center: Point(Vec3([0.0, 0.0, 0.0])),
                 radius: 0.7,
             },
-            material: Material {
-                diffuse_color: BLUE,
-                specular_color: 1.0 * WHITE,
-                shininess: 50.0,
-                reflectivity: 0.0,
-            },
-        },
-        Object {
-            shape: Shape::Sphere {
+            Material::opaque(BLUE),
+        ),
+        Object::new(
+            Sphere {
                 center: Point(Vec3([-0.7, 0.7, -1.0])),
                 radius: 1.0,
             },
-            material: Material {
-                diffuse_color: RED,
+            Material {
                 specular_color: 0.0 * WHITE,
-                shininess: 50.0,
-                reflectivity: 0.0,
+                ..Material::opaque(RED)
             },
-        },
-        Object {
-            shape: Shape::Sphere {
+        ),
+        Object::new(
+            Sphere {
                 center: Point(Vec3([1.0, -1.0, 1.0])),
                 radius: 0.5,
             },
-            material: Material {
-                diffuse_color: GREEN,
+            Material {
                 specular_color: 0.5 * WHITE,
-                shininess: 50.0,
-                reflectivity: 0.0,
+                ..Material::opaque(GREEN)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Sphere {
+                center: Point(Vec3([-2.2, 0.6, 0.6])),
+                radius: 0.6,
+            },
+            Material {
+                transparency: 1.0,
+                refractive_index: 1.5,
+                ..Material::opaque(BLACK)
+            },
+        ),
+        Object::new(
+            Sphere {
+                center: Point(Vec3([0.8, 1.6, -0.6])),
+                radius: 0.5,
+            },
+            Material {
+                conductor: Some(optics::Conductor::GOLD),
+                ..Material::opaque(BLACK)
+            },
+        ),
+    ];
+
+    let mut shapes: Vec<_> = vec![
+        Object::new(
+            Plane {
                 point: Point(Vec3([0.0, 0.0, -10.0])),
                 normal: Direction(Vec3([0.0, 0.0, 1.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: BLACK,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Plane {
                 point: Point(Vec3([0.0, 0.0, 10.0])),
                 normal: Direction(Vec3([0.0, 0.0, -1.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: 0.1 * WHITE,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Plane {
                 point: Point(Vec3([10.0, 0.0, 0.0])),
                 normal: Direction(Vec3([-1.0, 0.0, 0.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: 0.1 * WHITE,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Plane {
                 point: Point(Vec3([-10.0, 0.0, 0.0])),
                 normal: Direction(Vec3([1.0, 0.0, 0.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: 0.1 * WHITE,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Plane {
                 point: Point(Vec3([0.0, 10.0, 0.0])),
                 normal: Direction(Vec3([0.0, -1.0, 0.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: 0.1 * WHITE,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
-        Object {
-            shape: Shape::Plane {
+        ),
+        Object::new(
+            Plane {
                 point: Point(Vec3([0.0, -10.0, 0.0])),
                 normal: Direction(Vec3([0.0, 1.0, 0.0])),
             },
-            material: Material {
-                diffuse_color: 0.1 * WHITE,
+            Material {
                 specular_color: 0.5 * WHITE,
-                shininess: 50.0,
                 reflectivity: 0.7,
+                ..Material::opaque(0.1 * WHITE)
             },
-        },
+        ),
     ];
 
+    // the metal's own reflection gives it its color
+    if metal.is_some() {
+        for model in &mut models {
+            model.material = Material {
+                conductor: metal,
+                ..Material::opaque(BLACK)
+            };
+        }
+    }
+
+    // loaded models replace the demo spheres
+    if models.is_empty() {
+        shapes.extend(spheres);
+    } else {
+        shapes.extend(models);
+    }
+
     let lights = vec![
         Light {
             point: Point(Vec3([-2.0, 1.0, 0.7])),
@@ -406,16 +490,16 @@ fn main() {
     ];
 
     let scene = Scene {
-        camera: Camera {
-            ray: Ray::from_points(Point(Vec3([-4.9, 3.0, 3.0])), Point(Vec3([0.0, 0.0, 0.0]))),
-            up: Direction(Vec3([0.0, 0.0, 1.0])),
-            w_fov_degrees: 90.0,
-        },
-        imgx: 800,
-        imgy: 800,
+        camera,
+        imgx,
+        imgy,
         objects: shapes,
         lights,
+        accelerator,
+        renderer,
+        depth,
     };
 
-    scene.render("out.png");
+    let stats = scene.render(&output);
+    println!("{}", stats);
 }
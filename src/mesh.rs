@@ -0,0 +1,595 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::accel::Traversal;
+use crate::bvh::MAX_LEAF;
+use crate::cache::{Array, Reader, Writer};
+use crate::simd::Triangles4;
+use crate::wide::WideBvh;
+use crate::*;
+
+/// An edge whose neighbourhood keeps the sharp subdivision rules for
+/// `sharpness` levels; `Float::INFINITY` keeps it sharp forever.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crease {
+    pub vertices: [usize; 2],
+    pub sharpness: Float,
+}
+
+/// Polygons as authored, before triangulation. This is what subdivision works on.
+#[derive(Clone, Debug, Default)]
+pub struct PolygonMesh {
+    pub vertices: Vec<Point>,
+    pub faces: Vec<Vec<usize>>,
+    pub creases: Vec<Crease>,
+    /// Texture coordinates, one per vertex, or empty.
+    pub uvs: Vec<(Float, Float)>,
+}
+
+/// Triangle mesh with smooth vertex normals, ready for intersection.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    vertices: Array<Point>,
+    normals: Array<Direction>,
+    /// Texture coordinates, one per vertex, or empty.
+    uvs: Array<[Float; 2]>,
+    triangles: Array<[u32; 3]>,
+    /// Running total of triangle areas, for picking triangles by area.
+    area_sums: Vec<Float>,
+    bounds: Aabb,
+    bvh: WideBvh<4>,
+    /// The triangles of each leaf of `bvh`, to be tested together.
+    packets: Vec<Triangles4>,
+    /// Which triangle is in each lane of each packet.
+    packet_triangles: Vec<[usize; 4]>,
+}
+
+// every leaf fits in one packet
+const _: () = assert!(MAX_LEAF <= 4);
+
+impl PolygonMesh {
+    pub fn is_triangles(&self) -> bool {
+        self.faces.iter().all(|f| f.len() == 3)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for p in &self.vertices {
+            bounds.grow(p);
+        }
+        bounds
+    }
+
+    pub fn max_edge_length(&self) -> Float {
+        self.faces
+            .iter()
+            .flat_map(|f| (0..f.len()).map(move |i| (f[i], f[(i + 1) % f.len()])))
+            .map(|(a, b)| (self.vertices[a] - self.vertices[b]).0.magnitude())
+            .fold(0.0, Float::max)
+    }
+
+    /// Fan triangulation of every face.
+    pub fn triangles(&self) -> Vec<[usize; 3]> {
+        let mut triangles = Vec::new();
+        for f in &self.faces {
+            for i in 1..f.len() - 1 {
+                triangles.push([f[0], f[i], f[i + 1]]);
+            }
+        }
+        triangles
+    }
+
+    pub fn triangulate(&self) -> Mesh {
+        Mesh::new(self.vertices.clone(), self.uvs.clone(), self.triangles())
+    }
+}
+
+/// Area-weighted average of the face normals around each vertex.
+pub fn vertex_normals(vertices: &[Point], triangles: &[[usize; 3]]) -> Vec<Direction> {
+    let mut normals = vec![Direction::none(); vertices.len()];
+    for t in triangles {
+        // area weighted, since the cross product is not normalized
+        let n = (vertices[t[1]] - vertices[t[0]]).cross(&(vertices[t[2]] - vertices[t[0]]));
+        for v in t {
+            normals[*v] = normals[*v] + n;
+        }
+    }
+    for n in normals.iter_mut() {
+        if n.0.magnitude() > 0.0 {
+            n.normalize();
+        }
+    }
+    normals
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Point>, uvs: Vec<(Float, Float)>, triangles: Vec<[usize; 3]>) -> Mesh {
+        let normals = vertex_normals(&vertices, &triangles);
+        Mesh::with_normals(vertices, normals, uvs, triangles)
+    }
+
+    /// Builds a mesh whose vertex normals are already known, e.g. from the
+    /// surface it was tessellated from. They should point the way the
+    /// triangles wind. Vertices are numbered in 32 bits, so there can be
+    /// no more than `u32::MAX` of them.
+    pub fn with_normals(
+        vertices: Vec<Point>,
+        normals: Vec<Direction>,
+        uvs: Vec<(Float, Float)>,
+        triangles: Vec<[usize; 3]>,
+    ) -> Mesh {
+        let triangle_bounds: Vec<Aabb> = triangles
+            .iter()
+            .map(|t| {
+                let mut b = Aabb::empty();
+                for i in t {
+                    b.grow(&vertices[*i]);
+                }
+                b
+            })
+            .collect();
+        let bvh = WideBvh::new(&triangle_bounds);
+        let index = |i: usize| u32::try_from(i).expect("too many vertices for 32 bit indices");
+        let triangles: Vec<_> = triangles.iter().map(|t| t.map(index)).collect();
+        let uvs: Vec<_> = uvs.into_iter().map(|(u, v)| [u, v]).collect();
+        Mesh::assemble(
+            vertices.into(),
+            normals.into(),
+            uvs.into(),
+            triangles.into(),
+            bvh,
+        )
+    }
+
+    /// Adds what is quickly worked out from the rest: areas, bounds, and
+    /// the triangles of each leaf packed together.
+    fn assemble(
+        vertices: Array<Point>,
+        normals: Array<Direction>,
+        uvs: Array<[Float; 2]>,
+        triangles: Array<[u32; 3]>,
+        bvh: WideBvh<4>,
+    ) -> Mesh {
+        let area_sums = triangles
+            .iter()
+            .scan(0.0, |sum, t| {
+                let [p0, p1, p2] = t.map(|i| vertices[i as usize]);
+                let n = (p1 - p0).cross(&(p2 - p0));
+                *sum += 0.5 * n.0.magnitude();
+                Some(*sum)
+            })
+            .collect();
+
+        let mut bounds = Aabb::empty();
+        for p in vertices.iter() {
+            bounds.grow(p);
+        }
+
+        let leaves = (0..bvh.leaf_count()).map(|l| bvh.leaf(l));
+        let packets = leaves
+            .clone()
+            .map(|leaf| {
+                Triangles4::new(
+                    leaf.iter()
+                        .map(|t| triangles[*t as usize].map(|i| vertices[i as usize])),
+                )
+            })
+            .collect();
+        let packet_triangles = leaves
+            .map(|leaf| {
+                let mut lanes = [usize::MAX; 4];
+                for (lane, t) in lanes.iter_mut().zip(leaf) {
+                    *lane = *t as usize;
+                }
+                lanes
+            })
+            .collect();
+
+        Mesh {
+            vertices,
+            normals,
+            uvs,
+            triangles,
+            area_sums,
+            bounds,
+            bvh,
+            packets,
+            packet_triangles,
+        }
+    }
+
+    /// Writes the mesh and its tree for the cache, leaving out what
+    /// `assemble` works out again.
+    pub(crate) fn write(&self, w: &mut Writer) {
+        w.array(&self.vertices);
+        w.array(&self.normals);
+        w.array(&self.uvs);
+        w.array(&self.triangles);
+        self.bvh.write(w);
+    }
+
+    /// Reads back what `write` wrote, using the lists where they are in the
+    /// file once the indices in them are checked.
+    pub(crate) fn read(r: &mut Reader) -> io::Result<Mesh> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        let vertices: Array<Point> = r.array()?;
+        let normals: Array<Direction> = r.array()?;
+        if normals.len() != vertices.len() {
+            return Err(invalid("normals for some vertices only"));
+        }
+        let uvs: Array<[Float; 2]> = r.array()?;
+        if !uvs.is_empty() && uvs.len() != vertices.len() {
+            return Err(invalid("texture coordinates for some vertices only"));
+        }
+        let triangles: Array<[u32; 3]> = r.array()?;
+        if triangles
+            .iter()
+            .flatten()
+            .any(|i| *i as usize >= vertices.len())
+        {
+            return Err(invalid("index out of range"));
+        }
+        let bvh = WideBvh::read(r, triangles.len())?;
+        Ok(Mesh::assemble(vertices, normals, uvs, triangles, bvh))
+    }
+
+    /// Whether the lists that can be are used in place in a cache file.
+    #[cfg(test)]
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.vertices.is_mapped()
+            && self.normals.is_mapped()
+            && self.uvs.is_mapped()
+            && self.triangles.is_mapped()
+            && self.bvh.is_mapped()
+    }
+
+    /// Interpolated normal at barycentrics `(u, v)`, falling back to the face
+    /// normal where the vertex normals cancel out.
+    fn normal(&self, t: &[usize; 3], u: Float, v: Float) -> Direction {
+        let n =
+            (1.0 - u - v) * self.normals[t[0]] + u * self.normals[t[1]] + v * self.normals[t[2]];
+        if n.0.magnitude() > 0.0 {
+            n.normalized()
+        } else {
+            (self.vertices[t[1]] - self.vertices[t[0]])
+                .cross(&(self.vertices[t[2]] - self.vertices[t[0]]))
+                .normalized()
+        }
+    }
+}
+
+impl Shape for Mesh {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let mut closest = None;
+        self.bvh.closest_leaf(
+            r,
+            Float::INFINITY,
+            &mut Traversal::default(),
+            |leaf, max| {
+                let (lane, distance, u, v) = self.packets[leaf].intersect(r, max)?;
+                closest = Some((distance, u, v, self.packet_triangles[leaf][lane]));
+                Some(distance)
+            },
+        );
+
+        closest.map(|(distance, u, v, index)| {
+            let t = &self.triangles[index].map(|i| i as usize);
+            let [p0, p1, p2] = t.map(|i| self.vertices[i]);
+            let geometric_normal = (p1 - p0).cross(&(p2 - p0)).normalized();
+            // winding in imported files is not always consistent with the
+            // vertex normals, so keep the shading normal on the same side
+            let mut surface_normal = self.normal(t, u, v);
+            if surface_normal.dot(&geometric_normal) < 0.0 {
+                surface_normal = -1.0 * surface_normal;
+            }
+
+            // interpolating the corners is more accurate than stepping along the ray
+            let w = 1.0 - u - v;
+            let terms = [w * p0.0, u * p1.0, v * p2.0];
+            let point = Point(terms[0] + terms[1] + terms[2]);
+            let error = gamma(7) * (terms[0].abs() + terms[1].abs() + terms[2].abs());
+
+            // without texture coordinates, the barycentrics within the triangle
+            let (uv, dpdu, dpdv) = if self.uvs.is_empty() {
+                ((u, v), p1 - p0, p2 - p0)
+            } else {
+                let [a, b, c] = t.map(|i| self.uvs[i]);
+                let uv = (
+                    w * a[0] + u * b[0] + v * c[0],
+                    w * a[1] + u * b[1] + v * c[1],
+                );
+                // solve p - p0 = (uv - uv0) * [dpdu dpdv] over the two edges
+                let (du1, dv1) = (b[0] - a[0], b[1] - a[1]);
+                let (du2, dv2) = (c[0] - a[0], c[1] - a[1]);
+                let det = du1 * dv2 - dv1 * du2;
+                let (dpdu, dpdv) = if det.abs() > 1e-12 {
+                    let (e1, e2) = (p1 - p0, p2 - p0);
+                    (
+                        (1.0 / det) * (dv2 * e1 - dv1 * e2),
+                        (1.0 / det) * (du1 * e2 - du2 * e1),
+                    )
+                } else {
+                    // every corner has the same uv, so any frame will do
+                    geometric_normal.orthonormal_basis()
+                };
+                (uv, dpdu, dpdv)
+            };
+
+            Intersection {
+                distance,
+                point,
+                surface_normal,
+                geometric_normal,
+                front_face: r.1.dot(&geometric_normal) < 0.0,
+                tangent: None,
+                uv,
+                dpdu,
+                dpdv,
+                primitive: index,
+                object: 0,
+                error,
+            }
+        })
+    }
+
+    fn occluded(&self, r: &Ray, max_distance: Float) -> bool {
+        self.bvh
+            .any_leaf(r, max_distance, &mut Traversal::default(), |leaf| {
+                self.packets[leaf].intersect(r, max_distance).is_some()
+            })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    /// Picks a triangle in proportion to its area with `s.0`, reusing what
+    /// is left of it together with `s.1` to pick a point inside.
+    fn sample_surface(&self, s: (Float, Float)) -> Option<(Point, Direction)> {
+        let total = *self.area_sums.last()?;
+        if total <= 0.0 {
+            return None;
+        }
+        let target = s.0 * total;
+        let i = self
+            .area_sums
+            .partition_point(|sum| *sum < target)
+            .min(self.triangles.len() - 1);
+        let start = if i == 0 { 0.0 } else { self.area_sums[i - 1] };
+        let area = self.area_sums[i] - start;
+        let s0 = if area > 0.0 {
+            ((target - start) / area).clamp(0.0, 1.0)
+        } else {
+            0.5
+        };
+
+        // uniform on the triangle by folding the square along its diagonal
+        let root = s0.sqrt();
+        let (u, v) = (root * (1.0 - s.1), root * s.1);
+        let t = &self.triangles[i].map(|i| i as usize);
+        let p0 = self.vertices[t[0]];
+        let point = p0 + u * (self.vertices[t[1]] - p0) + v * (self.vertices[t[2]] - p0);
+        Some((point, self.normal(t, u, v)))
+    }
+}
+
+/// Parses the geometry of a Wavefront OBJ file: `v` positions, `vt` texture
+/// coordinates, `f` faces of any size (normal indices are ignored), and
+/// `t crease 2/1 a b s` edge tags. Everything else is skipped.
+pub fn parse_obj(reader: impl Read) -> io::Result<PolygonMesh> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut mesh = PolygonMesh::default();
+    let mut texcoords = Vec::new();
+    let mut vertex_uvs: Vec<Option<(Float, Float)>> = Vec::new();
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+        let mut tokens = line.split_whitespace();
+        let number = |t: &str| {
+            t.parse::<Float>()
+                .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+        };
+        let vertex_count = mesh.vertices.len();
+        // 1-based, or negative to count back from the latest entry
+        let index = |t: &str, count: usize| {
+            let i = t
+                .parse::<i64>()
+                .map_err(|e| invalid(line_number, format!("bad index '{}': {}", t, e)))?;
+            let i = if i < 0 { count as i64 + i } else { i - 1 };
+            if i < 0 || i >= count as i64 {
+                return Err(invalid(line_number, format!("index '{}' out of range", t)));
+            }
+            Ok(i as usize)
+        };
+
+        match tokens.next() {
+            Some("v") => {
+                let coordinates = tokens.take(3).map(number).collect::<io::Result<Vec<_>>>()?;
+                if coordinates.len() != 3 {
+                    return Err(invalid(
+                        line_number,
+                        "expected three coordinates".to_owned(),
+                    ));
+                }
+                mesh.vertices.push(Point(Vec3([
+                    coordinates[0],
+                    coordinates[1],
+                    coordinates[2],
+                ])));
+            }
+            Some("vt") => {
+                let uv = tokens.take(2).map(number).collect::<io::Result<Vec<_>>>()?;
+                if uv.len() != 2 {
+                    return Err(invalid(line_number, "expected two coordinates".to_owned()));
+                }
+                texcoords.push((uv[0], uv[1]));
+            }
+            Some("f") => {
+                let mut face = Vec::new();
+                for t in tokens {
+                    let mut parts = t.split('/');
+                    let v = index(parts.next().unwrap(), vertex_count)?;
+                    if let Some(vt) = parts.next().filter(|vt| !vt.is_empty()) {
+                        let vt = index(vt, texcoords.len())?;
+                        vertex_uvs.resize(vertex_count, None);
+                        vertex_uvs[v].get_or_insert(texcoords[vt]);
+                    }
+                    face.push(v);
+                }
+                if face.len() < 3 {
+                    return Err(invalid(line_number, "faces need three vertices".to_owned()));
+                }
+                mesh.faces.push(face);
+            }
+            Some("t") if tokens.next() == Some("crease") => {
+                let args: Vec<_> = tokens.collect();
+                if args.len() != 4 || args[0] != "2/1" {
+                    return Err(invalid(
+                        line_number,
+                        "expected 't crease 2/1 a b s'".to_owned(),
+                    ));
+                }
+                // crease tags use 0-based vertex indices
+                let vertex = |t: &str| match t.parse::<usize>() {
+                    Ok(i) if i < vertex_count => Ok(i),
+                    _ => Err(invalid(line_number, format!("bad crease vertex '{}'", t))),
+                };
+                mesh.creases.push(Crease {
+                    vertices: [vertex(args[1])?, vertex(args[2])?],
+                    sharpness: number(args[3])?,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    if !texcoords.is_empty() {
+        // a vertex takes the texture coordinates of the first face corner that names it
+        vertex_uvs.resize(mesh.vertices.len(), None);
+        mesh.uvs = vertex_uvs
+            .into_iter()
+            .map(|uv| uv.unwrap_or((0.0, 0.0)))
+            .collect();
+    }
+
+    Ok(mesh)
+}
+
+pub fn load_obj(path: &Path) -> io::Result<PolygonMesh> {
+    parse_obj(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const QUAD: &str = "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nf 1 2 3 -1\n";
+
+    #[test]
+    fn parse() {
+        let mesh = parse_obj(QUAD.as_bytes()).unwrap();
+        assert_eq!(4, mesh.vertices.len());
+        assert_eq!(vec![vec![0, 1, 2, 3]], mesh.faces);
+        assert!(!mesh.is_triangles());
+
+        let creased = parse_obj(format!("{}t crease 2/1 0 1 2.0\n", QUAD).as_bytes()).unwrap();
+        assert_eq!(
+            vec![Crease {
+                vertices: [0, 1],
+                sharpness: 2.0
+            }],
+            creased.creases
+        );
+
+        let textured = parse_obj(
+            "v 0 0 0\nv 2 0 0\nv 2 2 0\nvt 0 0\nvt 1 0\nvt 1 1\nf 1/1/1 2/2/2 3/-1/3\n".as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0)], textured.uvs);
+        assert!(mesh.uvs.is_empty());
+
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n".as_bytes()).is_err());
+        assert!(parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1/1 2/1 3/1\n".as_bytes()).is_err());
+        assert!(parse_obj("v 0 0\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn intersect() {
+        let mesh = parse_obj(QUAD.as_bytes()).unwrap().triangulate();
+        let i = mesh
+            .find_intersection(&Ray(
+                Point(Vec3([1.5, 0.5, 3.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 3.0).abs() < 1e-9);
+        assert!((i.surface_normal.0 .0[2] - 1.0).abs() < 1e-9);
+
+        let i = mesh
+            .find_intersection(&Ray(
+                Point(Vec3([0.5, 1.5, -3.0])),
+                Direction(Vec3([0.0, 0.0, 1.0])),
+            ))
+            .unwrap();
+        assert!((i.surface_normal.0 .0[2] - 1.0).abs() < 1e-9);
+        assert!(!i.front_face);
+        assert!((i.facing_normal().0 .0[2] + 1.0).abs() < 1e-9);
+        assert_eq!(1, i.primitive);
+
+        assert!(mesh
+            .find_intersection(&Ray(
+                Point(Vec3([2.5, 0.5, 3.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .is_none());
+
+        let down = Ray(
+            Point(Vec3([1.5, 0.5, 3.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+        assert!(mesh.occluded(&down, 3.5));
+        assert!(!mesh.occluded(&down, 2.5));
+    }
+
+    #[test]
+    fn shape() {
+        let quad = parse_obj(
+            "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n"
+                .as_bytes(),
+        )
+        .unwrap()
+        .triangulate();
+        let i = quad
+            .find_intersection(&Ray(
+                Point(Vec3([1.5, 0.5, 3.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.uv.0 - 0.75).abs() < 1e-9);
+        assert!((i.uv.1 - 0.25).abs() < 1e-9);
+        // the texture is stretched over twice its size
+        assert!((i.dpdu - Direction(Vec3([2.0, 0.0, 0.0]))).0.magnitude() < 1e-9);
+        assert!((i.dpdv - Direction(Vec3([0.0, 2.0, 0.0]))).0.magnitude() < 1e-9);
+
+        let bounds = quad.bounds().unwrap();
+        assert_eq!(Point(Vec3([2.0, 2.0, 0.0])), bounds.max);
+
+        // both triangles have the same area, so half the samples land in each
+        let (p, n) = quad.sample_surface((0.25, 0.5)).unwrap();
+        assert!(p.0 .0[0] > p.0 .0[1]);
+        assert!((n.0 .0[2] - 1.0).abs() < 1e-9);
+        let (p, _) = quad.sample_surface((0.75, 0.5)).unwrap();
+        assert!(p.0 .0[0] < p.0 .0[1]);
+        for s in [(0.0, 0.0), (1.0, 1.0), (0.5, 0.3)] {
+            let (p, _) = quad.sample_surface(s).unwrap();
+            assert!(p.0 .0.iter().all(|c| (0.0..=2.0).contains(c)));
+        }
+    }
+}
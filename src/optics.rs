@@ -0,0 +1,255 @@
+use std::str::FromStr;
+
+use crate::*;
+
+/// Fraction of unpolarized light reflected where it meets a smooth boundary
+/// between dielectrics at an angle with cosine `cos_i` to the normal, coming
+/// from the side with `eta` times the index of refraction of the other. The
+/// rest is transmitted; all of it is reflected past the critical angle.
+/// These are the exact Fresnel equations, averaged over both polarizations.
+pub fn fresnel_dielectric(cos_i: Float, eta: Float) -> Float {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let perpendicular = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let parallel = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (perpendicular * perpendicular + parallel * parallel)
+}
+
+/// Fraction of unpolarized light reflected off a smooth metal, in air, at an
+/// angle with cosine `cos_i` to the normal, where the metal has the complex
+/// index of refraction `n + ik`: the Fresnel equations for a conductor,
+/// averaged over both polarizations. With no `k` it is `fresnel_dielectric`.
+pub fn fresnel_conductor(cos_i: Float, n: Float, k: Float) -> Float {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let t0 = n * n - k * k - sin2;
+    let a2b2 = (t0 * t0 + 4.0 * n * n * k * k).sqrt();
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t1 = a2b2 + cos2;
+    let t2 = 2.0 * a * cos_i;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (perpendicular + parallel)
+}
+
+/// A metal, by its complex index of refraction `n + ik` at the red, green
+/// and blue wavelengths, of about 650, 550 and 450 nm.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Conductor {
+    pub n: Color,
+    pub k: Color,
+}
+
+impl Conductor {
+    pub const GOLD: Self = Self {
+        n: Color(0.18299, 0.42108, 1.3734),
+        k: Color(3.4242, 2.3459, 1.7704),
+    };
+    pub const SILVER: Self = Self {
+        n: Color(0.15943, 0.14512, 0.13547),
+        k: Color(3.9291, 3.19, 2.3808),
+    };
+    pub const COPPER: Self = Self {
+        n: Color(0.27105, 0.67693, 1.3164),
+        k: Color(3.6092, 2.6248, 2.2921),
+    };
+    pub const ALUMINIUM: Self = Self {
+        n: Color(1.3456, 0.96521, 0.61722),
+        k: Color(7.4746, 6.3995, 5.3031),
+    };
+    pub const CHROMIUM: Self = Self {
+        n: Color(3.1071, 3.1812, 2.323),
+        k: Color(3.3314, 3.3291, 3.135),
+    };
+
+    /// Share of each channel reflected at an angle with cosine `cos_i` to
+    /// the normal.
+    pub fn reflectance(&self, cos_i: Float) -> Color {
+        Color(
+            fresnel_conductor(cos_i, self.n.0, self.k.0),
+            fresnel_conductor(cos_i, self.n.1, self.k.1),
+            fresnel_conductor(cos_i, self.n.2, self.k.2),
+        )
+    }
+}
+
+impl FromStr for Conductor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Conductor, String> {
+        match s {
+            "gold" => Ok(Conductor::GOLD),
+            "silver" => Ok(Conductor::SILVER),
+            "copper" => Ok(Conductor::COPPER),
+            "aluminium" => Ok(Conductor::ALUMINIUM),
+            "chromium" => Ok(Conductor::CHROMIUM),
+            _ => Err(format!(
+                "unknown metal '{}': expected gold, silver, copper, aluminium or chromium",
+                s
+            )),
+        }
+    }
+}
+
+/// Color light is left with after `distance` through material that absorbs
+/// `absorption` of each channel per unit distance, by the Beer–Lambert law.
+pub fn transmittance(absorption: Color, distance: Float) -> Color {
+    Color(
+        (-absorption.0 * distance).exp(),
+        (-absorption.1 * distance).exp(),
+        (-absorption.2 * distance).exp(),
+    )
+}
+
+/// The absorption that leaves light with `transmittance` after `distance`
+/// through the material: the other way around from `transmittance`. A
+/// channel that is left with nothing has an infinite absorption.
+pub fn absorption(transmittance: Color, distance: Float) -> Color {
+    Color(
+        -transmittance.0.ln() / distance,
+        -transmittance.1.ln() / distance,
+        -transmittance.2.ln() / distance,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Schlick's approximation to `fresnel_dielectric`, with the same
+    /// arguments, which it should stay close to.
+    fn fresnel_schlick(cos_i: Float, eta: Float) -> Float {
+        let r0 = ((eta - 1.0) / (eta + 1.0)).powi(2);
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        // from the denser side the curve follows the angle outside
+        let cos = if eta > 1.0 {
+            let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+            if sin2_t >= 1.0 {
+                return 1.0;
+            }
+            (1.0 - sin2_t).sqrt()
+        } else {
+            cos_i
+        };
+        r0 + (1.0 - r0) * (1.0 - cos).powi(5)
+    }
+
+    #[test]
+    fn dielectric() {
+        let tolerance = per_precision(1e-6, 1e-3);
+        for n in [1.33, 1.5, 2.4] {
+            // head on, from either side
+            let r0 = ((n - 1.0) / (n + 1.0)) * ((n - 1.0) / (n + 1.0));
+            assert!((fresnel_dielectric(1.0, 1.0 / n) - r0).abs() < tolerance);
+            assert!((fresnel_dielectric(1.0, n) - r0).abs() < tolerance);
+            // grazing, everything is reflected
+            assert!((fresnel_dielectric(0.0, 1.0 / n) - 1.0).abs() < tolerance);
+
+            let critical = (1.0 / n).asin().cos();
+            let mut last = 0.0;
+            for k in 0..=100 {
+                let cos = 1.0 - k as Float / 100.0;
+                // light takes the same share both ways through the boundary,
+                // short of grazing, where the way back is right at the
+                // critical angle
+                let inside = 1.0 - (1.0 - cos * cos) / (n * n);
+                let reflected = fresnel_dielectric(cos, 1.0 / n);
+                if k < 100 {
+                    assert!(
+                        (reflected - fresnel_dielectric(inside.sqrt(), n)).abs() < tolerance,
+                        "{} at {}",
+                        n,
+                        cos
+                    );
+                }
+                // rising toward the grazing angle, and all of it past the
+                // critical one from within
+                assert!(reflected >= last, "{} at {}", n, cos);
+                last = reflected;
+                let within = fresnel_dielectric(cos, n);
+                assert_eq!(
+                    cos < critical - tolerance,
+                    within == 1.0,
+                    "{} at {}",
+                    n,
+                    cos
+                );
+                // Schlick is close, if not for diamond
+                if n < 2.0 {
+                    assert!((fresnel_schlick(cos, 1.0 / n) - reflected).abs() < 0.07);
+                    assert!((fresnel_schlick(cos, n) - within).abs() < 0.07);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn absorbed() {
+        let tolerance = per_precision(1e-9, 1e-5);
+        let tinted = absorption(Color(0.5, 0.9, 1.0), 2.0);
+        assert_eq!(0.0, tinted.2);
+        assert_eq!(WHITE, transmittance(tinted, 0.0));
+        // twice as far, the square
+        let t = transmittance(tinted, 4.0);
+        assert!((t.0 - 0.25).abs() < tolerance);
+        assert!((t.1 - 0.81).abs() < tolerance);
+        assert_eq!(1.0, t.2);
+        assert_eq!(0.0, transmittance(absorption(BLACK, 1.0), 0.1).0);
+    }
+
+    #[test]
+    fn conductor() {
+        let tolerance = per_precision(1e-6, 1e-4);
+        for (n, k) in [(0.2, 3.4), (1.3, 7.5), (3.1, 3.3)] {
+            // head on
+            let r0 = ((n - 1.0) * (n - 1.0) + k * k) / ((n + 1.0) * (n + 1.0) + k * k);
+            assert!((fresnel_conductor(1.0, n, k) - r0).abs() < tolerance);
+            // grazing, everything is reflected
+            assert!((fresnel_conductor(0.0, n, k) - 1.0).abs() < tolerance);
+        }
+        // without an imaginary part, a dielectric
+        for k in 0..=100 {
+            let cos = k as Float / 100.0;
+            let dielectric = fresnel_dielectric(cos, 1.0 / 1.5);
+            assert!((fresnel_conductor(cos, 1.5, 0.0) - dielectric).abs() < tolerance);
+        }
+
+        // every one of them reflects much of each channel head on
+        for metal in [
+            Conductor::GOLD,
+            Conductor::SILVER,
+            Conductor::COPPER,
+            Conductor::ALUMINIUM,
+            Conductor::CHROMIUM,
+        ] {
+            let c = metal.reflectance(1.0);
+            assert!(c.0.min(c.1).min(c.2) > 0.3, "{:?}", metal);
+        }
+        // gold and copper are warm, and whiter toward grazing angles
+        let spread = |c: Color| c.0.max(c.1).max(c.2) - c.0.min(c.1).min(c.2);
+        for metal in [Conductor::GOLD, Conductor::COPPER] {
+            let c = metal.reflectance(1.0);
+            assert!(c.0 > 0.9 && c.0 > c.1 && c.1 > c.2, "{:?}", metal);
+            assert!(spread(metal.reflectance(0.1)) < spread(c), "{:?}", metal);
+        }
+        // while silver and aluminium are nearly white
+        for metal in [Conductor::SILVER, Conductor::ALUMINIUM] {
+            let c = metal.reflectance(1.0);
+            assert!(c.0.min(c.1).min(c.2) > 0.9, "{:?}", metal);
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Ok(Conductor::GOLD), "gold".parse());
+        assert_eq!(Ok(Conductor::CHROMIUM), "chromium".parse());
+        assert!("brass".parse::<Conductor>().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::aabb::Aabb;
+use crate::bvh::slabs;
+use crate::*;
+
+use std::ops::Range;
+
+/// How much wider than the rays the frustum is made, so that rounding never
+/// culls a box that one of them grazes.
+const SLOPE_MARGIN: Float = per_precision(1e-7, 1e-5);
+
+/// Rays traced together through an acceleration structure, such as camera
+/// rays through neighbouring pixels. When they leave from one point within a
+/// narrow cone, whole subtrees can be culled against the frustum around them
+/// without looking at the rays one by one.
+#[derive(Clone, Debug)]
+pub struct Packet {
+    rays: Vec<Ray>,
+    /// Reciprocals of the ray directions, for slab tests.
+    inverses: Vec<[Float; 3]>,
+    frustum: Option<Frustum>,
+}
+
+/// Planes through a common origin, each with the rays on its inner side.
+#[derive(Clone, Copy, Debug)]
+struct Frustum {
+    origin: Point,
+    /// Outward normals of the four sides and of the plane behind the origin.
+    normals: [Direction; 5],
+}
+
+impl Packet {
+    pub fn new(rays: Vec<Ray>) -> Packet {
+        let frustum = Frustum::around(&rays);
+        let inverses = rays.iter().map(|r| r.1 .0 .0.map(|d| 1.0 / d)).collect();
+        Packet {
+            rays,
+            inverses,
+            frustum,
+        }
+    }
+
+    pub fn rays(&self) -> &[Ray] {
+        &self.rays
+    }
+
+    pub fn len(&self) -> usize {
+        self.rays.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rays.is_empty()
+    }
+
+    /// Whether the rays share an origin and are close enough in direction
+    /// for the packet to be culled as a whole.
+    pub fn is_coherent(&self) -> bool {
+        self.frustum.is_some()
+    }
+
+    /// Whether every ray certainly misses `bounds`. False says nothing.
+    pub fn misses(&self, bounds: &Aabb) -> bool {
+        let Some(frustum) = &self.frustum else {
+            return false;
+        };
+        frustum.normals.iter().any(|n| {
+            // the corner furthest inside this plane
+            let corner = Point(Vec3([0, 1, 2].map(|a| {
+                if n.0 .0[a] > 0.0 {
+                    bounds.min.0 .0[a]
+                } else {
+                    bounds.max.0 .0[a]
+                }
+            })));
+            n.dot(&(corner - frustum.origin)) > 0.0
+        })
+    }
+
+    /// Where ray `k` enters `bounds`, if it does so before `max`.
+    pub(crate) fn entry(&self, k: usize, bounds: &Aabb, max: Float) -> Option<Float> {
+        slabs(bounds, &self.rays[k].0 .0 .0, &self.inverses[k], max).map(|(t, _)| t)
+    }
+
+    /// The first and last of the rays in `range` that enter `bounds` before
+    /// their `max_distances` entries. Should the first ray of the range miss,
+    /// the frustum gets the chance to rule out the rest in one go.
+    pub(crate) fn active(
+        &self,
+        bounds: &Aabb,
+        range: Range<usize>,
+        max_distances: &[Float],
+    ) -> Option<Range<usize>> {
+        let enters = |k: &usize| self.entry(*k, bounds, max_distances[*k]).is_some();
+        let start = range.start;
+        if !enters(&start) && self.misses(bounds) {
+            return None;
+        }
+        let first = range.clone().find(enters)?;
+        let last = range.rev().find(enters)?;
+        Some(first..last + 1)
+    }
+}
+
+impl Frustum {
+    fn around(rays: &[Ray]) -> Option<Frustum> {
+        let origin = rays.first()?.0;
+        if rays.iter().any(|r| r.0 != origin) {
+            return None;
+        }
+        let mut sum = Vec3([0.0; 3]);
+        for r in rays {
+            sum += r.1 .0;
+        }
+        let axis = Direction(sum);
+        if axis.0.magnitude() < 1e-9 {
+            return None;
+        }
+        let axis = axis.normalized();
+        // wider than about 85 degrees off the axis, the slopes blow up
+        if rays.iter().any(|r| r.1.dot(&axis) < 0.1) {
+            return None;
+        }
+
+        // each ray's slope across the axis, in two directions
+        let (u, v) = axis.orthonormal_basis();
+        let mut range = [(Float::INFINITY, Float::NEG_INFINITY); 2];
+        for r in rays {
+            let along = r.1.dot(&axis);
+            for (side, (low, high)) in [u, v].iter().zip(range.iter_mut()) {
+                let slope = r.1.dot(side) / along;
+                *low = low.min(slope);
+                *high = high.max(slope);
+            }
+        }
+        let [(u_low, u_high), (v_low, v_high)] =
+            range.map(|(low, high)| (low - SLOPE_MARGIN, high + SLOPE_MARGIN));
+        Some(Frustum {
+            origin,
+            normals: [
+                u - u_high * axis,
+                u_low * axis - u,
+                v - v_high * axis,
+                v_low * axis - v,
+                -1.0 * axis,
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn culls_only_missed_boxes() {
+        let origin = Point(Vec3([0.5, -3.0, 0.2]));
+        let packet = Packet::new(
+            (0..16)
+                .map(|k| {
+                    let (x, y) = ((k % 4) as Float * 0.1, (k / 4) as Float * 0.1);
+                    Ray(
+                        origin,
+                        Direction(Vec3([x - 0.15, 1.0, y - 0.15])).normalized(),
+                    )
+                })
+                .collect(),
+        );
+        assert!(packet.is_coherent());
+        let mut culled = 0;
+        for i in 0..20 {
+            for j in 0..20 {
+                for k in 0..10 {
+                    let min = Point(Vec3([
+                        i as Float - 10.0,
+                        k as Float - 5.0,
+                        j as Float - 10.0,
+                    ]));
+                    let bounds = Aabb {
+                        min,
+                        max: min + Direction(Vec3([0.7; 3])),
+                    };
+                    let hit = packet.active(&bounds, 0..16, &[Float::INFINITY; 16]);
+                    let hit_by_some =
+                        (0..16).any(|r| packet.entry(r, &bounds, Float::INFINITY).is_some());
+                    assert_eq!(hit_by_some, hit.is_some());
+                    if packet.misses(&bounds) {
+                        assert!(!hit_by_some, "culled a box that is hit: {:?}", bounds);
+                        culled += 1;
+                    }
+                }
+            }
+        }
+        // most boxes are off to the side or behind
+        assert!(culled > 3000, "{}", culled);
+
+        // without a shared origin, nothing is culled
+        let mut rays = packet.rays.clone();
+        rays[3].0 = Point::origin();
+        let scattered = Packet::new(rays);
+        assert!(!scattered.is_coherent());
+        assert!(!scattered.misses(&Aabb {
+            min: Point(Vec3([100.0; 3])),
+            max: Point(Vec3([101.0; 3])),
+        }));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Infinite plane through `point`. `normal` picks which side is the front.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub point: Point,
+    pub normal: Direction,
+}
+
+impl Shape for Plane {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        // https://en.wikipedia.org/wiki/Line%E2%80%93plane_intersection#Algebraic_form
+        let p0 = self.point;
+        let n = &self.normal;
+        let l0 = r.0;
+        let l = r.1;
+
+        let l_dot_n = l.dot(n);
+
+        if l_dot_n == 0.0 {
+            return None;
+        }
+
+        let distance = (p0 - l0).dot(n) / l_dot_n;
+
+        if distance <= 0.0 {
+            return None;
+        }
+
+        let point = l0 + l * distance;
+        // put the point back on the plane; the error left is from doing so
+        let point = point - (point - p0).dot(n) * n;
+        // uv measures distance from `self.point` along two axes in the plane
+        let (u_axis, v_axis) = self.normal.orthonormal_basis();
+        let offset = point - p0;
+        Some(Intersection {
+            distance,
+            point,
+            surface_normal: self.normal,
+            geometric_normal: self.normal,
+            front_face: l_dot_n < 0.0,
+            tangent: None,
+            uv: (offset.dot(&u_axis), offset.dot(&v_axis)),
+            dpdu: u_axis,
+            dpdv: v_axis,
+            primitive: 0,
+            object: 0,
+            error: gamma(7) * (p0.0.abs() + point.0.abs()),
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intersect() {
+        let plane = Plane {
+            point: Point(Vec3([1.0, 2.0, 0.0])),
+            normal: Direction(Vec3([0.0, 0.0, 1.0])),
+        };
+        let i = plane
+            .find_intersection(&Ray(
+                Point(Vec3([1.0, 2.0, 3.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 3.0).abs() < 1e-9);
+        assert!(i.front_face);
+        assert_eq!((0.0, 0.0), i.uv);
+        assert!(i.dpdu.cross(&i.dpdv).dot(&plane.normal) > 0.999);
+
+        let i = plane
+            .find_intersection(&Ray(
+                Point(Vec3([3.0, 5.0, -1.0])),
+                Direction(Vec3([0.0, 0.0, 1.0])),
+            ))
+            .unwrap();
+        assert!(!i.front_face);
+        assert_eq!(plane.normal, i.geometric_normal);
+        assert!((i.facing_normal().0 .0[2] + 1.0).abs() < 1e-9);
+        // uv moves with the point along the plane's axes
+        let offset = i.uv.0 * i.dpdu + i.uv.1 * i.dpdv;
+        assert!(
+            (Point(Vec3([1.0, 2.0, 0.0])) + offset - i.point)
+                .0
+                .magnitude()
+                < 1e-9
+        );
+    }
+}
@@ -0,0 +1,797 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use smallvec::SmallVec;
+
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Most points in a leaf of the tree.
+const LEAF_SIZE: usize = 8;
+
+/// How each point is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Splat {
+    Sphere,
+    /// Flat disk across the point's normal, or facing the ray without one.
+    Disk,
+}
+
+/// Points as read from a file. `colors` and `normals` are either empty or
+/// have one entry per position.
+#[derive(Clone, Debug, Default)]
+pub struct Points {
+    pub positions: Vec<Point>,
+    pub colors: Vec<Color>,
+    pub normals: Vec<Direction>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum NodeKind {
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+    /// The left child follows its parent; the right one is elsewhere.
+    Interior {
+        right: usize,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+/// Points drawn as spheres or disks of one radius, found through a bounding
+/// volume tree split at the median. Hits report the point's index as their
+/// `primitive`, in the order the points were given.
+#[derive(Clone, Debug)]
+pub struct PointCloud {
+    pub radius: Float,
+    pub splat: Splat,
+    // in tree order, so each leaf is a contiguous run
+    positions: Vec<Point>,
+    normals: Vec<Direction>,
+    /// Original index of each point in tree order.
+    indices: Vec<usize>,
+    // in the order given, as hits report
+    colors: Vec<Color>,
+    nodes: Vec<Node>,
+}
+
+impl PointCloud {
+    /// Fails unless `points` has no colors or one for every position, and
+    /// likewise normals.
+    pub fn new(points: Points, radius: Float, splat: Splat) -> io::Result<PointCloud> {
+        let n = points.positions.len();
+        for (what, count) in [
+            ("colors", points.colors.len()),
+            ("normals", points.normals.len()),
+        ] {
+            if count != 0 && count != n {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} {} for {} points", count, what, n),
+                ));
+            }
+        }
+        let mut order: Vec<usize> = (0..points.positions.len()).collect();
+        let mut nodes = Vec::with_capacity(2 * order.len() / LEAF_SIZE + 1);
+        if !order.is_empty() {
+            build(&mut nodes, &points.positions, &mut order, 0, radius);
+        }
+        Ok(PointCloud {
+            radius,
+            splat,
+            positions: order.iter().map(|i| points.positions[*i]).collect(),
+            normals: order
+                .iter()
+                .filter_map(|i| points.normals.get(*i).copied())
+                .collect(),
+            indices: order,
+            colors: points.colors,
+            nodes,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Hit with the `i`th point in tree order.
+    fn intersect_point(&self, i: usize, r: &Ray) -> Option<Intersection> {
+        let center = self.positions[i];
+        let normal = self.normals.get(i).copied();
+        let hit = match self.splat {
+            Splat::Sphere => {
+                let mut hit = sphere::find_intersection(center, self.radius, r)?;
+                // scanned normals describe the surface better than the ball does
+                if let Some(n) = normal {
+                    hit.surface_normal = if n.dot(&hit.geometric_normal) < 0.0 {
+                        -1.0 * n
+                    } else {
+                        n
+                    };
+                }
+                hit
+            }
+            Splat::Disk => {
+                let normal = normal.unwrap_or(-1.0 * r.1);
+                let denominator = r.1.dot(&normal);
+                if denominator == 0.0 {
+                    return None;
+                }
+                let distance = (center - r.0).dot(&normal) / denominator;
+                if distance < 0.0 {
+                    return None;
+                }
+                let point = r.0 + distance * r.1;
+                // snap onto the disk, so the error bound only has to cover this
+                let point = point - (point - center).dot(&normal) * normal;
+                let offset = point - center;
+                if offset.dot(&offset) > self.radius * self.radius {
+                    return None;
+                }
+                let (x, y) = normal.orthonormal_basis();
+                let local = |axis: Direction| 0.5 + 0.5 * offset.dot(&axis) / self.radius;
+                Intersection {
+                    distance,
+                    point,
+                    surface_normal: normal,
+                    geometric_normal: normal,
+                    front_face: denominator < 0.0,
+                    tangent: None,
+                    uv: (local(x), local(y)),
+                    dpdu: 2.0 * self.radius * x,
+                    dpdv: 2.0 * self.radius * y,
+                    primitive: 0,
+                    object: 0,
+                    error: gamma(7) * (center.0.abs() + point.0.abs()),
+                }
+            }
+        };
+        Some(Intersection {
+            primitive: self.indices[i],
+            ..hit
+        })
+    }
+}
+
+/// Appends the subtree over `order`, whose first entry is at `offset` in the
+/// whole ordering, splitting the longest side of its bounds at the median.
+fn build(
+    nodes: &mut Vec<Node>,
+    positions: &[Point],
+    order: &mut [usize],
+    offset: usize,
+    radius: Float,
+) {
+    let mut bounds = Aabb::empty();
+    for i in order.iter() {
+        bounds.grow(&positions[*i]);
+    }
+    let index = nodes.len();
+    if order.len() <= LEAF_SIZE {
+        nodes.push(Node {
+            bounds: bounds.padded(radius),
+            kind: NodeKind::Leaf {
+                start: offset,
+                end: offset + order.len(),
+            },
+        });
+        return;
+    }
+
+    let extent = (bounds.max - bounds.min).0 .0;
+    let axis = (0..3)
+        .max_by(|a, b| extent[*a].total_cmp(&extent[*b]))
+        .unwrap();
+    let middle = order.len() / 2;
+    order.select_nth_unstable_by(middle, |a, b| {
+        positions[*a].0 .0[axis].total_cmp(&positions[*b].0 .0[axis])
+    });
+    nodes.push(Node {
+        bounds: bounds.padded(radius),
+        kind: NodeKind::Interior { right: 0 },
+    });
+    let (left, right) = order.split_at_mut(middle);
+    build(nodes, positions, left, offset, radius);
+    let right_index = nodes.len();
+    build(nodes, positions, right, offset + middle, radius);
+    nodes[index].kind = NodeKind::Interior { right: right_index };
+}
+
+impl Shape for PointCloud {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let mut closest: Option<Intersection> = None;
+        let mut stack: SmallVec<[(usize, Float); 64]> = SmallVec::new();
+        if let Some((t, _)) = self.nodes.first()?.bounds.intersect(r) {
+            stack.push((0, t));
+        }
+        while let Some((index, t)) = stack.pop() {
+            if closest.is_some_and(|c| c.distance < t) {
+                continue;
+            }
+            match self.nodes[index].kind {
+                NodeKind::Leaf { start, end } => {
+                    for i in start..end {
+                        if let Some(hit) = self.intersect_point(i, r) {
+                            if closest.is_none_or(|c| hit.distance < c.distance) {
+                                closest = Some(hit);
+                            }
+                        }
+                    }
+                }
+                NodeKind::Interior { right } => {
+                    let near = self.nodes[index + 1].bounds.intersect(r);
+                    let far = self.nodes[right].bounds.intersect(r);
+                    let mut children = [(index + 1, near), (right, far)];
+                    // the nearer child goes on top, to be searched first
+                    if near.map(|n| n.0) < far.map(|f| f.0) {
+                        children.swap(0, 1);
+                    }
+                    for (child, interval) in children {
+                        if let Some((t, _)) = interval {
+                            stack.push((child, t));
+                        }
+                    }
+                }
+            }
+        }
+        closest
+    }
+
+    fn occluded(&self, r: &Ray, max_distance: Float) -> bool {
+        let mut stack: SmallVec<[usize; 64]> = SmallVec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node
+                .bounds
+                .intersect(r)
+                .is_none_or(|(t, _)| t >= max_distance)
+            {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { start, end } => {
+                    let blocks = |i| {
+                        self.intersect_point(i, r)
+                            .is_some_and(|hit| hit.distance < max_distance)
+                    };
+                    if (start..end).any(blocks) {
+                        return true;
+                    }
+                }
+                NodeKind::Interior { right } => {
+                    stack.push(right);
+                    stack.push(index + 1);
+                }
+            }
+        }
+        false
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|n| n.bounds)
+    }
+
+    fn albedo(&self, i: &Intersection) -> Option<Color> {
+        self.colors.get(i.primitive).copied()
+    }
+}
+
+/// Reads whitespace-separated points, one per line: `x y z`, `x y z r g b`
+/// or `x y z r g b nx ny nz`. Colors are 0 to 1, or 0 to 255 if any of them
+/// is above 1. Blank lines and lines starting with `#` are skipped.
+pub fn parse_xyz(reader: impl Read) -> io::Result<Points> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut points = Points::default();
+    let mut columns = None;
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|t| !t.is_empty())
+            .map(|t| {
+                t.parse::<Float>()
+                    .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if !matches!(values.len(), 3 | 6 | 9) {
+            return Err(invalid(
+                line_number,
+                format!("expected 3, 6 or 9 values, found {}", values.len()),
+            ));
+        }
+        if *columns.get_or_insert(values.len()) != values.len() {
+            return Err(invalid(
+                line_number,
+                "not the same number of values as the lines before".to_owned(),
+            ));
+        }
+        points
+            .positions
+            .push(Point(Vec3([values[0], values[1], values[2]])));
+        if values.len() >= 6 {
+            points.colors.push(Color(values[3], values[4], values[5]));
+        }
+        if values.len() == 9 {
+            points
+                .normals
+                .push(Direction(Vec3([values[6], values[7], values[8]])).normalized());
+        }
+    }
+
+    let bytes = points
+        .colors
+        .iter()
+        .any(|c| c.0 > 1.0 || c.1 > 1.0 || c.2 > 1.0);
+    if bytes {
+        for c in points.colors.iter_mut() {
+            *c = Color(c.0 / 255.0, c.1 / 255.0, c.2 / 255.0);
+        }
+    }
+    Ok(points)
+}
+
+pub fn load_xyz(path: &Path) -> io::Result<Points> {
+    parse_xyz(std::fs::File::open(path)?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Type {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Type {
+    fn parse(name: &str) -> Option<Type> {
+        Some(match name {
+            "char" | "int8" => Type::I8,
+            "uchar" | "uint8" => Type::U8,
+            "short" | "int16" => Type::I16,
+            "ushort" | "uint16" => Type::U16,
+            "int" | "int32" => Type::I32,
+            "uint" | "uint32" => Type::U32,
+            "float" | "float32" => Type::F32,
+            "double" | "float64" => Type::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Type::I8 | Type::U8 => 1,
+            Type::I16 | Type::U16 => 2,
+            Type::I32 | Type::U32 | Type::F32 => 4,
+            Type::F64 => 8,
+        }
+    }
+
+    /// What a color stored as this type is out of.
+    fn color_scale(self) -> Float {
+        match self {
+            Type::U8 | Type::I8 => 255.0,
+            Type::U16 | Type::I16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+
+    fn read(self, bytes: &[u8], format: Format) -> Float {
+        macro_rules! decode {
+            ($t:ty) => {{
+                let b = bytes.try_into().unwrap();
+                (if format == Format::BigEndian {
+                    <$t>::from_be_bytes(b)
+                } else {
+                    <$t>::from_le_bytes(b)
+                }) as Float
+            }};
+        }
+        match self {
+            Type::I8 => decode!(i8),
+            Type::U8 => decode!(u8),
+            Type::I16 => decode!(i16),
+            Type::U16 => decode!(u16),
+            Type::I32 => decode!(i32),
+            Type::U32 => decode!(u32),
+            Type::F32 => decode!(f32),
+            Type::F64 => decode!(f64),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    /// Type of the list length, for list properties.
+    count: Option<Type>,
+    value: Type,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads the vertex element of an ASCII or binary PLY file: `x`, `y` and `z`,
+/// with colors from `red`, `green` and `blue` and normals from `nx`, `ny` and
+/// `nz` when all three are there. Faces and other elements are ignored.
+pub fn parse_ply(reader: impl Read) -> io::Result<Points> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut line_number = 0;
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid(line_number, "missing end_header".to_owned()));
+        }
+        line_number += 1;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if tokens != ["ply"] {
+                return Err(invalid(line_number, "not a PLY file".to_owned()));
+            }
+            continue;
+        }
+        match tokens.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(invalid(line_number, format!("unknown format '{}'", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|e| invalid(line_number, format!("bad count '{}': {}", count, e)))?,
+                properties: Vec::new(),
+            }),
+            ["property", rest @ ..] => {
+                let ty = |name: &str| {
+                    Type::parse(name)
+                        .ok_or_else(|| invalid(line_number, format!("unknown type '{}'", name)))
+                };
+                let property = match rest {
+                    ["list", count, value, name] => Property {
+                        name: name.to_string(),
+                        count: Some(ty(count)?),
+                        value: ty(value)?,
+                    },
+                    [value, name] => Property {
+                        name: name.to_string(),
+                        count: None,
+                        value: ty(value)?,
+                    },
+                    _ => return Err(invalid(line_number, "bad property".to_owned())),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| invalid(line_number, "property before any element".to_owned()))?
+                    .properties
+                    .push(property);
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid(line_number, "unknown header line".to_owned())),
+        }
+    }
+    let format = format.ok_or_else(|| invalid(line_number, "missing format".to_owned()))?;
+
+    let mut points = Points::default();
+    for element in &elements {
+        let column = |name: &str| {
+            element
+                .properties
+                .iter()
+                .position(|p| p.name == name && p.count.is_none())
+        };
+        let columns = |names: [&str; 3]| {
+            let c = names.map(column);
+            c.iter().all(Option::is_some).then(|| c.map(Option::unwrap))
+        };
+        let vertex = element.name == "vertex";
+        let position = columns(["x", "y", "z"]);
+        let color = columns(["red", "green", "blue"])
+            .or_else(|| columns(["diffuse_red", "diffuse_green", "diffuse_blue"]));
+        let normal = columns(["nx", "ny", "nz"]);
+        if vertex && position.is_none() {
+            return Err(invalid(
+                line_number,
+                "vertices without x, y and z".to_owned(),
+            ));
+        }
+
+        let mut values = vec![0.0; element.properties.len()];
+        for _ in 0..element.count {
+            if format == Format::Ascii {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(invalid(line_number, "too few elements".to_owned()));
+                }
+                line_number += 1;
+                let mut tokens = line.split_whitespace().map(|t| {
+                    t.parse::<Float>()
+                        .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+                });
+                let mut next = || {
+                    tokens
+                        .next()
+                        .unwrap_or_else(|| Err(invalid(line_number, "too few values".to_owned())))
+                };
+                for (value, property) in values.iter_mut().zip(&element.properties) {
+                    *value = next()?;
+                    if property.count.is_some() {
+                        for _ in 0..*value as usize {
+                            next()?;
+                        }
+                    }
+                }
+            } else {
+                let mut read = |ty: Type| -> io::Result<Float> {
+                    let mut bytes = [0; 8];
+                    reader.read_exact(&mut bytes[..ty.size()])?;
+                    Ok(ty.read(&bytes[..ty.size()], format))
+                };
+                for (value, property) in values.iter_mut().zip(&element.properties) {
+                    match property.count {
+                        Some(count) => {
+                            let n = read(count)? as usize;
+                            for _ in 0..n {
+                                read(property.value)?;
+                            }
+                        }
+                        None => *value = read(property.value)?,
+                    }
+                }
+            }
+
+            if !vertex {
+                continue;
+            }
+            let [x, y, z] = position.unwrap().map(|c| values[c]);
+            points.positions.push(Point(Vec3([x, y, z])));
+            if let Some(c) = color {
+                let [r, g, b] = c.map(|c| values[c] / element.properties[c].value.color_scale());
+                points.colors.push(Color(r, g, b));
+            }
+            if let Some(n) = normal {
+                points
+                    .normals
+                    .push(Direction(Vec3(n.map(|c| values[c]))).normalized());
+            }
+        }
+        if vertex {
+            // nothing after the vertices is needed
+            break;
+        }
+    }
+    Ok(points)
+}
+
+pub fn load_ply(path: &Path) -> io::Result<Points> {
+    parse_ply(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Deterministic points scattered through a unit cube.
+    fn scatter(n: usize) -> Vec<Point> {
+        let mut random = Random(12345);
+        (0..n).map(|_| random.point()).collect()
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let positions = scatter(2000);
+        let normals: Vec<_> = positions
+            .iter()
+            .map(|p| Direction(p.0 - Vec3([0.5; 3])).normalized())
+            .collect();
+        for splat in [Splat::Sphere, Splat::Disk] {
+            let cloud = PointCloud::new(
+                Points {
+                    positions: positions.clone(),
+                    colors: Vec::new(),
+                    normals: normals.clone(),
+                },
+                0.01,
+                splat,
+            )
+            .unwrap();
+            for (k, target) in scatter(200).into_iter().enumerate() {
+                let origin = Point(Vec3([-1.0, 0.3 * k as Float / 200.0, 2.0]));
+                let r = Ray::from_points(origin, target);
+                let expected = (0..cloud.len())
+                    .filter_map(|i| cloud.intersect_point(i, &r))
+                    .min_by(|a, b| a.distance.total_cmp(&b.distance));
+                let found = cloud.find_intersection(&r);
+                assert_eq!(expected.map(|i| i.primitive), found.map(|i| i.primitive));
+                if let Some(e) = expected {
+                    assert!(cloud.occluded(&r, e.distance + per_precision(1e-9, 1e-4)));
+                    assert!(!cloud.occluded(&r, e.distance - per_precision(1e-9, 1e-4)));
+                } else {
+                    assert!(!cloud.occluded(&r, Float::INFINITY));
+                }
+                if let Some(i) = found {
+                    // original order, not tree order
+                    assert!(
+                        (i.point - positions[i.primitive]).0.magnitude()
+                            <= 0.01 + per_precision(1e-9, 1e-5)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn splats() {
+        let points = Points {
+            positions: vec![Point::origin(), Point(Vec3([0.0, 0.0, -1.0]))],
+            colors: vec![Color(1.0, 0.0, 0.0), Color(0.0, 1.0, 0.0)],
+            normals: Vec::new(),
+        };
+        let r = Ray(
+            Point(Vec3([0.05, 0.0, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+
+        let spheres = PointCloud::new(points.clone(), 0.1, Splat::Sphere).unwrap();
+        let i = spheres.find_intersection(&r).unwrap();
+        assert_eq!(0, i.primitive);
+        assert!((i.distance - (5.0 - (0.0075 as Float).sqrt())).abs() < per_precision(1e-12, 1e-5));
+        assert_eq!(Some(Color(1.0, 0.0, 0.0)), spheres.albedo(&i));
+
+        // without normals, disks face the ray
+        let disks = PointCloud::new(points.clone(), 0.1, Splat::Disk).unwrap();
+        let i = disks.find_intersection(&r).unwrap();
+        assert!((i.distance - 5.0).abs() < per_precision(1e-12, 1e-5));
+        assert_eq!(Direction(Vec3([0.0, 0.0, 1.0])), i.geometric_normal);
+        assert!(i.front_face);
+        assert!(((i.uv.0 - 0.5).hypot(i.uv.1 - 0.5) - 0.25).abs() < per_precision(1e-12, 1e-5));
+        let miss = Ray(
+            Point(Vec3([0.15, 0.0, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        );
+        assert!(disks.find_intersection(&miss).is_none());
+
+        let empty = PointCloud::new(Points::default(), 0.1, Splat::Sphere).unwrap();
+        assert!(empty.find_intersection(&r).is_none());
+        assert!(empty.bounds().is_none());
+
+        let mut uneven = points;
+        uneven.normals.push(Direction(Vec3([0.0, 0.0, 1.0])));
+        assert!(PointCloud::new(uneven, 0.1, Splat::Disk).is_err());
+    }
+
+    #[test]
+    fn xyz() {
+        let points = parse_xyz("# scan\n0 0 0 255 0 0\n\n1.5 2 -3 0 128 255\n".as_bytes()).unwrap();
+        assert_eq!(2, points.positions.len());
+        assert_eq!(Point(Vec3([1.5, 2.0, -3.0])), points.positions[1]);
+        assert_eq!(Color(1.0, 0.0, 0.0), points.colors[0]);
+        assert!(points.normals.is_empty());
+
+        let points = parse_xyz("0,0,0,0.5,0.5,0.5,0,0,2\n".as_bytes()).unwrap();
+        assert_eq!(Color(0.5, 0.5, 0.5), points.colors[0]);
+        assert_eq!(Direction(Vec3([0.0, 0.0, 1.0])), points.normals[0]);
+
+        let error = parse_xyz("0 0 0\n1 1\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"));
+        assert!(parse_xyz("0 0 0\n1 1 1 1 1 1\n".as_bytes()).is_err());
+    }
+
+    const HEADER: &str = "ply\n\
+        comment made by hand\n\
+        element vertex 2\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property uchar red\n\
+        property uchar green\n\
+        property uchar blue\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n";
+
+    #[test]
+    fn ply_ascii() {
+        let file = HEADER.replace("ply\n", "ply\nformat ascii 1.0\n")
+            + "0 0 0 255 0 0\n1 2 3 0 0 255\n3 0 1 1\n";
+        let points = parse_ply(file.as_bytes()).unwrap();
+        assert_eq!(2, points.positions.len());
+        assert_eq!(Point(Vec3([1.0, 2.0, 3.0])), points.positions[1]);
+        assert_eq!(Color(0.0, 0.0, 1.0), points.colors[1]);
+        assert!(points.normals.is_empty());
+
+        let file = HEADER.replace("ply\n", "ply\nformat ascii 1.0\n") + "0 0 0 255 0 0\n";
+        assert!(parse_ply(file.as_bytes()).is_err());
+        assert!(parse_ply("ply\nformat ascii 1.0\nend_header\n".as_bytes())
+            .unwrap()
+            .positions
+            .is_empty());
+        assert!(parse_ply("PLY\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn ply_binary() {
+        // a face element before the vertices has to be read past
+        let header = "ply\n\
+            format binary_big_endian 1.0\n\
+            element face 1\n\
+            property list uchar int vertex_indices\n\
+            element vertex 1\n\
+            property double x\n\
+            property double y\n\
+            property double z\n\
+            property float nx\n\
+            property float ny\n\
+            property float nz\n\
+            end_header\n";
+        let mut file = header.as_bytes().to_vec();
+        file.push(2);
+        file.extend(0i32.to_be_bytes());
+        file.extend(1i32.to_be_bytes());
+        for v in [1.0f64, -2.0, 0.5] {
+            file.extend(v.to_be_bytes());
+        }
+        for v in [0.0f32, 3.0, 0.0] {
+            file.extend(v.to_be_bytes());
+        }
+        let points = parse_ply(file.as_slice()).unwrap();
+        assert_eq!(vec![Point(Vec3([1.0, -2.0, 0.5]))], points.positions);
+        assert_eq!(vec![Direction(Vec3([0.0, 1.0, 0.0]))], points.normals);
+        assert!(points.colors.is_empty());
+
+        assert!(parse_ply(&file[..file.len() - 1]).is_err());
+    }
+}
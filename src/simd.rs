@@ -0,0 +1,499 @@
+//! Four [`Float`] lanes at a time, for testing one ray against several boxes
+//! or triangles at once. The kernels are written once over [`Lanes`]. In f64
+//! with AVX enabled at compile time (`-Ctarget-cpu=native` on a machine that
+//! has it) they run on 256-bit registers, in f32 on x86-64 on SSE's 128-bit
+//! ones, and otherwise on plain arrays, which give the same answers bit for
+//! bit.
+
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::*;
+
+pub(crate) trait Lanes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    type Mask: Copy;
+
+    fn splat(x: Float) -> Self;
+    fn load(x: &[Float; 4]) -> Self;
+    fn store(self) -> [Float; 4];
+    /// `self` where it is above `rhs`, else `rhs`, which is also what a NaN
+    /// on either side gives.
+    fn max(self, rhs: Self) -> Self;
+    /// `self` where it is below `rhs`, else `rhs`.
+    fn min(self, rhs: Self) -> Self;
+    fn abs(self) -> Self;
+    fn lt(self, rhs: Self) -> Self::Mask;
+    fn le(self, rhs: Self) -> Self::Mask;
+    fn and(a: Self::Mask, b: Self::Mask) -> Self::Mask;
+    /// Bit k set for each lane k where the mask holds.
+    fn bits(m: Self::Mask) -> u32;
+}
+
+/// The fallback, one lane after another. Where there are vector registers,
+/// only the tests use it, to check the two against each other.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(
+    all(target_arch = "x86_64", any(target_feature = "avx", feature = "f32")),
+    allow(dead_code)
+)]
+pub(crate) struct Scalar([Float; 4]);
+
+#[cfg_attr(
+    all(target_arch = "x86_64", any(target_feature = "avx", feature = "f32")),
+    allow(dead_code)
+)]
+impl Scalar {
+    fn zip(self, rhs: Self, f: impl Fn(Float, Float) -> Float) -> Self {
+        Scalar([0, 1, 2, 3].map(|k| f(self.0[k], rhs.0[k])))
+    }
+}
+
+impl Add for Scalar {
+    type Output = Scalar;
+    fn add(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| a + b)
+    }
+}
+
+impl Sub for Scalar {
+    type Output = Scalar;
+    fn sub(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| a - b)
+    }
+}
+
+impl Mul for Scalar {
+    type Output = Scalar;
+    fn mul(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| a * b)
+    }
+}
+
+impl Div for Scalar {
+    type Output = Scalar;
+    fn div(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| a / b)
+    }
+}
+
+impl Lanes for Scalar {
+    type Mask = [bool; 4];
+
+    fn splat(x: Float) -> Scalar {
+        Scalar([x; 4])
+    }
+
+    fn load(x: &[Float; 4]) -> Scalar {
+        Scalar(*x)
+    }
+
+    fn store(self) -> [Float; 4] {
+        self.0
+    }
+
+    fn max(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| if a > b { a } else { b })
+    }
+
+    fn min(self, rhs: Scalar) -> Scalar {
+        self.zip(rhs, |a, b| if a < b { a } else { b })
+    }
+
+    fn abs(self) -> Scalar {
+        Scalar(self.0.map(Float::abs))
+    }
+
+    fn lt(self, rhs: Scalar) -> [bool; 4] {
+        [0, 1, 2, 3].map(|k| self.0[k] < rhs.0[k])
+    }
+
+    fn le(self, rhs: Scalar) -> [bool; 4] {
+        [0, 1, 2, 3].map(|k| self.0[k] <= rhs.0[k])
+    }
+
+    fn and(a: [bool; 4], b: [bool; 4]) -> [bool; 4] {
+        [0, 1, 2, 3].map(|k| a[k] && b[k])
+    }
+
+    fn bits(m: [bool; 4]) -> u32 {
+        (0..4).filter(|k| m[*k]).map(|k| 1 << k).sum()
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx", not(feature = "f32")))]
+mod avx {
+    //! Intrinsics are unsafe to call only because the CPU might lack them;
+    //! this module is compiled only where AVX is known to be there.
+
+    use std::arch::x86_64::*;
+    use std::ops::{Add, Div, Mul, Sub};
+
+    use super::Lanes;
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct Avx(__m256d);
+
+    impl Add for Avx {
+        type Output = Avx;
+        fn add(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_add_pd(self.0, rhs.0) })
+        }
+    }
+
+    impl Sub for Avx {
+        type Output = Avx;
+        fn sub(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_sub_pd(self.0, rhs.0) })
+        }
+    }
+
+    impl Mul for Avx {
+        type Output = Avx;
+        fn mul(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_mul_pd(self.0, rhs.0) })
+        }
+    }
+
+    impl Div for Avx {
+        type Output = Avx;
+        fn div(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_div_pd(self.0, rhs.0) })
+        }
+    }
+
+    impl Lanes for Avx {
+        type Mask = __m256d;
+
+        fn splat(x: f64) -> Avx {
+            Avx(unsafe { _mm256_set1_pd(x) })
+        }
+
+        fn load(x: &[f64; 4]) -> Avx {
+            // SAFETY: four f64 are readable at `x`, and loadu takes any alignment
+            Avx(unsafe { _mm256_loadu_pd(x.as_ptr()) })
+        }
+
+        fn store(self) -> [f64; 4] {
+            let mut out = [0.0; 4];
+            // SAFETY: four f64 are writable at `out`, and storeu takes any alignment
+            unsafe { _mm256_storeu_pd(out.as_mut_ptr(), self.0) };
+            out
+        }
+
+        // maxpd and minpd return the second operand when either is NaN
+        fn max(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_max_pd(self.0, rhs.0) })
+        }
+
+        fn min(self, rhs: Avx) -> Avx {
+            Avx(unsafe { _mm256_min_pd(self.0, rhs.0) })
+        }
+
+        fn abs(self) -> Avx {
+            Avx(unsafe { _mm256_andnot_pd(_mm256_set1_pd(-0.0), self.0) })
+        }
+
+        fn lt(self, rhs: Avx) -> __m256d {
+            unsafe { _mm256_cmp_pd::<_CMP_LT_OQ>(self.0, rhs.0) }
+        }
+
+        fn le(self, rhs: Avx) -> __m256d {
+            unsafe { _mm256_cmp_pd::<_CMP_LE_OQ>(self.0, rhs.0) }
+        }
+
+        fn and(a: __m256d, b: __m256d) -> __m256d {
+            unsafe { _mm256_and_pd(a, b) }
+        }
+
+        fn bits(m: __m256d) -> u32 {
+            unsafe { _mm256_movemask_pd(m) as u32 }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", feature = "f32"))]
+mod sse {
+    //! Intrinsics are unsafe to call only because the CPU might lack them;
+    //! SSE is part of every x86-64.
+
+    use std::arch::x86_64::*;
+    use std::ops::{Add, Div, Mul, Sub};
+
+    use super::Lanes;
+
+    #[derive(Clone, Copy, Debug)]
+    pub(crate) struct Sse(__m128);
+
+    impl Add for Sse {
+        type Output = Sse;
+        fn add(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_add_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Sub for Sse {
+        type Output = Sse;
+        fn sub(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_sub_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Mul for Sse {
+        type Output = Sse;
+        fn mul(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_mul_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Div for Sse {
+        type Output = Sse;
+        fn div(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_div_ps(self.0, rhs.0) })
+        }
+    }
+
+    impl Lanes for Sse {
+        type Mask = __m128;
+
+        fn splat(x: f32) -> Sse {
+            Sse(unsafe { _mm_set1_ps(x) })
+        }
+
+        fn load(x: &[f32; 4]) -> Sse {
+            // SAFETY: four f32 are readable at `x`, and loadu takes any alignment
+            Sse(unsafe { _mm_loadu_ps(x.as_ptr()) })
+        }
+
+        fn store(self) -> [f32; 4] {
+            let mut out = [0.0; 4];
+            // SAFETY: four f32 are writable at `out`, and storeu takes any alignment
+            unsafe { _mm_storeu_ps(out.as_mut_ptr(), self.0) };
+            out
+        }
+
+        // maxps and minps return the second operand when either is NaN
+        fn max(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_max_ps(self.0, rhs.0) })
+        }
+
+        fn min(self, rhs: Sse) -> Sse {
+            Sse(unsafe { _mm_min_ps(self.0, rhs.0) })
+        }
+
+        fn abs(self) -> Sse {
+            Sse(unsafe { _mm_andnot_ps(_mm_set1_ps(-0.0), self.0) })
+        }
+
+        fn lt(self, rhs: Sse) -> __m128 {
+            unsafe { _mm_cmplt_ps(self.0, rhs.0) }
+        }
+
+        fn le(self, rhs: Sse) -> __m128 {
+            unsafe { _mm_cmple_ps(self.0, rhs.0) }
+        }
+
+        fn and(a: __m128, b: __m128) -> __m128 {
+            unsafe { _mm_and_ps(a, b) }
+        }
+
+        fn bits(m: __m128) -> u32 {
+            unsafe { _mm_movemask_ps(m) as u32 }
+        }
+    }
+}
+
+/// The lanes the kernels run on in this build.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx", not(feature = "f32")))]
+pub(crate) type Float4 = avx::Avx;
+#[cfg(all(target_arch = "x86_64", feature = "f32"))]
+pub(crate) type Float4 = sse::Sse;
+#[cfg(not(all(target_arch = "x86_64", any(target_feature = "avx", feature = "f32"))))]
+pub(crate) type Float4 = Scalar;
+
+/// `bvh::slabs` for four boxes at once, given as the lanes of their corners
+/// along each axis. Bit k of the mask is set when box k is entered before
+/// `max`, at `entries[k]`. Boxes with their minimum above their maximum,
+/// such as padding, are never entered.
+pub(crate) fn slabs4<L: Lanes>(
+    min: [&[Float; 4]; 3],
+    max: [&[Float; 4]; 3],
+    origin: &[Float; 3],
+    inverse: &[Float; 3],
+    t_max: Float,
+) -> (u32, [Float; 4]) {
+    let mut t_near = L::splat(0.0);
+    let mut t_far = L::splat(t_max);
+    for axis in 0..3 {
+        let (near, far) = if inverse[axis] < 0.0 {
+            (max[axis], min[axis])
+        } else {
+            (min[axis], max[axis])
+        };
+        let o = L::splat(origin[axis]);
+        let inv = L::splat(inverse[axis]);
+        let t0 = (L::load(near) - o) * inv;
+        let t1 = (L::load(far) - o) * inv;
+        // the running bounds go second, so that NaN (0 * inf) leaves them be
+        t_near = t0.max(t_near);
+        t_far = t1.min(t_far);
+    }
+    (L::bits(t_near.le(t_far)), t_near.store())
+}
+
+/// Four triangles, each as a corner and the two edges leaving it, laid out
+/// lane by lane. Lanes without a triangle have zero edges, which nothing hits.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Triangles4 {
+    p0: [[Float; 4]; 3],
+    edge1: [[Float; 4]; 3],
+    edge2: [[Float; 4]; 3],
+}
+
+impl Triangles4 {
+    /// Packs up to four triangles, given by their corners.
+    pub(crate) fn new(triangles: impl IntoIterator<Item = [Point; 3]>) -> Triangles4 {
+        let mut packet = Triangles4::default();
+        for (k, [p0, p1, p2]) in triangles.into_iter().enumerate() {
+            assert!(k < 4, "a packet holds four triangles");
+            let (edge1, edge2) = (p1 - p0, p2 - p0);
+            for axis in 0..3 {
+                packet.p0[axis][k] = p0.0 .0[axis];
+                packet.edge1[axis][k] = edge1.0 .0[axis];
+                packet.edge2[axis][k] = edge2.0 .0[axis];
+            }
+        }
+        packet
+    }
+
+    /// The nearest triangle hit closer than `max`: its lane, distance and
+    /// barycentrics.
+    pub(crate) fn intersect(&self, r: &Ray, max: Float) -> Option<(usize, Float, Float, Float)> {
+        self.intersect_with::<Float4>(r, max)
+    }
+
+    /// Möller–Trumbore on every lane, with the operations in the same order
+    /// as for a single triangle, so that each lane rounds the same way.
+    pub(crate) fn intersect_with<L: Lanes>(
+        &self,
+        r: &Ray,
+        max: Float,
+    ) -> Option<(usize, Float, Float, Float)> {
+        let load = |v: &[[Float; 4]; 3]| [L::load(&v[0]), L::load(&v[1]), L::load(&v[2])];
+        let cross = |a: [L; 3], b: [L; 3]| {
+            [
+                a[1] * b[2] - a[2] * b[1],
+                a[2] * b[0] - a[0] * b[2],
+                a[0] * b[1] - a[1] * b[0],
+            ]
+        };
+        let dot = |a: [L; 3], b: [L; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+
+        let d = r.1 .0 .0.map(L::splat);
+        let (edge1, edge2) = (load(&self.edge1), load(&self.edge2));
+        let p = cross(d, edge2);
+        let det = dot(edge1, p);
+        let inv_det = L::splat(1.0) / det;
+        let p0 = load(&self.p0);
+        let s = [0, 1, 2].map(|a| L::splat(r.0 .0 .0[a]) - p0[a]);
+        let u = dot(s, p) * inv_det;
+        let q = cross(s, edge1);
+        let v = dot(d, q) * inv_det;
+        let distance = dot(edge2, q) * inv_det;
+
+        let (zero, one) = (L::splat(0.0), L::splat(1.0));
+        let masks = [
+            L::splat(1e-12).le(det.abs()),
+            zero.le(u),
+            u.le(one),
+            zero.le(v),
+            (u + v).le(one),
+            zero.lt(distance),
+            distance.lt(L::splat(max)),
+        ];
+        let hits = L::bits(masks.into_iter().reduce(L::and).unwrap());
+        if hits == 0 {
+            return None;
+        }
+        let (distance, u, v) = (distance.store(), u.store(), v.store());
+        // the first of equally near lanes, as a loop over them would keep
+        let lane = (0..4)
+            .filter(|k| hits & (1 << k) != 0)
+            .min_by(|a, b| distance[*a].total_cmp(&distance[*b]))?;
+        Some((lane, distance[lane], u[lane], v[lane]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn point(x: Float, y: Float, z: Float) -> Point {
+        Point(Vec3([x, y, z]))
+    }
+
+    #[test]
+    fn lanes_agree() {
+        let packet = Triangles4::new([
+            [
+                point(-1.0, -1.0, 0.0),
+                point(1.0, -1.0, 0.0),
+                point(0.0, 1.0, 0.0),
+            ],
+            [
+                point(-1.0, -1.0, -1.0),
+                point(1.0, -1.0, -1.0),
+                point(0.0, 1.0, -1.0),
+            ],
+            // edge on to the rays below
+            [
+                point(0.0, -1.0, -2.0),
+                point(0.0, 1.0, -2.0),
+                point(0.0, 0.0, -3.0),
+            ],
+        ]);
+        let origin = point(0.1, 0.2, 5.0);
+        for k in 0..200 {
+            let angle = k as Float * 0.05;
+            let target = point(angle.cos() * k as Float / 150.0, angle.sin(), -1.0);
+            let r = Ray::from_points(origin, target);
+            for max in [Float::INFINITY, 5.5, 4.0] {
+                let expected = packet.intersect_with::<Scalar>(&r, max);
+                assert_eq!(expected, packet.intersect(&r, max));
+                if let Some((lane, distance, _, _)) = expected {
+                    assert!(distance < max);
+                    // the top triangle shades the one below
+                    assert!(lane == 0 || distance > 5.0);
+                }
+            }
+        }
+        // padding lanes are never hit, even along their zero edges
+        let r = Ray(point(0.0, 0.0, 1.0), Direction(Vec3([0.0, 0.0, -1.0])));
+        assert_eq!(None, Triangles4::default().intersect(&r, Float::INFINITY));
+
+        let min = [[0.0, 2.0, Float::INFINITY, -1.0]; 3];
+        let max = [[1.0, 3.0, Float::NEG_INFINITY, 1.0]; 3];
+        for direction in [[1.0, 1.0, 1.0], [1.0, 0.0, 0.0], [-1.0, -2.0, 0.5]] {
+            let r = Ray(
+                point(0.5, 0.5, 0.5),
+                Direction(Vec3(direction)).normalized(),
+            );
+            let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+            let args = (
+                [&min[0], &min[1], &min[2]],
+                [&max[0], &max[1], &max[2]],
+                &r.0 .0 .0,
+                &inverse,
+            );
+            for t_max in [Float::INFINITY, 1.0] {
+                let expected = slabs4::<Scalar>(args.0, args.1, args.2, args.3, t_max);
+                assert_eq!(
+                    expected,
+                    slabs4::<Float4>(args.0, args.1, args.2, args.3, t_max)
+                );
+                // the origin is inside the first and last boxes, and not the padding
+                assert_eq!(0b1001, expected.0 & 0b1101);
+            }
+        }
+    }
+}
@@ -1,7 +1,73 @@
+use crate::consts::PI;
+
+use crate::aabb::Aabb;
 use crate::*;
 
+#[derive(Clone, Copy, Debug)]
+pub struct Sphere {
+    pub center: Point,
+    pub radius: Float,
+}
+
+/// Latitude-longitude coordinates of the point on a sphere with outward
+/// normal `n`: `u` goes once around the z axis, `v` runs from the south pole
+/// to the north.
+pub fn spherical_uv(n: &Direction) -> (Float, Float) {
+    let [x, y, z] = n.0 .0;
+    let u = (y.atan2(x) / (2.0 * PI)).rem_euclid(1.0);
+    let v = 0.5 + z.clamp(-1.0, 1.0).asin() / PI;
+    (u, v)
+}
+
+/// Derivatives of the point on a unit sphere with respect to the coordinates
+/// from `spherical_uv`. At the poles `u` has no effect.
+pub fn spherical_derivatives(n: &Direction) -> (Direction, Direction) {
+    let [x, y, z] = n.0 .0;
+    let rho = (x * x + y * y).sqrt();
+    let (cos_phi, sin_phi) = if rho > 0.0 {
+        (x / rho, y / rho)
+    } else {
+        (1.0, 0.0)
+    };
+    (
+        Direction(Vec3([-2.0 * PI * y, 2.0 * PI * x, 0.0])),
+        Direction(Vec3([-PI * z * cos_phi, -PI * z * sin_phi, PI * rho])),
+    )
+}
+
+impl Shape for Sphere {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        find_intersection(self.center, self.radius, r)
+    }
+
+    fn occluded(&self, r: &Ray, max_distance: Float) -> bool {
+        // no need for the normal and coordinates of the hit
+        interval(self.center, self.radius, r).is_some_and(|(t0, t1)| {
+            let t = if t0 >= 0.0 { t0 } else { t1 };
+            t >= 0.0 && t < max_distance
+        })
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let r = Direction(Vec3([self.radius; 3]));
+        Some(Aabb {
+            min: self.center - r,
+            max: self.center + r,
+        })
+    }
+
+    fn sample_surface(&self, s: (Float, Float)) -> Option<(Point, Direction)> {
+        // uniform in z and in angle around z is uniform in area (Archimedes)
+        let z = 1.0 - 2.0 * s.0;
+        let ring = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * s.1;
+        let n = Direction(Vec3([ring * phi.cos(), ring * phi.sin(), z]));
+        Some((self.center + self.radius * n, n))
+    }
+}
+
 #[allow(non_snake_case)]
-pub fn find_intersection(center: Point, radius: f64, r: &Ray) -> Option<Intersection> {
+pub fn find_intersection(center: Point, radius: Float, r: &Ray) -> Option<Intersection> {
     // https://www.scratchapixel.com/lessons/3d-basic-rendering/minimal-ray-tracer-rendering-simple-shapes/ray-sphere-intersection
 
     let O = r.0;
@@ -38,18 +104,106 @@ pub fn find_intersection(center: Point, radius: f64, r: &Ray) -> Option<Intersec
             if t1 < 0.0 {
                 Some(t0)
             } else {
-                Some(f64::min(t0, t1))
+                Some(Float::min(t0, t1))
             }
         }
     };
 
     t.map(|t| {
         let P = Point(O.0 + D.0 * t);
-        let normal = P - C;
+        // the point computed from t can be well off the surface, so move it
+        // back on; what remains is rounding in these few operations
+        let v = P - C;
+        let v = (R / v.0.magnitude()) * v;
+        let P = C + v;
+        let normal = v.normalized();
+        let (dpdu, dpdv) = spherical_derivatives(&normal);
         Intersection {
             distance: t,
             point: P,
-            surface_normal: normal.normalized(),
+            surface_normal: normal,
+            geometric_normal: normal,
+            front_face: D.dot(&normal) < 0.0,
+            tangent: None,
+            uv: spherical_uv(&normal),
+            dpdu: R * dpdu,
+            dpdv: R * dpdv,
+            primitive: 0,
+            object: 0,
+            error: gamma(5) * v.0.abs() + gamma(1) * P.0.abs(),
         }
     })
 }
+
+/// Distances at which the ray enters and leaves the sphere, which may be
+/// negative when the sphere is behind or around the ray origin.
+pub fn interval(center: Point, radius: Float, r: &Ray) -> Option<(Float, Float)> {
+    let oc = r.0 - center;
+    let b = r.1.dot(&oc);
+    let c = oc.dot(&oc) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-b - root, -b + root))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn shape() {
+        let sphere = Sphere {
+            center: Point(Vec3([1.0, 0.0, 0.0])),
+            radius: 2.0,
+        };
+        let i = sphere
+            .find_intersection(&Ray(
+                Point(Vec3([1.0, 0.0, 5.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 3.0).abs() < 1e-9);
+        assert!((i.uv.1 - 1.0).abs() < 1e-9);
+        assert!(i.front_face);
+
+        // from inside, the normal still points out
+        let i = sphere
+            .find_intersection(&Ray(sphere.center, Direction(Vec3([0.0, 1.0, 0.0]))))
+            .unwrap();
+        assert!(!i.front_face);
+        assert!((i.geometric_normal.0 .0[1] - 1.0).abs() < 1e-9);
+        assert!((i.facing_normal().0 .0[1] + 1.0).abs() < 1e-9);
+        assert!((i.uv.0 - 0.25).abs() < 1e-9);
+        assert!((i.uv.1 - 0.5).abs() < 1e-9);
+
+        // compare the derivatives against a step in uv
+        let n = Direction(Vec3([0.36, 0.48, 0.8]));
+        let (u, v) = spherical_uv(&n);
+        let (dpdu, dpdv) = spherical_derivatives(&n);
+        // in f32, a step big enough to outrun rounding, and the curvature that comes with it
+        let (h, bound) = (per_precision(1e-6, 1e-3), per_precision(1e-4, 5e-2));
+        let at = |u: Float, v: Float| {
+            let (phi, theta) = (2.0 * PI * u, PI * (v - 0.5));
+            Vec3([
+                theta.cos() * phi.cos(),
+                theta.cos() * phi.sin(),
+                theta.sin(),
+            ])
+        };
+        assert!(((1.0 / h) * (at(u + h, v) - at(u, v)) - dpdu.0).magnitude() < bound);
+        assert!(((1.0 / h) * (at(u, v + h) - at(u, v)) - dpdv.0).magnitude() < bound);
+
+        let bounds = sphere.bounds().unwrap();
+        assert_eq!(Point(Vec3([-1.0, -2.0, -2.0])), bounds.min);
+        assert_eq!(Point(Vec3([3.0, 2.0, 2.0])), bounds.max);
+
+        for s in [(0.0, 0.0), (0.3, 0.7), (1.0, 0.5)] {
+            let (p, n) = sphere.sample_surface(s).unwrap();
+            assert!(((p - sphere.center).0.magnitude() - 2.0).abs() < 1e-9);
+            assert!((sphere.radius * n - (p - sphere.center)).0.magnitude() < 1e-9);
+        }
+    }
+}
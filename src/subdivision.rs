@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+
+use crate::mesh::{Crease, PolygonMesh};
+use crate::*;
+
+/// Never subdivide further than this, whatever the target asks for.
+pub const MAX_LEVEL: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /// Subdivide exactly this many times.
+    Level(u32),
+    /// Keep subdividing until no edge is longer than this.
+    EdgeLength(Float),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scheme {
+    CatmullClark,
+    Loop,
+}
+
+impl Scheme {
+    /// Loop for all-triangle meshes, Catmull-Clark for anything with quads or n-gons.
+    pub fn for_mesh(mesh: &PolygonMesh) -> Scheme {
+        if mesh.is_triangles() {
+            Scheme::Loop
+        } else {
+            Scheme::CatmullClark
+        }
+    }
+}
+
+struct Edge {
+    vertices: [usize; 2],
+    faces: SmallVec<[usize; 2]>,
+    sharpness: Float,
+}
+
+impl Edge {
+    /// Boundary and non-manifold edges are treated as infinitely sharp.
+    fn sharpness(&self) -> Float {
+        if self.faces.len() != 2 {
+            Float::INFINITY
+        } else {
+            self.sharpness
+        }
+    }
+
+    fn is_sharp(&self) -> bool {
+        self.sharpness() > 0.0
+    }
+
+    fn other(&self, v: usize) -> usize {
+        if self.vertices[0] == v {
+            self.vertices[1]
+        } else {
+            self.vertices[0]
+        }
+    }
+}
+
+struct Topology {
+    edges: Vec<Edge>,
+    edge_index: HashMap<(usize, usize), usize>,
+    vertex_edges: Vec<SmallVec<[usize; 8]>>,
+    vertex_faces: Vec<SmallVec<[usize; 8]>>,
+}
+
+fn key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Topology {
+    fn new(mesh: &PolygonMesh) -> Topology {
+        let mut topology = Topology {
+            edges: Vec::new(),
+            edge_index: HashMap::new(),
+            vertex_edges: vec![SmallVec::new(); mesh.vertices.len()],
+            vertex_faces: vec![SmallVec::new(); mesh.vertices.len()],
+        };
+
+        for (f, face) in mesh.faces.iter().enumerate() {
+            for (i, &a) in face.iter().enumerate() {
+                let b = face[(i + 1) % face.len()];
+                topology.vertex_faces[a].push(f);
+                let edges = &mut topology.edges;
+                let vertex_edges = &mut topology.vertex_edges;
+                let e = *topology.edge_index.entry(key(a, b)).or_insert_with(|| {
+                    edges.push(Edge {
+                        vertices: [a, b],
+                        faces: SmallVec::new(),
+                        sharpness: 0.0,
+                    });
+                    vertex_edges[a].push(edges.len() - 1);
+                    vertex_edges[b].push(edges.len() - 1);
+                    edges.len() - 1
+                });
+                topology.edges[e].faces.push(f);
+            }
+        }
+
+        for c in &mesh.creases {
+            if let Some(e) = topology.edge_index.get(&key(c.vertices[0], c.vertices[1])) {
+                topology.edges[*e].sharpness = c.sharpness;
+            }
+        }
+
+        topology
+    }
+
+    fn edge(&self, a: usize, b: usize) -> usize {
+        self.edge_index[&key(a, b)]
+    }
+
+    fn sharp_edges(&self, v: usize) -> SmallVec<[usize; 4]> {
+        self.vertex_edges[v]
+            .iter()
+            .copied()
+            .filter(|e| self.edges[*e].is_sharp())
+            .collect()
+    }
+
+    /// How far a vertex goes from the smooth rule toward the one for its
+    /// `sharp` edges: their mean sharpness, up to 1.
+    fn vertex_weight(&self, sharp: &[usize]) -> Float {
+        let sum: Float = sharp.iter().map(|e| self.edges[*e].sharpness()).sum();
+        (sum / sharp.len() as Float).min(1.0)
+    }
+
+    /// Creases on the two halves of every split edge, one level softer.
+    fn child_creases(&self, vertex_count: usize) -> Vec<Crease> {
+        let mut creases = Vec::new();
+        for (e, edge) in self.edges.iter().enumerate() {
+            if edge.sharpness > 1.0 {
+                let middle = vertex_count + e;
+                for v in edge.vertices {
+                    creases.push(Crease {
+                        vertices: [v, middle],
+                        sharpness: edge.sharpness - 1.0,
+                    });
+                }
+            }
+        }
+        creases
+    }
+}
+
+/// Texture coordinates are interpolated linearly onto the new vertex layout
+/// rather than smoothed, so the texture does not swim over the surface.
+fn child_uvs(mesh: &PolygonMesh, topology: &Topology, face_points: bool) -> Vec<(Float, Float)> {
+    if mesh.uvs.is_empty() {
+        return Vec::new();
+    }
+    let mean = |corners: &[usize]| {
+        let n = corners.len() as Float;
+        let (u, v) = corners.iter().fold((0.0, 0.0), |(u, v), i| {
+            (u + mesh.uvs[*i].0, v + mesh.uvs[*i].1)
+        });
+        (u / n, v / n)
+    };
+
+    let mut uvs = mesh.uvs.clone();
+    uvs.extend(topology.edges.iter().map(|e| mean(&e.vertices)));
+    if face_points {
+        uvs.extend(mesh.faces.iter().map(|f| mean(f)));
+    }
+    uvs
+}
+
+/// Semi-sharp creases (DeRose et al. 1998): below a sharpness of 1 a point
+/// lies that far from where the smooth rule puts it toward where the sharp
+/// one does. Only the rule that applies is evaluated.
+fn blend(weight: Float, smooth: impl FnOnce() -> Point, sharp: impl FnOnce() -> Point) -> Point {
+    if weight >= 1.0 {
+        sharp()
+    } else if weight <= 0.0 {
+        smooth()
+    } else {
+        Point((1.0 - weight) * smooth().0 + weight * sharp().0)
+    }
+}
+
+fn average(points: impl Iterator<Item = Point>) -> Point {
+    let mut sum = Vec3([0.0; 3]);
+    let mut count = 0;
+    for p in points {
+        sum += p.0;
+        count += 1;
+    }
+    Point((1.0 / count as Float) * sum)
+}
+
+/// One level of Catmull-Clark. New vertices are laid out as the old
+/// vertices, then one per edge, then one per face.
+pub fn catmull_clark(mesh: &PolygonMesh) -> PolygonMesh {
+    let topology = Topology::new(mesh);
+    let v = &mesh.vertices;
+
+    let face_points: Vec<Point> = mesh
+        .faces
+        .iter()
+        .map(|f| average(f.iter().map(|i| v[*i])))
+        .collect();
+
+    let edge_points = topology.edges.iter().map(|e| {
+        let [a, b] = e.vertices;
+        blend(
+            e.sharpness(),
+            || average([v[a], v[b], face_points[e.faces[0]], face_points[e.faces[1]]].into_iter()),
+            || average([v[a], v[b]].into_iter()),
+        )
+    });
+
+    let vertex_points = (0..v.len()).map(|i| {
+        let sharp = topology.sharp_edges(i);
+        let edges = &topology.vertex_edges[i];
+        let smooth = || {
+            let n = edges.len() as Float;
+            let f = average(topology.vertex_faces[i].iter().map(|f| face_points[*f]));
+            let r = average(edges.iter().map(|e| {
+                let [a, b] = topology.edges[*e].vertices;
+                average([v[a], v[b]].into_iter())
+            }));
+            Point((1.0 / n) * (f.0 + 2.0 * r.0 + (n - 3.0) * v[i].0))
+        };
+        match sharp.len() {
+            // unused vertex
+            _ if edges.is_empty() => v[i],
+            0 | 1 => smooth(),
+            // a vertex on only one face is a corner of the surface and stays put
+            2 if edges.len() == 2 => v[i],
+            2 => blend(topology.vertex_weight(&sharp), smooth, || {
+                let a = v[topology.edges[sharp[0]].other(i)];
+                let b = v[topology.edges[sharp[1]].other(i)];
+                Point(0.125 * (a.0 + 6.0 * v[i].0 + b.0))
+            }),
+            _ => blend(topology.vertex_weight(&sharp), smooth, || v[i]),
+        }
+    });
+
+    let mut vertices: Vec<Point> = vertex_points.collect();
+    vertices.extend(edge_points);
+    vertices.extend(face_points.iter().copied());
+
+    let edge_base = v.len();
+    let face_base = v.len() + topology.edges.len();
+    let mut faces = Vec::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let n = face.len();
+        for i in 0..n {
+            let previous = face[(i + n - 1) % n];
+            let next = face[(i + 1) % n];
+            faces.push(vec![
+                face[i],
+                edge_base + topology.edge(face[i], next),
+                face_base + f,
+                edge_base + topology.edge(previous, face[i]),
+            ]);
+        }
+    }
+
+    PolygonMesh {
+        vertices,
+        faces,
+        creases: topology.child_creases(edge_base),
+        uvs: child_uvs(mesh, &topology, true),
+    }
+}
+
+/// One level of Loop subdivision of a triangle mesh. New vertices are laid
+/// out as the old vertices, then one per edge.
+pub fn loop_subdivide(mesh: &PolygonMesh) -> PolygonMesh {
+    assert!(mesh.is_triangles());
+    let topology = Topology::new(mesh);
+    let v = &mesh.vertices;
+
+    let opposite = |f: usize, e: &Edge| -> Point {
+        let face = &mesh.faces[f];
+        let o = face.iter().find(|i| !e.vertices.contains(i)).unwrap();
+        v[*o]
+    };
+
+    let edge_points = topology.edges.iter().map(|e| {
+        let [a, b] = e.vertices;
+        blend(
+            e.sharpness(),
+            || {
+                let c = opposite(e.faces[0], e);
+                let d = opposite(e.faces[1], e);
+                Point(0.375 * (v[a].0 + v[b].0) + 0.125 * (c.0 + d.0))
+            },
+            || average([v[a], v[b]].into_iter()),
+        )
+    });
+
+    let vertex_points = (0..v.len()).map(|i| {
+        let sharp = topology.sharp_edges(i);
+        let edges = &topology.vertex_edges[i];
+        let smooth = || {
+            let n = edges.len();
+            let beta = if n == 3 {
+                3.0 / 16.0
+            } else {
+                3.0 / (8.0 * n as Float)
+            };
+            let mut sum = (1.0 - n as Float * beta) * v[i].0;
+            for e in edges {
+                sum += beta * v[topology.edges[*e].other(i)].0;
+            }
+            Point(sum)
+        };
+        match sharp.len() {
+            _ if edges.is_empty() => v[i],
+            0 | 1 => smooth(),
+            // a vertex on only one face is a corner of the surface and stays put
+            2 if edges.len() == 2 => v[i],
+            2 => blend(topology.vertex_weight(&sharp), smooth, || {
+                let a = v[topology.edges[sharp[0]].other(i)];
+                let b = v[topology.edges[sharp[1]].other(i)];
+                Point(0.75 * v[i].0 + 0.125 * (a.0 + b.0))
+            }),
+            _ => blend(topology.vertex_weight(&sharp), smooth, || v[i]),
+        }
+    });
+
+    let mut vertices: Vec<Point> = vertex_points.collect();
+    vertices.extend(edge_points);
+
+    let edge_base = v.len();
+    let mut faces = Vec::new();
+    for face in &mesh.faces {
+        let [a, b, c] = [face[0], face[1], face[2]];
+        let ab = edge_base + topology.edge(a, b);
+        let bc = edge_base + topology.edge(b, c);
+        let ca = edge_base + topology.edge(c, a);
+        faces.push(vec![a, ab, ca]);
+        faces.push(vec![ab, b, bc]);
+        faces.push(vec![ca, bc, c]);
+        faces.push(vec![ab, bc, ca]);
+    }
+
+    PolygonMesh {
+        vertices,
+        faces,
+        creases: topology.child_creases(edge_base),
+        uvs: child_uvs(mesh, &topology, false),
+    }
+}
+
+pub fn subdivide(mesh: &PolygonMesh, target: Target) -> PolygonMesh {
+    let scheme = Scheme::for_mesh(mesh);
+    let mut mesh = mesh.clone();
+    for level in 0..MAX_LEVEL {
+        let done = match target {
+            Target::Level(levels) => level >= levels,
+            Target::EdgeLength(length) => mesh.max_edge_length() <= length,
+        };
+        if done {
+            break;
+        }
+        mesh = match scheme {
+            Scheme::CatmullClark => catmull_clark(&mesh),
+            Scheme::Loop => loop_subdivide(&mesh),
+        };
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mesh::parse_obj;
+
+    const CUBE: &str = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+                        v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+                        f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n";
+
+    const TETRAHEDRON: &str = "v 1 1 1\nv 1 -1 -1\nv -1 1 -1\nv -1 -1 1\n\
+                               f 1 2 3\nf 1 4 2\nf 1 3 4\nf 2 4 3\n";
+
+    fn radius_range(mesh: &PolygonMesh) -> (Float, Float) {
+        mesh.vertices
+            .iter()
+            .map(|p| p.0.magnitude())
+            .fold((Float::INFINITY, 0.0), |(lo, hi), r| (lo.min(r), hi.max(r)))
+    }
+
+    #[test]
+    fn catmull_clark_cube() {
+        let cube = parse_obj(CUBE.as_bytes()).unwrap();
+        assert_eq!(Scheme::CatmullClark, Scheme::for_mesh(&cube));
+
+        let once = catmull_clark(&cube);
+        assert_eq!(8 + 12 + 6, once.vertices.len());
+        assert_eq!(24, once.faces.len());
+        // the classic result: corners move to (5/9, 5/9, 5/9)
+        assert!((once.vertices[6].0 .0[0] - 5.0 / 9.0).abs() < 1e-12);
+
+        // repeated subdivision rounds the cube toward a sphere
+        let smooth = subdivide(&cube, Target::Level(3));
+        let (lo, hi) = radius_range(&smooth);
+        assert!(hi / lo < 1.2);
+    }
+
+    #[test]
+    fn creases_keep_edges() {
+        let mut cube = parse_obj(CUBE.as_bytes()).unwrap();
+        // crease every edge of the top face: it stays flat
+        for (a, b) in [(4, 5), (5, 6), (6, 7), (7, 4)] {
+            cube.creases.push(Crease {
+                vertices: [a, b],
+                sharpness: Float::INFINITY,
+            });
+        }
+        let smooth = subdivide(&cube, Target::Level(2));
+        let top = smooth
+            .vertices
+            .iter()
+            .filter(|p| (p.0 .0[2] - 1.0).abs() < 1e-12)
+            .count();
+        assert!(top > 9);
+
+        // a finite crease relaxes back to the smooth surface
+        for c in cube.creases.iter_mut() {
+            c.sharpness = 1.0;
+        }
+        let once = catmull_clark(&cube);
+        assert!(once.creases.is_empty());
+    }
+
+    #[test]
+    fn semi_sharp_creases_blend() {
+        let top_edges = [(4, 5), (5, 6), (6, 7), (7, 4)];
+        let creased = |sharpness| {
+            let mut cube = parse_obj(CUBE.as_bytes()).unwrap();
+            for (a, b) in top_edges {
+                cube.creases.push(Crease {
+                    vertices: [a, b],
+                    sharpness,
+                });
+            }
+            catmull_clark(&cube)
+        };
+        let smooth = creased(0.0);
+        let half = creased(0.5);
+        let sharp = creased(1.0);
+
+        // edge points on the crease and the corners at its ends go half way
+        // from the smooth surface to the sharp one
+        let topology = Topology::new(&parse_obj(CUBE.as_bytes()).unwrap());
+        let mut moved = 0;
+        for i in (4..8).chain(top_edges.iter().map(|(a, b)| 8 + topology.edge(*a, *b))) {
+            let (s, h, c) = (smooth.vertices[i], half.vertices[i], sharp.vertices[i]);
+            assert!(
+                (h.0 - 0.5 * (s.0 + c.0)).magnitude() < 1e-12,
+                "vertex {}",
+                i
+            );
+            assert!((s.0 - c.0).magnitude() > 0.01, "vertex {}", i);
+            moved += 1;
+        }
+        assert_eq!(8, moved);
+        // the middle of a creased edge stays closer to it than smoothing allows
+        let middle = 8 + topology.edge(4, 5);
+        let height = |mesh: &PolygonMesh| mesh.vertices[middle].0 .0[2];
+        assert!(height(&smooth) < height(&half) && height(&half) < height(&sharp));
+        // and does not carry on to the next level
+        assert!(half.creases.is_empty());
+    }
+
+    #[test]
+    fn loop_tetrahedron() {
+        let tetrahedron = parse_obj(TETRAHEDRON.as_bytes()).unwrap();
+        assert_eq!(Scheme::Loop, Scheme::for_mesh(&tetrahedron));
+
+        let once = loop_subdivide(&tetrahedron);
+        assert_eq!(4 + 6, once.vertices.len());
+        assert_eq!(16, once.faces.len());
+
+        let smooth = subdivide(&tetrahedron, Target::EdgeLength(0.3));
+        assert!(smooth.max_edge_length() <= 0.3);
+        let (lo, hi) = radius_range(&smooth);
+        assert!(hi / lo < 1.5);
+    }
+
+    #[test]
+    fn open_boundary() {
+        // a single quad keeps its boundary on the original edges
+        let quad = parse_obj(
+            "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+             f 1/1 2/2 3/3 4/4\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let once = catmull_clark(&quad);
+        assert_eq!(Point(Vec3([0.0, 0.0, 0.0])), once.vertices[0]);
+        assert_eq!(Point(Vec3([1.0, 0.0, 0.0])), once.vertices[4]);
+        assert_eq!(Point(Vec3([1.0, 1.0, 0.0])), *once.vertices.last().unwrap());
+        // texture coordinates follow linearly
+        assert_eq!(once.vertices.len(), once.uvs.len());
+        assert_eq!((0.5, 0.0), once.uvs[4]);
+        assert_eq!((0.5, 0.5), *once.uvs.last().unwrap());
+    }
+}
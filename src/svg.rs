@@ -0,0 +1,978 @@
+use crate::consts::PI;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::mesh::Mesh;
+use crate::*;
+
+/// Line segments each Bézier curve is flattened into.
+const CURVE_SEGMENTS: usize = 16;
+/// Arcs get a segment for every this many radians they sweep.
+const ARC_STEP: Float = PI / 16.0;
+/// Wall normals are smoothed across corners gentler than this (cos 30°), so
+/// flattened curves look round while real corners stay sharp.
+const SMOOTH_CORNER: Float = 0.866;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FillRule {
+    NonZero,
+    EvenOdd,
+}
+
+/// The flattened contours of one SVG `<path>`, in a y-up frame. Contours
+/// may cross neither themselves nor each other; which regions are solid
+/// follows from how they nest and `fill_rule`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outline {
+    pub contours: Vec<Vec<(Float, Float)>>,
+    pub fill_rule: FillRule,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Extrusion {
+    /// Thickness along z, above zero; the back face is at 0 and the front at
+    /// `depth`.
+    pub depth: Float,
+    /// Width of the 45° chamfer around both faces, at most half the depth.
+    /// It should also stay below half the narrowest feature of the outline.
+    pub bevel: Float,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Reads numbers and flags out of SVG path data, where separators are
+/// optional wherever the grammar allows (`10-5`, `.5.5`, `a1 1 0 015 5`).
+struct Scanner<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Scanner<'_> {
+    fn skip_separators(&mut self) {
+        while self.position < self.data.len()
+            && (self.data[self.position].is_ascii_whitespace() || self.data[self.position] == b',')
+        {
+            self.position += 1;
+        }
+    }
+
+    /// The next command letter, if that is what comes next.
+    fn command(&mut self) -> Option<u8> {
+        self.skip_separators();
+        let c = *self.data.get(self.position)?;
+        if c.is_ascii_alphabetic() && c != b'e' && c != b'E' {
+            self.position += 1;
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn at_number(&mut self) -> bool {
+        self.skip_separators();
+        matches!(self.data.get(self.position), Some(c) if c.is_ascii_digit() || b"+-.".contains(c))
+    }
+
+    fn number(&mut self) -> io::Result<Float> {
+        self.skip_separators();
+        let start = self.position;
+        let mut seen_dot = false;
+        let mut seen_exponent = false;
+        while let Some(&c) = self.data.get(self.position) {
+            let sign_allowed =
+                self.position == start || matches!(self.data[self.position - 1], b'e' | b'E');
+            match c {
+                b'0'..=b'9' => {}
+                b'+' | b'-' if sign_allowed => {}
+                b'.' if !seen_dot && !seen_exponent => seen_dot = true,
+                b'e' | b'E' if !seen_exponent && self.position > start => seen_exponent = true,
+                _ => break,
+            }
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.data[start..self.position]).unwrap();
+        text.parse::<Float>()
+            .map_err(|_| invalid(format!("expected a number at offset {}", start)))
+    }
+
+    fn flag(&mut self) -> io::Result<bool> {
+        self.skip_separators();
+        let flag = match self.data.get(self.position) {
+            Some(b'0') => false,
+            Some(b'1') => true,
+            _ => {
+                return Err(invalid(format!(
+                    "expected an arc flag at offset {}",
+                    self.position
+                )))
+            }
+        };
+        self.position += 1;
+        Ok(flag)
+    }
+
+    fn point(&mut self) -> io::Result<(Float, Float)> {
+        Ok((self.number()?, self.number()?))
+    }
+}
+
+/// Flattens the elliptical arc from `from` to `to`, appending the points
+/// after `from`. Follows the endpoint to center conversion in the SVG spec.
+fn flatten_arc(
+    points: &mut Vec<(Float, Float)>,
+    from: (Float, Float),
+    radii: (Float, Float),
+    rotation_degrees: Float,
+    large_arc: bool,
+    sweep: bool,
+    to: (Float, Float),
+) {
+    let (mut rx, mut ry) = (radii.0.abs(), radii.1.abs());
+    if from == to {
+        return;
+    }
+    if rx == 0.0 || ry == 0.0 {
+        points.push(to);
+        return;
+    }
+    let (sin, cos) = rotation_degrees.to_radians().sin_cos();
+    let (hx, hy) = (0.5 * (from.0 - to.0), 0.5 * (from.1 - to.1));
+    let x1 = cos * hx + sin * hy;
+    let y1 = -sin * hx + cos * hy;
+
+    // radii too small to reach are scaled up until they just do
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1.0 {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let numerator = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let denominator = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coefficient = (numerator / denominator).max(0.0).sqrt();
+    if large_arc == sweep {
+        coefficient = -coefficient;
+    }
+    let cx1 = coefficient * rx * y1 / ry;
+    let cy1 = -coefficient * ry * x1 / rx;
+    let cx = cos * cx1 - sin * cy1 + 0.5 * (from.0 + to.0);
+    let cy = sin * cx1 + cos * cy1 + 0.5 * (from.1 + to.1);
+
+    let angle = |x: Float, y: Float| y.atan2(x);
+    let start = angle((x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut delta = angle((-x1 - cx1) / rx, (-y1 - cy1) / ry) - start;
+    if sweep && delta < 0.0 {
+        delta += 2.0 * PI;
+    } else if !sweep && delta > 0.0 {
+        delta -= 2.0 * PI;
+    }
+
+    let segments = ((delta.abs() / ARC_STEP).ceil() as usize).max(1);
+    for k in 1..segments {
+        let theta = start + delta * k as Float / segments as Float;
+        let (s, c) = theta.sin_cos();
+        points.push((
+            cx + rx * cos * c - ry * sin * s,
+            cy + rx * sin * c + ry * cos * s,
+        ));
+    }
+    // land exactly on the endpoint so the next command starts there
+    points.push(to);
+}
+
+/// Parses the `d` attribute of an SVG path into closed contours, in the
+/// path's own (y-down) coordinates. Curves and arcs are flattened; open
+/// subpaths are closed, as they are when filled.
+pub fn parse_path_data(d: &str) -> io::Result<Vec<Vec<(Float, Float)>>> {
+    let mut scanner = Scanner {
+        data: d.as_bytes(),
+        position: 0,
+    };
+    let mut contours = Vec::new();
+    let mut points: Vec<(Float, Float)> = Vec::new();
+    let mut current = (0.0, 0.0);
+    let mut start = (0.0, 0.0);
+    // the second control point of the previous curve, for S and T
+    let mut last_cubic: Option<(Float, Float)> = None;
+    let mut last_quadratic: Option<(Float, Float)> = None;
+
+    let finish = |points: &mut Vec<(Float, Float)>, contours: &mut Vec<Vec<(Float, Float)>>| {
+        let mut contour = std::mem::take(points);
+        contour.dedup();
+        while contour.len() > 1 && contour.first() == contour.last() {
+            contour.pop();
+        }
+        if contour.len() >= 3 {
+            contours.push(contour);
+        }
+    };
+
+    let mut command = match scanner.command() {
+        Some(c) if c.eq_ignore_ascii_case(&b'm') => c,
+        None if !scanner.at_number() => return Ok(contours),
+        _ => return Err(invalid("path data must start with a moveto".to_owned())),
+    };
+    loop {
+        let relative = command.is_ascii_lowercase();
+        let offset = |p: (Float, Float), current: (Float, Float)| {
+            if relative {
+                (p.0 + current.0, p.1 + current.1)
+            } else {
+                p
+            }
+        };
+        let mut cubic = None;
+        let mut quadratic = None;
+        match command.to_ascii_uppercase() {
+            b'M' => {
+                finish(&mut points, &mut contours);
+                current = offset(scanner.point()?, current);
+                start = current;
+                points.push(current);
+                // further pairs are implicit linetos
+                command = if relative { b'l' } else { b'L' };
+            }
+            b'L' => {
+                current = offset(scanner.point()?, current);
+                points.push(current);
+            }
+            b'H' => {
+                let x = scanner.number()?;
+                current.0 = if relative { current.0 + x } else { x };
+                points.push(current);
+            }
+            b'V' => {
+                let y = scanner.number()?;
+                current.1 = if relative { current.1 + y } else { y };
+                points.push(current);
+            }
+            b'C' | b'S' => {
+                let c1 = if command.eq_ignore_ascii_case(&b'c') {
+                    offset(scanner.point()?, current)
+                } else {
+                    // reflection of the previous control point, if there was a curve
+                    last_cubic.map_or(current, |c| (2.0 * current.0 - c.0, 2.0 * current.1 - c.1))
+                };
+                let c2 = offset(scanner.point()?, current);
+                let to = offset(scanner.point()?, current);
+                let p = [current, c1, c2, to].map(|(x, y)| Vec3([x, y, 0.0]));
+                for k in 1..=CURVE_SEGMENTS {
+                    let b = bezier::bernstein(k as Float / CURVE_SEGMENTS as Float);
+                    let q = b[0] * p[0] + b[1] * p[1] + b[2] * p[2] + b[3] * p[3];
+                    points.push((q.0[0], q.0[1]));
+                }
+                current = to;
+                cubic = Some(c2);
+            }
+            b'Q' | b'T' => {
+                let c = if command.eq_ignore_ascii_case(&b'q') {
+                    offset(scanner.point()?, current)
+                } else {
+                    last_quadratic
+                        .map_or(current, |c| (2.0 * current.0 - c.0, 2.0 * current.1 - c.1))
+                };
+                let to = offset(scanner.point()?, current);
+                for k in 1..=CURVE_SEGMENTS {
+                    let t = k as Float / CURVE_SEGMENTS as Float;
+                    let (a, b, e) = ((1.0 - t) * (1.0 - t), 2.0 * t * (1.0 - t), t * t);
+                    points.push((
+                        a * current.0 + b * c.0 + e * to.0,
+                        a * current.1 + b * c.1 + e * to.1,
+                    ));
+                }
+                current = to;
+                quadratic = Some(c);
+            }
+            b'A' => {
+                let radii = scanner.point()?;
+                let rotation = scanner.number()?;
+                let large_arc = scanner.flag()?;
+                let sweep = scanner.flag()?;
+                let to = offset(scanner.point()?, current);
+                flatten_arc(&mut points, current, radii, rotation, large_arc, sweep, to);
+                current = to;
+            }
+            b'Z' => {
+                finish(&mut points, &mut contours);
+                current = start;
+                // a new subpath starts here unless the next command moves
+                points.push(current);
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "unknown path command '{}'",
+                    command as char
+                )))
+            }
+        }
+        last_cubic = cubic;
+        last_quadratic = quadratic;
+
+        // arguments repeat the command until the next letter
+        if command.eq_ignore_ascii_case(&b'z') || !scanner.at_number() {
+            match scanner.command() {
+                Some(c) => command = c,
+                None if scanner.at_number() => {
+                    return Err(invalid("numbers after closepath".to_owned()))
+                }
+                None => {
+                    scanner.skip_separators();
+                    if scanner.position < scanner.data.len() {
+                        return Err(invalid(format!(
+                            "unexpected '{}' in path data",
+                            scanner.data[scanner.position] as char
+                        )));
+                    }
+                    break;
+                }
+            }
+        }
+    }
+    finish(&mut points, &mut contours);
+    Ok(contours)
+}
+
+/// Value of attribute `name` in the text of one tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(i) = rest.find(name) {
+        let before = rest[..i].chars().last();
+        let after = rest[i + name.len()..].trim_start();
+        rest = &rest[i + name.len()..];
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        if let Some(value) = after.strip_prefix('=') {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote == '"' || quote == '\'' {
+                return value[1..].split(quote).next();
+            }
+        }
+    }
+    None
+}
+
+/// Reads the outline of every `<path>` element in an SVG document, flipping
+/// y so the drawing is upright in a y-up frame. Only the `d` attribute and
+/// the fill rule (as an attribute or in `style`) are read; transforms and
+/// all other elements are ignored.
+pub fn parse_svg(mut reader: impl Read) -> io::Result<Vec<Outline>> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+
+    let mut outlines = Vec::new();
+    let mut rest = text.as_str();
+    while let Some(i) = rest.find("<path") {
+        rest = &rest[i + "<path".len()..];
+        let end = rest
+            .find('>')
+            .ok_or_else(|| invalid("unterminated <path> tag".to_owned()))?;
+        let tag = &rest[..end];
+        let style = attribute(tag, "style").unwrap_or("");
+        let even_odd = attribute(tag, "fill-rule") == Some("evenodd")
+            || style.replace(' ', "").contains("fill-rule:evenodd");
+        let contours = parse_path_data(attribute(tag, "d").unwrap_or(""))?;
+        outlines.push(Outline {
+            contours: contours
+                .into_iter()
+                .map(|c| c.into_iter().map(|(x, y)| (x, -y)).collect())
+                .collect(),
+            fill_rule: if even_odd {
+                FillRule::EvenOdd
+            } else {
+                FillRule::NonZero
+            },
+        });
+    }
+    Ok(outlines)
+}
+
+pub fn load_svg(path: &Path) -> io::Result<Vec<Outline>> {
+    parse_svg(std::fs::File::open(path)?)
+}
+
+fn signed_area(contour: &[(Float, Float)]) -> Float {
+    let mut area = 0.0;
+    for (i, a) in contour.iter().enumerate() {
+        let b = contour[(i + 1) % contour.len()];
+        area += a.0 * b.1 - b.0 * a.1;
+    }
+    0.5 * area
+}
+
+/// Even-odd crossing test.
+fn contains(contour: &[(Float, Float)], p: (Float, Float)) -> bool {
+    let mut inside = false;
+    for (i, a) in contour.iter().enumerate() {
+        let b = contour[(i + 1) % contour.len()];
+        if (a.1 > p.1) != (b.1 > p.1) && p.0 < a.0 + (p.1 - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Twice the signed area of triangle `abc`; positive when counterclockwise.
+fn cross(a: (Float, Float), b: (Float, Float), c: (Float, Float)) -> Float {
+    (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+}
+
+fn in_triangle(p: (Float, Float), a: (Float, Float), b: (Float, Float), c: (Float, Float)) -> bool {
+    cross(a, b, p) >= 0.0 && cross(b, c, p) >= 0.0 && cross(c, a, p) >= 0.0
+}
+
+/// One solid region: a counterclockwise boundary with clockwise holes.
+struct Polygon {
+    contours: Vec<Vec<(Float, Float)>>,
+}
+
+impl Outline {
+    /// Splits the outline into solid regions. Each contour bounds a region
+    /// whose winding number is its own direction plus its parent's; regions
+    /// are solid or not by the fill rule, and walls go only where solid
+    /// meets empty.
+    fn polygons(&self) -> Vec<Polygon> {
+        let contours = &self.contours;
+        let areas: Vec<_> = contours.iter().map(|c| signed_area(c)).collect();
+        // the smallest contour around each one; contours do not cross, so
+        // testing one vertex is enough
+        let parents: Vec<Option<usize>> = (0..contours.len())
+            .map(|i| {
+                (0..contours.len())
+                    .filter(|j| *j != i && areas[*j].abs() > areas[i].abs())
+                    .filter(|j| contains(&contours[*j], contours[i][0]))
+                    .min_by(|a, b| areas[*a].abs().partial_cmp(&areas[*b].abs()).unwrap())
+            })
+            .collect();
+
+        // winding number and nesting depth just inside each contour
+        let mut winding = vec![None; contours.len()];
+        fn resolve(
+            i: usize,
+            parents: &[Option<usize>],
+            areas: &[Float],
+            winding: &mut [Option<(i32, u32)>],
+        ) -> (i32, u32) {
+            if let Some(w) = winding[i] {
+                return w;
+            }
+            let (w, depth) = match parents[i] {
+                Some(p) => resolve(p, parents, areas, winding),
+                None => (0, 0),
+            };
+            let result = (w + areas[i].signum() as i32, depth + 1);
+            winding[i] = Some(result);
+            result
+        }
+        let solid: Vec<bool> = (0..contours.len())
+            .map(|i| {
+                let (w, depth) = resolve(i, &parents, &areas, &mut winding);
+                match self.fill_rule {
+                    FillRule::NonZero => w != 0,
+                    FillRule::EvenOdd => depth % 2 == 1,
+                }
+            })
+            .collect();
+
+        // walk up from an empty contour to find the solid region it is a hole in
+        let hole_of = |mut i: usize| {
+            let p = parents[i]?;
+            if !solid[p] {
+                return None;
+            }
+            i = p;
+            while let Some(p) = parents[i].filter(|p| solid[*p]) {
+                i = p;
+            }
+            Some(i)
+        };
+
+        let oriented = |i: usize, counterclockwise: bool| {
+            let mut c = contours[i].clone();
+            if (areas[i] > 0.0) != counterclockwise {
+                c.reverse();
+            }
+            c
+        };
+        let mut polygons = Vec::new();
+        for i in 0..contours.len() {
+            if solid[i] && parents[i].is_none_or(|p| !solid[p]) {
+                let holes = (0..contours.len()).filter(|h| !solid[*h] && hole_of(*h) == Some(i));
+                let mut polygon = vec![oriented(i, true)];
+                polygon.extend(holes.map(|h| oriented(h, false)));
+                polygons.push(Polygon { contours: polygon });
+            }
+        }
+        polygons
+    }
+}
+
+impl Polygon {
+    /// Ear clipping, after joining each hole to the boundary with a pair of
+    /// coincident edges (Eberly's method). Indices run through the contours
+    /// in order.
+    fn triangulate(&self) -> Vec<[usize; 3]> {
+        let points: Vec<(Float, Float)> = self.contours.concat();
+        let mut ring: Vec<usize> = (0..self.contours[0].len()).collect();
+
+        let mut holes: Vec<Vec<usize>> = Vec::new();
+        let mut first = self.contours[0].len();
+        for hole in &self.contours[1..] {
+            holes.push((first..first + hole.len()).collect());
+            first += hole.len();
+        }
+        let max_x = |h: &Vec<usize>| {
+            h.iter()
+                .map(|i| points[*i].0)
+                .fold(Float::NEG_INFINITY, Float::max)
+        };
+        holes.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap());
+
+        for hole in holes {
+            let start = (0..hole.len())
+                .max_by(|a, b| points[hole[*a]].0.partial_cmp(&points[hole[*b]].0).unwrap())
+                .unwrap();
+            let m = points[hole[start]];
+
+            // the nearest boundary edge to the right of the hole
+            let mut best: Option<(Float, usize)> = None;
+            for k in 0..ring.len() {
+                let (a, b) = (points[ring[k]], points[ring[(k + 1) % ring.len()]]);
+                if (a.1 - m.1) * (b.1 - m.1) > 0.0 || a.1 == b.1 {
+                    continue;
+                }
+                let x = a.0 + (m.1 - a.1) / (b.1 - a.1) * (b.0 - a.0);
+                if x >= m.0 && best.is_none_or(|(best_x, _)| x < best_x) {
+                    best = Some((x, k));
+                }
+            }
+            let Some((x, k)) = best else {
+                // not inside the boundary after all; leave it out
+                continue;
+            };
+            let (a, b) = (k, (k + 1) % ring.len());
+            let mut visible = if points[ring[a]].0 > points[ring[b]].0 {
+                a
+            } else {
+                b
+            };
+            // a reflex vertex inside the triangle would block the bridge, so
+            // connect to the one closest in angle to the ray instead
+            let i = (x, m.1);
+            let p = points[ring[visible]];
+            let (t0, t1, t2) = if cross(m, i, p) >= 0.0 {
+                (m, i, p)
+            } else {
+                (m, p, i)
+            };
+            let mut best_cos = Float::NEG_INFINITY;
+            for (r, index) in ring.iter().enumerate() {
+                let q = points[*index];
+                let previous = points[ring[(r + ring.len() - 1) % ring.len()]];
+                let next = points[ring[(r + 1) % ring.len()]];
+                if r == visible || cross(previous, q, next) > 0.0 || q == m {
+                    continue;
+                }
+                if in_triangle(q, t0, t1, t2) {
+                    let d = (q.0 - m.0, q.1 - m.1);
+                    let cos = d.0 / (d.0 * d.0 + d.1 * d.1).sqrt();
+                    if cos > best_cos {
+                        best_cos = cos;
+                        visible = r;
+                    }
+                }
+            }
+
+            let mut bridged = ring[..=visible].to_vec();
+            bridged.extend((0..=hole.len()).map(|j| hole[(start + j) % hole.len()]));
+            bridged.extend_from_slice(&ring[visible..]);
+            ring = bridged;
+        }
+
+        let mut triangles = Vec::with_capacity(ring.len());
+        while ring.len() > 3 {
+            let n = ring.len();
+            let corner = |r: usize| {
+                (
+                    points[ring[(r + n - 1) % n]],
+                    points[ring[r]],
+                    points[ring[(r + 1) % n]],
+                )
+            };
+            let is_ear = |r: usize| {
+                let (a, b, c) = corner(r);
+                if cross(a, b, c) <= 0.0 {
+                    return false;
+                }
+                let triangle = [ring[(r + n - 1) % n], ring[r], ring[(r + 1) % n]];
+                ring.iter().all(|i| {
+                    let q = points[*i];
+                    // bridge vertices appear twice; their copies sit on the corners
+                    triangle.contains(i) || q == a || q == b || q == c || !in_triangle(q, a, b, c)
+                })
+            };
+            let r = match (0..n).find(|r| is_ear(*r)) {
+                Some(r) => r,
+                // only degenerate corners are left: drop the flattest one
+                None => (0..n)
+                    .min_by(|x, y| {
+                        let (a, b, c) = corner(*x);
+                        let (d, e, f) = corner(*y);
+                        cross(a, b, c)
+                            .abs()
+                            .partial_cmp(&cross(d, e, f).abs())
+                            .unwrap()
+                    })
+                    .unwrap(),
+            };
+            let (a, b, c) = corner(r);
+            if cross(a, b, c) > 0.0 {
+                triangles.push([ring[(r + n - 1) % n], ring[r], ring[(r + 1) % n]]);
+            }
+            ring.remove(r);
+        }
+        if ring.len() == 3 && cross(points[ring[0]], points[ring[1]], points[ring[2]]) > 0.0 {
+            triangles.push([ring[0], ring[1], ring[2]]);
+        }
+        triangles
+    }
+}
+
+/// Moves every vertex of a contour `distance` to its left, which is into the
+/// solid for both boundaries and holes. Sharp corners are limited so they do
+/// not shoot off.
+fn inset(contour: &[(Float, Float)], distance: Float) -> Vec<(Float, Float)> {
+    let n = contour.len();
+    let left = |a: (Float, Float), b: (Float, Float)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let length = (dx * dx + dy * dy).sqrt();
+        (-dy / length, dx / length)
+    };
+    (0..n)
+        .map(|i| {
+            let p = contour[i];
+            let n0 = left(contour[(i + n - 1) % n], p);
+            let n1 = left(p, contour[(i + 1) % n]);
+            // offset whose projection on both edge normals is one
+            let scale = 1.0 / (1.0 + n0.0 * n1.0 + n0.1 * n1.1).max(0.25);
+            (
+                p.0 + distance * scale * (n0.0 + n1.0),
+                p.1 + distance * scale * (n0.1 + n1.1),
+            )
+        })
+        .collect()
+}
+
+/// Builds a solid from the outlines: front and back faces filled in, walls
+/// around every boundary and hole, and a chamfer between them if there is a
+/// bevel. Faces and walls have separate vertices so the edges stay crisp.
+/// Faces take `uv` from the position scaled to the outlines' bounds; walls
+/// run `u` along the contour and `v` from back to front. The depth must be
+/// above zero.
+pub fn extrude(outlines: &[Outline], extrusion: &Extrusion) -> Mesh {
+    let depth = extrusion.depth;
+    assert!(depth > 0.0, "extrusions need a depth above zero");
+    let bevel = extrusion.bevel.clamp(0.0, 0.5 * depth);
+
+    let (mut min, mut max) = (
+        (Float::INFINITY, Float::INFINITY),
+        (Float::NEG_INFINITY, Float::NEG_INFINITY),
+    );
+    for p in outlines.iter().flat_map(|o| o.contours.iter().flatten()) {
+        min = (min.0.min(p.0), min.1.min(p.1));
+        max = (max.0.max(p.0), max.1.max(p.1));
+    }
+    let size = (max.0 - min.0).max(max.1 - min.1).max(Float::MIN_POSITIVE);
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut triangles = Vec::new();
+    let mut vertex = |p: (Float, Float), z: Float, n: Direction, uv: (Float, Float)| {
+        vertices.push(Point(Vec3([p.0, p.1, z])));
+        normals.push(n);
+        uvs.push(uv);
+        vertices.len() - 1
+    };
+
+    for polygon in outlines.iter().flat_map(|o| o.polygons()) {
+        let faces = polygon.triangulate();
+        let insets: Vec<_> = polygon.contours.iter().map(|c| inset(c, bevel)).collect();
+        let face_points = insets.concat();
+        let uv = |p: (Float, Float)| ((p.0 - min.0) / size, (p.1 - min.1) / size);
+
+        let up = Direction(Vec3([0.0, 0.0, 1.0]));
+        let front: Vec<_> = face_points
+            .iter()
+            .map(|p| vertex(*p, depth, up, uv(*p)))
+            .collect();
+        let back: Vec<_> = face_points
+            .iter()
+            .map(|p| vertex(*p, 0.0, -1.0 * up, uv(*p)))
+            .collect();
+        for [a, b, c] in faces {
+            triangles.push([front[a], front[b], front[c]]);
+            triangles.push([back[a], back[c], back[b]]);
+        }
+
+        for (contour, inner) in polygon.contours.iter().zip(&insets) {
+            let n = contour.len();
+            // outward normal of each edge: the solid is on the left
+            let outward: Vec<_> = (0..n)
+                .map(|i| {
+                    let (a, b) = (contour[i], contour[(i + 1) % n]);
+                    Direction(Vec3([b.1 - a.1, a.0 - b.0, 0.0])).normalized()
+                })
+                .collect();
+            let mut lengths = vec![0.0];
+            for i in 0..n {
+                let (a, b) = (contour[i], contour[(i + 1) % n]);
+                lengths.push(lengths[i] + ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt());
+            }
+            let perimeter = lengths[n];
+
+            // rings from back to front: where each sits, and how it leans
+            let rings = if bevel > 0.0 {
+                vec![
+                    (inner, 0.0, -1.0),
+                    (contour, bevel, 0.0),
+                    (contour, depth - bevel, 0.0),
+                    (inner, depth, 1.0),
+                ]
+            } else {
+                vec![(contour, 0.0, 0.0), (contour, depth, 0.0)]
+            };
+
+            for i in 0..n {
+                let j = (i + 1) % n;
+                // smooth across gentle turns so curves look round
+                let smoothed = |k: usize, other: usize| {
+                    if outward[k].dot(&outward[other]) > SMOOTH_CORNER {
+                        (outward[k] + outward[other]).normalized()
+                    } else {
+                        outward[k]
+                    }
+                };
+                let side = [smoothed(i, (i + n - 1) % n), smoothed(i, j)];
+                let u = [lengths[i] / perimeter, lengths[i + 1] / perimeter];
+
+                for band in rings.windows(2) {
+                    let [(bottom, z0, lean0), (top, z1, lean1)] = [band[0], band[1]];
+                    // a chamfer leans halfway between the wall and the face
+                    let lean = lean0 + lean1;
+                    let normal = |s: Direction| (s + lean * up).normalized();
+                    let a = vertex(bottom[i], z0, normal(side[0]), (u[0], z0 / depth));
+                    let b = vertex(bottom[j], z0, normal(side[1]), (u[1], z0 / depth));
+                    let c = vertex(top[j], z1, normal(side[1]), (u[1], z1 / depth));
+                    let d = vertex(top[i], z1, normal(side[0]), (u[0], z1 / depth));
+                    triangles.push([a, b, c]);
+                    triangles.push([a, c, d]);
+                }
+            }
+        }
+    }
+
+    Mesh::with_normals(vertices, normals, uvs, triangles)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn area(points: &[(Float, Float)], triangles: &[[usize; 3]]) -> Float {
+        triangles
+            .iter()
+            .map(|[a, b, c]| 0.5 * cross(points[*a], points[*b], points[*c]))
+            .sum()
+    }
+
+    fn hit(mesh: &Mesh, x: Float, y: Float) -> Option<Intersection> {
+        mesh.find_intersection(&Ray(
+            Point(Vec3([x, y, 5.0])),
+            Direction(Vec3([0.0, 0.0, -1.0])),
+        ))
+    }
+
+    #[test]
+    fn path_data() {
+        let square = parse_path_data("M0,0 L10,0 10,10 H0 z").unwrap();
+        assert_eq!(
+            vec![vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)]],
+            square
+        );
+
+        // relative commands, implicit linetos, compact numbers and two subpaths
+        let paths = parse_path_data("m1 1h2v2h-2zm5-1l1.5.5-1.5.5").unwrap();
+        assert_eq!(2, paths.len());
+        assert_eq!((3.0, 3.0), paths[0][2]);
+        assert_eq!(vec![(6.0, 0.0), (7.5, 0.5), (6.0, 1.0)], paths[1]);
+
+        // curves end where they should and bulge the right way
+        let curve =
+            parse_path_data("M0 0 C0 10 10 10 10 0 S20 -10 20 0 Q25 5 30 0 T40 0 Z").unwrap();
+        let c = &curve[0];
+        assert_eq!(1 + 4 * CURVE_SEGMENTS, c.len());
+        assert!((c[CURVE_SEGMENTS / 2].1 - 7.5).abs() < per_precision(1e-9, 1e-4));
+        assert!((c[3 * CURVE_SEGMENTS / 2].1 + 7.5).abs() < per_precision(1e-9, 1e-4));
+        assert!(
+            (c[2 * CURVE_SEGMENTS + CURVE_SEGMENTS / 2].1 - 2.5).abs() < per_precision(1e-9, 1e-4)
+        );
+        assert!(
+            (c[3 * CURVE_SEGMENTS + CURVE_SEGMENTS / 2].1 + 2.5).abs() < per_precision(1e-9, 1e-4)
+        );
+
+        assert!(parse_path_data("L 1 2").is_err());
+        assert!(parse_path_data("M 0 0 X").is_err());
+        assert!(parse_path_data("M 0 0 A 1 1 0 2 0 1 1").is_err());
+        assert!(parse_path_data("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn arcs() {
+        // two half circles make a circle of radius 5 around (5, 0)
+        let circle = parse_path_data("M0 0 A5 5 0 0 1 10 0 A5 5 0 0 1 0 0z").unwrap();
+        for p in &circle[0] {
+            assert!(
+                (((p.0 - 5.0).powi(2) + p.1 * p.1).sqrt() - 5.0).abs() < per_precision(1e-9, 1e-4)
+            );
+        }
+        assert!((signed_area(&circle[0]).abs() - 25.0 * PI).abs() < 2.0);
+        // the sweep flag picks the side: y-down, sweep 1 goes through negative y first
+        assert!(circle[0][4].1 < 0.0);
+
+        // radii too small are scaled up to a half circle
+        let half = parse_path_data("M0 0 A1 1 0 0 0 10 0").unwrap();
+        assert!(half[0].iter().all(|p| p.1 >= -1e-9));
+        assert!(half[0]
+            .iter()
+            .any(|p| (p.1 - 5.0).abs() < per_precision(1e-9, 1e-4)));
+    }
+
+    #[test]
+    fn fill_rules() {
+        // the same ring drawn both ways, and a second one wound like its boundary
+        let d = "M0 0 H10 V10 H0 Z M2 2 V8 H8 V2 Z M3 3 H7 V7 H3 Z";
+        let contours = parse_path_data(d).unwrap();
+        let outline = |fill_rule| Outline {
+            contours: contours.clone(),
+            fill_rule,
+        };
+
+        // even-odd: solid, hole, solid again
+        let polygons = outline(FillRule::EvenOdd).polygons();
+        assert_eq!(2, polygons.len());
+        assert_eq!(2, polygons[0].contours.len());
+        assert_eq!(1, polygons[1].contours.len());
+        let filled: Float = polygons
+            .iter()
+            .map(|p| area(&p.contours.concat(), &p.triangulate()))
+            .sum();
+        assert!((filled - (100.0 - 36.0 + 16.0)).abs() < per_precision(1e-9, 1e-4));
+
+        // nonzero: the middle square winds against the outer one and cancels
+        // it, and the inner one winds with it, so the result is the same
+        let polygons = outline(FillRule::NonZero).polygons();
+        assert_eq!(2, polygons.len());
+        let last = parse_path_data("M0 0 H10 V10 H0 Z M2 2 H8 V8 H2 Z").unwrap();
+        let same_way = Outline {
+            contours: last,
+            fill_rule: FillRule::NonZero,
+        }
+        .polygons();
+        // wound the same way, the inner square just adds to the solid
+        assert_eq!(1, same_way.len());
+        assert_eq!(1, same_way[0].contours.len());
+    }
+
+    #[test]
+    fn triangulation() {
+        // an L with a notch, so some corners are reflex
+        let l = vec![
+            (0.0, 0.0),
+            (4.0, 0.0),
+            (4.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 3.0),
+            (2.0, 3.0),
+            (2.0, 4.0),
+            (0.0, 4.0),
+        ];
+        let polygon = Polygon {
+            contours: vec![l.clone()],
+        };
+        let triangles = polygon.triangulate();
+        assert_eq!(l.len() - 2, triangles.len());
+        assert!((area(&l, &triangles) - signed_area(&l)).abs() < per_precision(1e-9, 1e-4));
+
+        // two holes side by side, so the second bridge has to get past the first
+        let outer = vec![(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        let hole = |x: Float| vec![(x, 4.0), (x, 6.0), (x + 2.0, 6.0), (x + 2.0, 4.0)];
+        let polygon = Polygon {
+            contours: vec![outer, hole(2.0), hole(6.0)],
+        };
+        let points = polygon.contours.concat();
+        let triangles = polygon.triangulate();
+        assert!((area(&points, &triangles) - 92.0).abs() < per_precision(1e-9, 1e-4));
+        assert!(triangles
+            .iter()
+            .all(|[a, b, c]| cross(points[*a], points[*b], points[*c]) > 0.0));
+    }
+
+    #[test]
+    fn extruded() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <path fill-rule="evenodd" d="M0 0 H10 V10 H0 Z M3 3 H7 V7 H3 Z"/>
+        </svg>"#;
+        let outlines = parse_svg(svg.as_bytes()).unwrap();
+        assert_eq!(1, outlines.len());
+        assert_eq!(FillRule::EvenOdd, outlines[0].fill_rule);
+        // y is flipped
+        assert!(outlines[0].contours[0].iter().all(|p| p.1 <= 0.0));
+
+        let mesh = extrude(
+            &outlines,
+            &Extrusion {
+                depth: 2.0,
+                bevel: 0.5,
+            },
+        );
+        let face = hit(&mesh, 1.5, -5.0).unwrap();
+        assert!((face.distance - 3.0).abs() < per_precision(1e-9, 1e-4));
+        assert!((face.surface_normal.0 .0[2] - 1.0).abs() < per_precision(1e-9, 1e-4));
+        assert!(face.front_face);
+
+        // the chamfer is lower and leans out
+        let chamfer = hit(&mesh, 0.25, -5.0).unwrap();
+        assert!((chamfer.distance - 3.25).abs() < per_precision(1e-9, 1e-4));
+        assert!(chamfer.surface_normal.0 .0[0] < -0.5);
+        assert!(chamfer.front_face);
+
+        // straight through the hole and past the outside
+        assert!(hit(&mesh, 5.0, -5.0).is_none());
+        assert!(hit(&mesh, 11.0, -5.0).is_none());
+
+        // from the side, the wall
+        let wall = mesh
+            .find_intersection(&Ray(
+                Point(Vec3([20.0, -5.0, 1.0])),
+                Direction(Vec3([-1.0, 0.0, 0.0])),
+            ))
+            .unwrap();
+        assert!((wall.distance - 10.0).abs() < per_precision(1e-9, 1e-4));
+        assert!((wall.surface_normal.0 .0[0] - 1.0).abs() < per_precision(1e-9, 1e-4));
+        assert!(wall.front_face);
+        assert!((wall.uv.1 - 0.5).abs() < per_precision(1e-9, 1e-4));
+    }
+
+    #[test]
+    fn attributes() {
+        let tag = r#" id="x" d='M0 0' style="fill-rule: evenodd" data-d="no""#;
+        assert_eq!(Some("M0 0"), attribute(tag, "d"));
+        assert_eq!(Some("x"), attribute(tag, "id"));
+        assert_eq!(None, attribute(tag, "fill"));
+        let outlines = parse_svg(format!("<path{}/>", tag).as_bytes()).unwrap();
+        assert_eq!(FillRule::EvenOdd, outlines[0].fill_rule);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::consts::PI;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::mesh::Mesh;
+use crate::*;
+
+/// Samples per spline span when tessellating, along both profile and path.
+pub const STEPS: usize = 8;
+/// Slices around the axis of a lathe.
+pub const SEGMENTS: usize = 64;
+
+/// 2D Catmull-Rom spline through `points`. Sharp corners need a point
+/// repeated on either side of them.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub points: Vec<(Float, Float)>,
+    /// Joins the last point back to the first.
+    pub closed: bool,
+}
+
+/// One tessellation sample: position, direction of travel, and the spline
+/// parameter scaled to `[0, 1]`.
+struct Sample {
+    position: Vec3,
+    tangent: Vec3,
+    v: Float,
+}
+
+/// Position and derivative of the uniform Catmull-Rom spline through
+/// `points` at `t`, which runs from 0 to the number of spans. Open splines
+/// extend their end segments to get the missing neighbours.
+fn catmull_rom(points: &[Vec3], closed: bool, t: Float) -> (Vec3, Vec3) {
+    let n = points.len() as i64;
+    let spans = if closed { n } else { n - 1 };
+    let i = (t.floor() as i64).clamp(0, spans - 1);
+    let s = t - i as Float;
+    let point = |k: i64| {
+        if closed {
+            points[k.rem_euclid(n) as usize]
+        } else if k < 0 {
+            2.0 * points[0] - points[1]
+        } else if k >= n {
+            2.0 * points[n as usize - 1] - points[n as usize - 2]
+        } else {
+            points[k as usize]
+        }
+    };
+    let [p0, p1, p2, p3] = [i - 1, i, i + 1, i + 2].map(point);
+
+    let a = p2 - p0;
+    let b = 2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3;
+    let c = 3.0 * (p1 - p2) + p3 - p0;
+    let position = 0.5 * (2.0 * p1 + s * a + (s * s) * b + (s * s * s) * c);
+    let derivative = 0.5 * (a + (2.0 * s) * b + (3.0 * s * s) * c);
+    (position, derivative)
+}
+
+/// `steps` samples per span along the spline, ends included. Where the
+/// derivative vanishes (at repeated points) the tangent comes from the
+/// neighbouring samples instead.
+fn samples(points: &[Vec3], closed: bool, steps: usize) -> Vec<Sample> {
+    let spans = if closed {
+        points.len()
+    } else {
+        points.len() - 1
+    };
+    let count = spans * steps;
+    let mut samples: Vec<_> = (0..=count)
+        .map(|k| {
+            let (position, tangent) = catmull_rom(points, closed, k as Float / steps as Float);
+            Sample {
+                position,
+                tangent,
+                v: k as Float / count as Float,
+            }
+        })
+        .collect();
+    for k in 0..samples.len() {
+        if samples[k].tangent.magnitude() < 1e-12 {
+            let before = samples[k.saturating_sub(1)].position;
+            let after = samples[(k + 1).min(count)].position;
+            samples[k].tangent = after - before;
+        }
+    }
+    samples
+}
+
+/// Triangles over a grid of `rows` by `cols` vertices stored row by row,
+/// wound so the normal is the row direction crossed with the column direction.
+fn grid_triangles(rows: usize, cols: usize) -> Vec<[usize; 3]> {
+    let mut triangles = Vec::with_capacity(2 * (rows - 1) * (cols - 1));
+    for i in 0..rows - 1 {
+        for j in 0..cols - 1 {
+            let a = i * cols + j;
+            let b = a + cols;
+            triangles.push([a, b, b + 1]);
+            triangles.push([a, b + 1, a + 1]);
+        }
+    }
+    triangles
+}
+
+impl Profile {
+    fn to_vec3(&self) -> Vec<Vec3> {
+        self.points
+            .iter()
+            .map(|(x, y)| Vec3([*x, *y, 0.0]))
+            .collect()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.points.len() >= if self.closed { 3 } else { 2 }
+    }
+}
+
+/// Revolves the profile, read as `(radius, height)`, once around the z axis.
+/// `u` goes around the axis and `v` follows the profile. Normals are on the
+/// right of the profile's direction of travel, so a profile drawn upward
+/// faces away from the axis.
+pub fn lathe(profile: &Profile, steps: usize, segments: usize) -> Mesh {
+    assert!(
+        profile.is_valid(),
+        "lathe profiles need at least two points"
+    );
+    let samples = samples(&profile.to_vec3(), profile.closed, steps);
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    // the seam is stored twice so u can run all the way to 1
+    for i in 0..=segments {
+        let u = i as Float / segments as Float;
+        let (sin, cos) = (2.0 * PI * u).sin_cos();
+        for s in &samples {
+            let [r, z, _] = s.position.0;
+            let [dr, dz, _] = s.tangent.0;
+            vertices.push(Point(Vec3([r * cos, r * sin, z])));
+            normals.push(Direction(Vec3([dz * cos, dz * sin, -dr])).normalized());
+            uvs.push((u, s.v));
+        }
+    }
+
+    let triangles = grid_triangles(segments + 1, samples.len());
+    Mesh::with_normals(vertices, normals, uvs, triangles)
+}
+
+/// Frames along the path samples that twist as little as possible, by the
+/// double reflection method of Wang et al. Returns `(normal, binormal)` pairs.
+fn rotation_minimizing_frames(path: &[Sample]) -> Vec<(Direction, Direction)> {
+    let t0 = Direction(path[0].tangent).normalized();
+    let mut r = t0.orthonormal_basis().0;
+    let mut frames = vec![(r, t0.cross(&r))];
+    for w in path.windows(2) {
+        let t = Direction(w[0].tangent).normalized();
+        let next_t = Direction(w[1].tangent).normalized();
+        // reflect across the plane between the two samples...
+        let v1 = Direction(w[1].position - w[0].position);
+        let c1 = v1.dot(&v1);
+        let (r_l, t_l) = if c1 > 0.0 {
+            (
+                r - (2.0 / c1 * v1.dot(&r)) * v1,
+                t - (2.0 / c1 * v1.dot(&t)) * v1,
+            )
+        } else {
+            (r, t)
+        };
+        // ...then across the one that lines the reflected tangent up again
+        let v2 = next_t - t_l;
+        let c2 = v2.dot(&v2);
+        r = if c2 > 0.0 {
+            r_l - (2.0 / c2 * v2.dot(&r_l)) * v2
+        } else {
+            r_l
+        }
+        .normalized();
+        frames.push((r, next_t.cross(&r)));
+    }
+    frames
+}
+
+/// Moves the profile along the path, a Catmull-Rom spline through at least
+/// two points, keeping it perpendicular to the path. The profile's x and y
+/// follow the frame's normal and binormal. `u` follows the path and `v` the
+/// profile; a counterclockwise profile faces outward. The ends are left open.
+pub fn sweep(profile: &Profile, path: &[Point], steps: usize) -> Mesh {
+    assert!(
+        profile.is_valid(),
+        "sweep profiles need at least two points"
+    );
+    assert!(path.len() >= 2, "sweep paths need at least two points");
+    let path: Vec<_> = path.iter().map(|p| p.0).collect();
+    let path = samples(&path, false, steps);
+    let frames = rotation_minimizing_frames(&path);
+    let profile = samples(&profile.to_vec3(), profile.closed, steps);
+
+    let mut vertices = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    for s in &profile {
+        let [x, y, _] = s.position.0;
+        let [dx, dy, _] = s.tangent.0;
+        for (p, (n, b)) in path.iter().zip(&frames) {
+            vertices.push(Point(p.position) + x * n + y * b);
+            normals.push((dy * n - dx * b).normalized());
+            uvs.push((p.v, s.v));
+        }
+    }
+
+    let triangles = grid_triangles(profile.len(), path.len());
+    Mesh::with_normals(vertices, normals, uvs, triangles)
+}
+
+/// Parses a profile and, for sweeps, a path, one entry per line: `x y`
+/// profile points, `path x y z` path points, and `closed` to close the
+/// profile. Blank lines and lines starting with `#` are skipped.
+pub fn parse_sweep(reader: impl Read) -> io::Result<(Profile, Vec<Point>)> {
+    let invalid = |line: usize, msg: String| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("line {}: {}", line, msg),
+        )
+    };
+
+    let mut profile = Profile::default();
+    let mut path = Vec::new();
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let line_number = line_number + 1;
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        let (is_path, tokens) = match tokens.first() {
+            None => continue,
+            Some(t) if t.starts_with('#') => continue,
+            Some(&"closed") if tokens.len() == 1 => {
+                profile.closed = true;
+                continue;
+            }
+            Some(&"path") => (true, &tokens[1..]),
+            Some(_) => (false, &tokens[..]),
+        };
+        let numbers = tokens
+            .iter()
+            .map(|t| {
+                t.parse::<Float>()
+                    .map_err(|e| invalid(line_number, format!("bad number '{}': {}", t, e)))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        match (is_path, numbers.as_slice()) {
+            (false, [x, y]) => profile.points.push((*x, *y)),
+            (true, [x, y, z]) => path.push(Point(Vec3([*x, *y, *z]))),
+            _ => {
+                return Err(invalid(
+                    line_number,
+                    "expected '<x> <y>', 'path <x> <y> <z>' or 'closed'".to_owned(),
+                ))
+            }
+        }
+    }
+
+    if !profile.is_valid() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the profile needs at least two points, or three when closed".to_owned(),
+        ));
+    }
+    Ok((profile, path))
+}
+
+pub fn load_sweep(path: &Path) -> io::Result<(Profile, Vec<Point>)> {
+    parse_sweep(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn square() -> Profile {
+        // repeated corners keep the sides straight
+        let corners = [(0.5, -0.5), (0.5, 0.5), (-0.5, 0.5), (-0.5, -0.5)];
+        Profile {
+            points: corners.iter().flat_map(|c| [*c, *c]).collect(),
+            closed: true,
+        }
+    }
+
+    #[test]
+    fn spline() {
+        let points = [[0.0, 0.0], [1.0, 1.0], [2.0, 0.0]].map(|[x, y]| Vec3([x, y, 0.0]));
+        // passes through its points
+        for (i, p) in points.iter().enumerate() {
+            let (q, _) = catmull_rom(&points, false, i as Float);
+            assert!((q - *p).magnitude() < 1e-12);
+        }
+        let (_, d) = catmull_rom(&points, false, 1.0);
+        assert!((d - Vec3([1.0, 0.0, 0.0])).magnitude() < 1e-12);
+        let s = samples(&points, true, 4);
+        assert_eq!(13, s.len());
+        assert!((s[12].position - s[0].position).magnitude() < 1e-12);
+    }
+
+    #[test]
+    fn cylinder() {
+        let profile = Profile {
+            points: vec![(1.0, 0.0), (1.0, 1.0), (1.0, 2.0)],
+            closed: false,
+        };
+        let cylinder = lathe(&profile, STEPS, SEGMENTS);
+        let r = Ray(
+            Point(Vec3([5.0, 0.01, 1.0])),
+            Direction(Vec3([-1.0, 0.0, 0.0])),
+        );
+        let i = cylinder.find_intersection(&r).unwrap();
+        // the chords of the polygon sit slightly inside the circle
+        assert!((i.distance - 4.0).abs() < 1e-2);
+        assert!(i.front_face);
+        assert!((i.surface_normal.0 .0[0] - 1.0).abs() < 1e-3);
+        assert!((i.uv.1 - 0.5).abs() < 1e-9);
+        assert!(i.uv.0 < 0.01);
+
+        // from the inside we see the back faces
+        let r = Ray(
+            Point(Vec3([0.0, 0.0, 1.5])),
+            Direction(Vec3([0.0, 1.0, 0.0])),
+        );
+        let i = cylinder.find_intersection(&r).unwrap();
+        assert!(!i.front_face);
+        assert!((i.uv.0 - 0.25).abs() < 0.02);
+        assert!((i.uv.1 - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn frames() {
+        // a quarter circle in the xy plane; the frames must not twist out of it
+        let path: Vec<_> = (0..=8)
+            .map(|k| {
+                let a = 0.5 * PI * k as Float / 8.0;
+                Vec3([a.cos(), a.sin(), 0.0])
+            })
+            .collect();
+        let path = samples(&path, false, STEPS);
+        let frames = rotation_minimizing_frames(&path);
+        let (first, _) = frames[0];
+        assert!((first.0 .0[2].abs() - 1.0).abs() < 1e-9);
+        for (s, (n, b)) in path.iter().zip(frames) {
+            let t = Direction(s.tangent).normalized();
+            assert!(n.dot(&t).abs() < 1e-9);
+            assert!(b.dot(&t).abs() < 1e-9);
+            // the first normal is out of the plane, so it stays that way
+            assert!((n.dot(&first) - 1.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn swept_square() {
+        let path = [Point::origin(), Point(Vec3([0.0, 0.0, 4.0]))];
+        let beam = sweep(&square(), &path, STEPS);
+        let (n, b) = Direction(Vec3([0.0, 0.0, 1.0])).orthonormal_basis();
+        // aim at the middle of the side at profile x = 0.5
+        let side = Point(Vec3([0.0, 0.0, 1.0])) + 0.5 * n + 0.1 * b;
+        let r = Ray(side + 3.0 * n, -1.0 * n);
+        let i = beam.find_intersection(&r).unwrap();
+        assert!((i.distance - 3.0).abs() < 1e-9);
+        assert!(i.front_face);
+        assert!((i.surface_normal.dot(&n) - 1.0).abs() < 1e-9);
+        assert!((i.uv.0 - 0.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parse() {
+        let text = "# vase\n1 0\n1.5 1\n0.5 2\n";
+        let (profile, path) = parse_sweep(text.as_bytes()).unwrap();
+        assert_eq!(vec![(1.0, 0.0), (1.5, 1.0), (0.5, 2.0)], profile.points);
+        assert!(!profile.closed);
+        assert!(path.is_empty());
+
+        let text = "closed\n0 0\n1 0\n0 1\npath 0 0 0\npath 0 0 1\n";
+        let (profile, path) = parse_sweep(text.as_bytes()).unwrap();
+        assert!(profile.closed);
+        assert_eq!(Point(Vec3([0.0, 0.0, 1.0])), path[1]);
+
+        assert!(parse_sweep("1 0\n".as_bytes()).is_err());
+        assert!(parse_sweep("1 0\n2 0\npath 1 2\n".as_bytes()).is_err());
+    }
+}
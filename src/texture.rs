@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use crate::*;
+
+/// Something that can be looked up by surface coordinates or position.
+/// Image textures use `uv`; procedural ones use the point in space, so they
+/// work on geometry without texture coordinates.
+#[derive(Clone, Debug)]
+pub enum Texture {
+    /// Bilinearly filtered, repeating outside `[0, 1]`. `v` runs up the image.
+    Image(image::Rgb32FImage),
+    /// Alternating black and white cubes of side `size`.
+    Checker { size: Float },
+    /// Smooth value noise with features roughly `1 / frequency` across.
+    Noise { frequency: Float },
+}
+
+/// Hashes a lattice point to a value in `[0, 1]`.
+fn lattice(x: i64, y: i64, z: i64) -> Float {
+    let mut h =
+        (x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ z.wrapping_mul(83492791)) as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^= h >> 33;
+    (h >> 11) as Float / (1u64 << 53) as Float
+}
+
+fn value_noise(p: Vec3) -> Float {
+    let cell = p.0.map(|c| c.floor());
+    // smoothstep the fractional part so the noise has no creases at cell edges
+    let f = [0, 1, 2].map(|i| {
+        let t = p.0[i] - cell[i];
+        t * t * (3.0 - 2.0 * t)
+    });
+    let [x, y, z] = cell.map(|c| c as i64);
+
+    let mut result = 0.0;
+    for corner in 0..8 {
+        let (dx, dy, dz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+        let weight = (if dx == 1 { f[0] } else { 1.0 - f[0] })
+            * (if dy == 1 { f[1] } else { 1.0 - f[1] })
+            * (if dz == 1 { f[2] } else { 1.0 - f[2] });
+        result += weight * lattice(x + dx, y + dy, z + dz);
+    }
+    result
+}
+
+impl Texture {
+    pub fn load(path: &Path) -> image::ImageResult<Texture> {
+        Ok(Texture::Image(image::open(path)?.to_rgb32f()))
+    }
+
+    pub fn sample(&self, uv: (Float, Float), p: Point) -> Color {
+        match self {
+            Texture::Image(img) => {
+                let (w, h) = img.dimensions();
+                let x = uv.0.rem_euclid(1.0) * w as Float - 0.5;
+                let y = (1.0 - uv.1.rem_euclid(1.0)) * h as Float - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let texel = |dx: Float, dy: Float| {
+                    let px = img.get_pixel(
+                        (x0 + dx).rem_euclid(w as Float) as u32,
+                        (y0 + dy).rem_euclid(h as Float) as u32,
+                    );
+                    Color(px[0] as Float, px[1] as Float, px[2] as Float)
+                };
+                (1.0 - fy) * ((1.0 - fx) * texel(0.0, 0.0) + fx * texel(1.0, 0.0))
+                    + fy * ((1.0 - fx) * texel(0.0, 1.0) + fx * texel(1.0, 1.0))
+            }
+            Texture::Checker { size } => {
+                let parity: i64 = p.0 .0.iter().map(|c| (c / size).floor() as i64).sum();
+                if parity.rem_euclid(2) == 0 {
+                    WHITE
+                } else {
+                    BLACK
+                }
+            }
+            Texture::Noise { frequency } => {
+                let n = value_noise(*frequency * p.0);
+                Color(n, n, n)
+            }
+        }
+    }
+
+    /// Average of the channels, for textures used as a single value.
+    pub fn sample_scalar(&self, uv: (Float, Float), p: Point) -> Float {
+        let c = self.sample(uv, p);
+        (c.0 + c.1 + c.2) / 3.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn image() {
+        let mut img = image::Rgb32FImage::new(2, 1);
+        img.put_pixel(1, 0, image::Rgb([1.0, 0.5, 0.0]));
+        let texture = Texture::Image(img);
+        let p = Point::origin();
+        assert_eq!(BLACK, texture.sample((0.25, 0.5), p));
+        assert_eq!(Color(1.0, 0.5, 0.0), texture.sample((0.75, 0.5), p));
+        // halfway between the texel centers, and wrapping around the edge
+        assert_eq!(Color(0.5, 0.25, 0.0), texture.sample((0.5, 0.5), p));
+        assert_eq!(Color(0.5, 0.25, 0.0), texture.sample((1.0, 0.5), p));
+    }
+
+    #[test]
+    fn checker() {
+        let texture = Texture::Checker { size: 1.0 };
+        assert_eq!(
+            WHITE,
+            texture.sample((0.0, 0.0), Point(Vec3([0.5, 0.5, 0.5])))
+        );
+        assert_eq!(
+            BLACK,
+            texture.sample((0.0, 0.0), Point(Vec3([1.5, 0.5, 0.5])))
+        );
+        assert_eq!(
+            BLACK,
+            texture.sample((0.0, 0.0), Point(Vec3([-0.5, 0.5, 0.5])))
+        );
+    }
+
+    #[test]
+    fn noise() {
+        let texture = Texture::Noise { frequency: 3.0 };
+        let a = texture.sample_scalar((0.0, 0.0), Point(Vec3([0.1, 0.2, 0.3])));
+        let b = texture.sample_scalar((0.0, 0.0), Point(Vec3([0.1001, 0.2, 0.3])));
+        assert!((0.0..=1.0).contains(&a));
+        assert!((a - b).abs() < 0.01);
+    }
+}
@@ -3,8 +3,35 @@ use std::{
     ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign},
 };
 
+/// The float type the renderer computes in: `f64`, or `f32` with the `f32`
+/// feature, which halves the size of meshes and acceleration structures at
+/// the cost of precision.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(feature = "f32")]
+pub use std::f32::consts;
+/// Constants such as π, in [`Float`] precision.
+#[cfg(not(feature = "f32"))]
+pub use std::f64::consts;
+
+/// `f64_value` or `f32_value`, whichever suits the precision, for the odd
+/// threshold that one float type cannot honour, or needlessly wastes in the
+/// other.
+#[cfg(not(feature = "f32"))]
+pub const fn per_precision(f64_value: f64, _f32_value: f32) -> Float {
+    f64_value
+}
+#[cfg(feature = "f32")]
+pub const fn per_precision(_f64_value: f64, f32_value: f32) -> Float {
+    f32_value
+}
+
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
-pub struct Vec3(pub [f64; 3]);
+#[repr(transparent)]
+pub struct Vec3(pub [Float; 3]);
 
 impl Debug for Vec3 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -13,11 +40,11 @@ impl Debug for Vec3 {
 }
 
 impl Vec3 {
-    pub fn magnitude(&self) -> f64 {
+    pub fn magnitude(&self) -> Float {
         (self.0[0] * self.0[0] + self.0[1] * self.0[1] + self.0[2] * self.0[2]).sqrt()
     }
 
-    pub fn normalize(&mut self) -> () {
+    pub fn normalize(&mut self) {
         let mag = self.magnitude();
         self.0[0] /= mag;
         self.0[1] /= mag;
@@ -28,6 +55,17 @@ impl Vec3 {
         self.normalize();
         self
     }
+
+    pub fn abs(&self) -> Self {
+        Vec3(self.0.map(Float::abs))
+    }
+}
+
+/// Bound on the relative error built up by `n` floating-point operations,
+/// each of which may round by half an ulp.
+pub fn gamma(n: u32) -> Float {
+    let e = n as Float * 0.5 * Float::EPSILON;
+    e / (1.0 - e)
 }
 
 impl AddAssign for Vec3 {
@@ -66,25 +104,25 @@ impl Sub for Vec3 {
     }
 }
 
-impl MulAssign<f64> for Vec3 {
-    fn mul_assign(&mut self, rhs: f64) {
+impl MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, rhs: Float) {
         self.0[0] *= rhs;
         self.0[1] *= rhs;
         self.0[2] *= rhs;
     }
 }
 
-impl Mul<f64> for Vec3 {
+impl Mul<Float> for Vec3 {
     type Output = Vec3;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         let mut result = self;
         result *= rhs;
         result
     }
 }
 
-impl Mul<Vec3> for f64 {
+impl Mul<Vec3> for Float {
     type Output = Vec3;
     fn mul(self, rhs: Vec3) -> Vec3 {
         Vec3([self * rhs.0[0], self * rhs.0[1], self * rhs.0[2]])
@@ -92,6 +130,7 @@ impl Mul<Vec3> for f64 {
 }
 
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[repr(transparent)]
 pub struct Point(pub Vec3);
 
 impl Point {
@@ -131,6 +170,7 @@ impl Sub for Point {
 }
 
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
+#[repr(transparent)]
 pub struct Direction(pub Vec3);
 
 impl Debug for Direction {
@@ -144,7 +184,7 @@ impl Direction {
         Direction(Vec3([0.0, 0.0, 0.0]))
     }
 
-    pub fn dot(&self, rhs: &Self) -> f64 {
+    pub fn dot(&self, rhs: &Self) -> Float {
         self.0.0[0] * rhs.0.0[0] + self.0.0[1] * rhs.0.0[1] + self.0.0[2] * rhs.0.0[2]
     }
 
@@ -156,10 +196,36 @@ impl Direction {
         ]))
     }
 
+    /// Two unit directions perpendicular to this (unit) direction and to each other.
+    pub fn orthonormal_basis(&self) -> (Self, Self) {
+        let v = self.0 .0;
+        let x = if v[0].abs() > v[1].abs() {
+            Direction(Vec3([-v[2], 0.0, v[0]]))
+        } else {
+            Direction(Vec3([0.0, v[2], -v[1]]))
+        }
+        .normalized();
+        (x, self.cross(&x))
+    }
+
     pub fn reflect(&self, normal: &Self) -> Self {
         *self - 2.0 * (self.dot(normal)) * normal
     }
 
+    /// This (unit) direction bent by Snell's law as it crosses a surface with
+    /// the unit `normal` on its side, from a medium with `eta` times the
+    /// index of refraction of the one beyond. `None` if it cannot cross but
+    /// is totally internally reflected.
+    pub fn refract(&self, normal: &Self, eta: Float) -> Option<Self> {
+        let cos_i = -self.dot(normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+        if sin2_t >= 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(eta * *self + (eta * cos_i - cos_t) * *normal)
+    }
+
     pub fn mirror(&self, normal: &Self) -> Self {
         // https://mathworld.wolfram.com/Reflection.html
         // dbg!(&self);
@@ -171,7 +237,7 @@ impl Direction {
         *self + 2.0 * to_normal
     }
 
-    pub fn normalize(&mut self) -> () {
+    pub fn normalize(&mut self) {
         let mag = self.0.magnitude();
         self.0.0[0] /= mag;
         self.0.0[1] /= mag;
@@ -200,23 +266,23 @@ impl Sub<Direction> for Direction {
     }
 }
 
-impl Mul<f64> for Direction {
+impl Mul<Float> for Direction {
     type Output = Self;
 
-    fn mul(self, rhs: f64) -> Self::Output {
+    fn mul(self, rhs: Float) -> Self::Output {
         Direction(rhs * self.0)
     }
 }
 
-impl Mul<Direction> for f64 {
+impl Mul<Direction> for Float {
     type Output = Direction;
 
     fn mul(self, rhs: Direction) -> Self::Output {
-        self * (&rhs)
+        Direction(self * rhs.0)
     }
 }
 
-impl Mul<&Direction> for f64 {
+impl Mul<&Direction> for Float {
     type Output = Direction;
 
     fn mul(self, rhs: &Direction) -> Self::Output {
@@ -270,6 +336,27 @@ mod test {
         );
     }
 
+    #[test]
+    fn refract() {
+        let normal = Direction(Vec3([0.0, 0.0, 1.0]));
+        let into_glass = Direction(Vec3([1.0, 0.0, -1.0])).normalized();
+        let bent = into_glass.refract(&normal, 1.0 / 1.5).unwrap();
+        assert!((bent.0.magnitude() - 1.0).abs() < 1e-6);
+        // Snell: n1 sin(t1) = n2 sin(t2), in the plane of incidence
+        assert!((bent.0.0[0] * 1.5 - into_glass.0.0[0]).abs() < 1e-6);
+        assert!(bent.0.0[1] == 0.0 && bent.0.0[2] < 0.0);
+        // head on, straight through
+        let down = Direction(Vec3([0.0, 0.0, -1.0]));
+        assert_eq!(Some(down), down.refract(&normal, 1.0 / 1.5));
+
+        // back out, past the critical angle of about 42 degrees
+        let out = Direction(Vec3([0.6, 0.0, 0.8]));
+        let inner = Direction(Vec3([0.0, 0.0, -1.0]));
+        assert!(out.refract(&inner, 1.5).is_some());
+        let grazing = Direction(Vec3([0.8, 0.0, 0.6]));
+        assert_eq!(None, grazing.refract(&inner, 1.5));
+    }
+
     #[test]
     fn reflect() {
         assert_eq!(
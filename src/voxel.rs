@@ -0,0 +1,626 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::*;
+
+/// Side of the cubic chunks sparse grids are stored in.
+pub const CHUNK: usize = 16;
+const CHUNK_VOLUME: usize = CHUNK * CHUNK * CHUNK;
+type Chunk = [u8; CHUNK_VOLUME];
+
+/// Voxel values, one byte each: 0 is empty and anything else indexes the palette.
+#[derive(Clone, Debug)]
+pub enum Voxels {
+    /// Every voxel stored, x fastest, then y, then z.
+    Dense(Vec<u8>),
+    /// Only the chunks with something in them, keyed by chunk coordinates.
+    Sparse(HashMap<[usize; 3], Box<Chunk>>),
+}
+
+/// Box of unit cubes, each empty or filled with a palette color, stepped
+/// through with a 3D-DDA (Amanatides and Woo). Hits report the voxel's linear
+/// index as their `primitive` and the face's own coordinates as `uv`.
+#[derive(Clone, Debug)]
+pub struct VoxelGrid {
+    pub size: [usize; 3],
+    /// Corner of voxel `[0, 0, 0]`.
+    pub origin: Point,
+    pub voxel_size: Float,
+    /// Colors by voxel value. Entry 0 is never shown.
+    pub palette: Vec<Color>,
+    voxels: Voxels,
+}
+
+/// Gray ramp, for data without colors of its own.
+pub fn grayscale_palette() -> Vec<Color> {
+    (0..256)
+        .map(|i| {
+            let v = i as Float / 255.0;
+            Color(v, v, v)
+        })
+        .collect()
+}
+
+impl VoxelGrid {
+    pub fn dense(size: [usize; 3]) -> VoxelGrid {
+        VoxelGrid {
+            size,
+            origin: Point::origin(),
+            voxel_size: 1.0,
+            palette: grayscale_palette(),
+            voxels: Voxels::Dense(vec![0; size[0] * size[1] * size[2]]),
+        }
+    }
+
+    pub fn sparse(size: [usize; 3]) -> VoxelGrid {
+        VoxelGrid {
+            size,
+            voxels: Voxels::Sparse(HashMap::new()),
+            ..VoxelGrid::dense([0; 3])
+        }
+    }
+
+    pub fn voxels(&self) -> &Voxels {
+        &self.voxels
+    }
+
+    fn index(&self, [x, y, z]: [usize; 3]) -> usize {
+        x + self.size[0] * (y + self.size[1] * z)
+    }
+
+    fn split(p: [usize; 3]) -> ([usize; 3], usize) {
+        let chunk = p.map(|c| c / CHUNK);
+        let [x, y, z] = p.map(|c| c % CHUNK);
+        (chunk, x + CHUNK * (y + CHUNK * z))
+    }
+
+    /// Value of the voxel at `p`; 0 outside the grid.
+    pub fn get(&self, p: [usize; 3]) -> u8 {
+        if (0..3).any(|i| p[i] >= self.size[i]) {
+            return 0;
+        }
+        match &self.voxels {
+            Voxels::Dense(v) => v[self.index(p)],
+            Voxels::Sparse(chunks) => {
+                let (chunk, i) = VoxelGrid::split(p);
+                chunks.get(&chunk).map_or(0, |c| c[i])
+            }
+        }
+    }
+
+    pub fn set(&mut self, p: [usize; 3], value: u8) {
+        assert!(
+            (0..3).all(|i| p[i] < self.size[i]),
+            "voxel {:?} outside the grid",
+            p
+        );
+        let index = self.index(p);
+        match &mut self.voxels {
+            Voxels::Dense(v) => v[index] = value,
+            Voxels::Sparse(chunks) => {
+                let (chunk, i) = VoxelGrid::split(p);
+                if value != 0 {
+                    chunks
+                        .entry(chunk)
+                        .or_insert_with(|| Box::new([0; CHUNK_VOLUME]))[i] = value;
+                } else if let Some(c) = chunks.get_mut(&chunk) {
+                    c[i] = 0;
+                    if c.iter().all(|v| *v == 0) {
+                        chunks.remove(&chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The same voxels, stored in chunks.
+    pub fn into_sparse(self) -> VoxelGrid {
+        let mut sparse = VoxelGrid {
+            voxels: Voxels::Sparse(HashMap::new()),
+            ..self.clone()
+        };
+        for z in 0..self.size[2] {
+            for y in 0..self.size[1] {
+                for x in 0..self.size[0] {
+                    let value = self.get([x, y, z]);
+                    if value != 0 {
+                        sparse.set([x, y, z], value);
+                    }
+                }
+            }
+        }
+        sparse
+    }
+
+    fn grid_bounds(&self) -> Aabb {
+        let extent = Direction(Vec3(self.size.map(|s| s as Float * self.voxel_size)));
+        Aabb {
+            min: self.origin,
+            max: self.origin + extent,
+        }
+    }
+}
+
+impl Shape for VoxelGrid {
+    fn find_intersection(&self, r: &Ray) -> Option<Intersection> {
+        let bounds = self.grid_bounds();
+        let (t_near, t_far) = bounds.intersect(r)?;
+        if self.size.contains(&0) {
+            return None;
+        }
+        let o = r.0 .0 .0;
+        let d = r.1 .0 .0;
+        let min = self.origin.0 .0;
+        let size = self.size.map(|s| s as i64);
+
+        let start = (r.0 + t_near * r.1 - self.origin).0 .0;
+        let mut cell =
+            [0, 1, 2].map(|a| ((start[a] / self.voxel_size).floor() as i64).clamp(0, size[a] - 1));
+        let step = d.map(|d| if d > 0.0 { 1 } else { -1 });
+        // distance to the next voxel boundary on each axis, and between them
+        let mut t_max = [0, 1, 2].map(|a| {
+            if d[a] == 0.0 {
+                return Float::INFINITY;
+            }
+            let next = cell[a] + if step[a] > 0 { 1 } else { 0 };
+            (min[a] + next as Float * self.voxel_size - o[a]) / d[a]
+        });
+        let t_delta = d.map(|d| self.voxel_size / d.abs());
+
+        // the face the ray came in through, unless it starts inside the grid
+        let mut axis = (t_near > 0.0).then(|| {
+            let entry = [0, 1, 2].map(|a| {
+                if d[a] == 0.0 {
+                    return Float::NEG_INFINITY;
+                }
+                let face = if d[a] > 0.0 {
+                    bounds.min.0 .0[a]
+                } else {
+                    bounds.max.0 .0[a]
+                };
+                (face - o[a]) / d[a]
+            });
+            (0..3)
+                .max_by(|a, b| entry[*a].partial_cmp(&entry[*b]).unwrap())
+                .unwrap()
+        });
+
+        // sparse grids look chunks up once, not once per voxel
+        let chunk_cache: Cell<Option<([usize; 3], Option<&Chunk>)>> = Cell::new(None);
+        let solid = |cell: [i64; 3]| -> bool {
+            let p = cell.map(|c| c as usize);
+            match &self.voxels {
+                Voxels::Dense(v) => v[self.index(p)] != 0,
+                Voxels::Sparse(chunks) => {
+                    let (chunk, i) = VoxelGrid::split(p);
+                    let data = match chunk_cache.get() {
+                        Some((cached, data)) if cached == chunk => data,
+                        _ => {
+                            let data = chunks.get(&chunk).map(|c| &**c);
+                            chunk_cache.set(Some((chunk, data)));
+                            data
+                        }
+                    };
+                    data.is_some_and(|c| c[i] != 0)
+                }
+            }
+        };
+
+        // starting inside a solid voxel means looking for the way out
+        let inside = axis.is_none() && solid(cell);
+        let mut t = t_near;
+        let mut previous = cell;
+        loop {
+            if let Some(a) = axis {
+                let is_solid = solid(cell);
+                if is_solid != inside {
+                    let filled = if inside { previous } else { cell };
+                    return Some(self.face_hit(r, t, a, step[a], cell, filled, inside));
+                }
+            }
+
+            let a = (0..3)
+                .min_by(|a, b| t_max[*a].partial_cmp(&t_max[*b]).unwrap())
+                .unwrap();
+            if t_max[a] > t_far {
+                return None;
+            }
+            previous = cell;
+            // a start right on a boundary can round into the cell behind it
+            t = t_max[a].max(t_near);
+            cell[a] += step[a];
+            t_max[a] += t_delta[a];
+            axis = Some(a);
+            if cell[a] < 0 || cell[a] >= size[a] {
+                // leaving the grid from inside a solid voxel is a way out too
+                return inside.then(|| self.face_hit(r, t, a, step[a], cell, previous, inside));
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.grid_bounds())
+    }
+
+    fn albedo(&self, i: &Intersection) -> Option<Color> {
+        let [sx, sy, _] = self.size;
+        let p = [
+            i.primitive % sx,
+            i.primitive / sx % sy,
+            i.primitive / (sx * sy),
+        ];
+        self.palette.get(self.get(p) as usize).copied()
+    }
+}
+
+impl VoxelGrid {
+    /// Hit on the face between `cell` and the one before it along axis `a`.
+    #[allow(clippy::too_many_arguments)]
+    fn face_hit(
+        &self,
+        r: &Ray,
+        t: Float,
+        a: usize,
+        step: i64,
+        cell: [i64; 3],
+        filled: [i64; 3],
+        leaving: bool,
+    ) -> Intersection {
+        // the face is exactly on a voxel boundary, so put the point there
+        let boundary = if step > 0 { cell[a] } else { cell[a] + 1 };
+        let mut point = (r.0 + t * r.1).0;
+        point.0[a] = self.origin.0 .0[a] + boundary as Float * self.voxel_size;
+
+        let axis = |i: usize| {
+            let mut v = [0.0; 3];
+            v[i] = 1.0;
+            Direction(Vec3(v))
+        };
+        let (u_axis, v_axis) = ((a + 1) % 3, (a + 2) % 3);
+        // facing out of the filled voxel: against the ray going in, with it going out
+        let normal = if leaving {
+            step as Float * axis(a)
+        } else {
+            -step as Float * axis(a)
+        };
+        let local =
+            |i: usize| (point.0[i] - self.origin.0 .0[i]) / self.voxel_size - filled[i] as Float;
+        let filled = filled.map(|c| c as usize);
+
+        Intersection {
+            distance: t,
+            point: Point(point),
+            surface_normal: normal,
+            geometric_normal: normal,
+            front_face: !leaving,
+            tangent: None,
+            uv: (local(u_axis).clamp(0.0, 1.0), local(v_axis).clamp(0.0, 1.0)),
+            dpdu: self.voxel_size * axis(u_axis),
+            dpdv: self.voxel_size * axis(v_axis),
+            primitive: self.index(filled),
+            object: 0,
+            error: gamma(7) * point.abs(),
+        }
+    }
+}
+
+/// Voxels in a grid of `size`, or an error if there are too many to count.
+fn raw_length(size: [usize; 3]) -> io::Result<usize> {
+    size.iter()
+        .try_fold(1usize, |length, s| length.checked_mul(*s))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}x{}x{} voxels are too many", size[0], size[1], size[2]),
+            )
+        })
+}
+
+/// Reads `size[0] * size[1] * size[2]` bytes, x fastest, as voxel values.
+/// The grid gets a gray palette, so values read as densities.
+pub fn parse_raw(reader: impl Read, size: [usize; 3]) -> io::Result<VoxelGrid> {
+    let length = raw_length(size)?;
+    // the voxels are kept as they arrive rather than allocated up front, so
+    // a size the data does not bear out fails without claiming the memory
+    let mut data = Vec::new();
+    reader.take(length as u64).read_to_end(&mut data)?;
+    if data.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("expected {} voxels, found {}", length, data.len()),
+        ));
+    }
+    Ok(VoxelGrid {
+        size,
+        voxels: Voxels::Dense(data),
+        ..VoxelGrid::dense([0; 3])
+    })
+}
+
+pub fn load_raw(path: &Path, size: [usize; 3]) -> io::Result<VoxelGrid> {
+    let file = std::fs::File::open(path)?;
+    let length = raw_length(size)?;
+    if file.metadata()?.len() < length as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "{} is too short for {}x{}x{} voxels",
+                path.display(),
+                size[0],
+                size[1],
+                size[2]
+            ),
+        ));
+    }
+    parse_raw(file, size)
+}
+
+/// Reads the first model of a MagicaVoxel `.vox` file, with its palette.
+/// Files without a palette chunk get a gray ramp rather than MagicaVoxel's
+/// built-in palette. Scene graph, material and layer chunks are skipped.
+pub fn parse_vox(mut reader: impl Read) -> io::Result<VoxelGrid> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    if data.len() < 8 || &data[..4] != b"VOX " {
+        return Err(invalid("not a .vox file"));
+    }
+    let int = |at: usize| -> io::Result<i32> {
+        let bytes = data
+            .get(at..at + 4)
+            .ok_or_else(|| invalid("truncated .vox file"))?;
+        Ok(i32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut size = None;
+    let mut voxels = None;
+    let mut palette = None;
+    // chunks are an id, content and children sizes, then the content; MAIN's
+    // children follow its (empty) content, so the walk can be flat
+    let mut at = 8;
+    while at + 12 <= data.len() {
+        let id = &data[at..at + 4];
+        let content = int(at + 4)?;
+        if content < 0 {
+            return Err(invalid("negative chunk size"));
+        }
+        let start = at + 12;
+        let end = start + content as usize;
+        if end > data.len() {
+            return Err(invalid("truncated .vox file"));
+        }
+        match id {
+            b"SIZE" if size.is_none() => {
+                let s = [int(start)?, int(start + 4)?, int(start + 8)?];
+                if s.iter().any(|s| *s <= 0 || *s > 2048) {
+                    return Err(invalid("bad model size"));
+                }
+                size = Some(s.map(|s| s as usize));
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = int(start)?.max(0) as usize;
+                let bytes = data
+                    .get(start + 4..start + 4 + 4 * count)
+                    .ok_or_else(|| invalid("truncated voxel list"))?;
+                voxels = Some(
+                    bytes
+                        .chunks(4)
+                        .map(|v| [v[0], v[1], v[2], v[3]])
+                        .collect::<Vec<_>>(),
+                );
+            }
+            b"RGBA" => {
+                let bytes = data
+                    .get(start..start + 1024)
+                    .ok_or_else(|| invalid("truncated palette"))?;
+                // entry i of the chunk is color index i + 1
+                let mut colors = vec![BLACK];
+                colors.extend(bytes.chunks(4).take(255).map(|c| {
+                    Color(
+                        c[0] as Float / 255.0,
+                        c[1] as Float / 255.0,
+                        c[2] as Float / 255.0,
+                    )
+                }));
+                palette = Some(colors);
+            }
+            _ => {}
+        }
+        // step into MAIN's children; skip over everything else's
+        at = if id == b"MAIN" {
+            end
+        } else {
+            end + int(at + 8)?.max(0) as usize
+        };
+    }
+
+    let size = size.ok_or_else(|| invalid("missing SIZE chunk"))?;
+    let voxels = voxels.ok_or_else(|| invalid("missing XYZI chunk"))?;
+    let mut grid = VoxelGrid::dense(size);
+    if let Some(palette) = palette {
+        grid.palette = palette;
+    }
+    for [x, y, z, i] in voxels {
+        let p = [x, y, z].map(|c| c as usize);
+        if (0..3).all(|a| p[a] < size[a]) {
+            grid.set(p, i);
+        }
+    }
+    Ok(grid)
+}
+
+pub fn load_vox(path: &Path) -> io::Result<VoxelGrid> {
+    parse_vox(std::fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn steps() -> VoxelGrid {
+        // a staircase along x, one voxel wide in y
+        let mut grid = VoxelGrid::dense([4, 1, 4]);
+        for x in 0..4 {
+            for z in 0..=x {
+                grid.set([x, 0, z], 1 + x as u8);
+            }
+        }
+        grid
+    }
+
+    fn check(grid: &VoxelGrid) {
+        // down onto the top of the third column
+        let i = grid
+            .find_intersection(&Ray(
+                Point(Vec3([2.25, 0.75, 10.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 7.0).abs() < per_precision(1e-9, 1e-4));
+        assert_eq!(Direction(Vec3([0.0, 0.0, 1.0])), i.surface_normal);
+        assert!(i.front_face);
+        assert_eq!(grid.index([2, 0, 2]), i.primitive);
+        assert_eq!((0.25, 0.75), i.uv);
+        assert_eq!(grid.palette[3], grid.albedo(&i).unwrap());
+
+        // along x at height 1.5: over the first column, into the side of the second
+        let i = grid
+            .find_intersection(&Ray(
+                Point(Vec3([-1.0, 0.5, 1.5])),
+                Direction(Vec3([1.0, 0.0, 0.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 2.0).abs() < per_precision(1e-9, 1e-4));
+        assert_eq!(Direction(Vec3([-1.0, 0.0, 0.0])), i.surface_normal);
+        assert_eq!(grid.index([1, 0, 1]), i.primitive);
+
+        // diagonally down the stairs, missing every step
+        assert!(grid
+            .find_intersection(&Ray(
+                Point(Vec3([-0.5, 0.5, 0.9])),
+                Direction(Vec3([1.0, 0.0, 1.0])).normalized(),
+            ))
+            .is_none());
+        // passing beside the grid
+        assert!(grid
+            .find_intersection(&Ray(
+                Point(Vec3([-1.0, 2.0, 0.5])),
+                Direction(Vec3([1.0, 0.0, 0.0])),
+            ))
+            .is_none());
+
+        // from inside the last column, out through its side and out of the grid
+        let i = grid
+            .find_intersection(&Ray(
+                Point(Vec3([3.5, 0.5, 3.5])),
+                Direction(Vec3([-1.0, 0.0, 0.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 0.5).abs() < per_precision(1e-9, 1e-4));
+        assert!(!i.front_face);
+        assert_eq!(Direction(Vec3([-1.0, 0.0, 0.0])), i.geometric_normal);
+        let i = grid
+            .find_intersection(&Ray(
+                Point(Vec3([3.5, 0.5, 3.5])),
+                Direction(Vec3([0.0, 0.0, 1.0])),
+            ))
+            .unwrap();
+        assert!((i.distance - 0.5).abs() < per_precision(1e-9, 1e-4));
+        assert!(!i.front_face);
+    }
+
+    #[test]
+    fn dense() {
+        check(&steps());
+    }
+
+    #[test]
+    fn sparse() {
+        let grid = steps().into_sparse();
+        assert!(matches!(grid.voxels(), Voxels::Sparse(c) if c.len() == 1));
+        check(&grid);
+
+        // a big, mostly empty grid with voxels in two distant chunks
+        let mut grid = VoxelGrid::sparse([1000, 1000, 1000]);
+        grid.set([900, 10, 10], 7);
+        grid.set([10, 10, 10], 5);
+        let r = Ray(
+            Point(Vec3([2000.0, 10.5, 10.5])),
+            Direction(Vec3([-1.0, 0.0, 0.0])),
+        );
+        let i = grid.find_intersection(&r).unwrap();
+        assert!((i.distance - 1099.0).abs() < per_precision(1e-9, 1e-4));
+        grid.set([900, 10, 10], 0);
+        assert!(matches!(grid.voxels(), Voxels::Sparse(c) if c.len() == 1));
+        let i = grid.find_intersection(&r).unwrap();
+        assert!((i.distance - 1989.0).abs() < per_precision(1e-9, 1e-4));
+    }
+
+    #[test]
+    fn scaled() {
+        let mut grid = steps();
+        grid.origin = Point(Vec3([-1.0, -1.0, -1.0]));
+        grid.voxel_size = 0.5;
+        let i = grid
+            .find_intersection(&Ray(
+                Point(Vec3([0.1, -0.75, 10.0])),
+                Direction(Vec3([0.0, 0.0, -1.0])),
+            ))
+            .unwrap();
+        // x = 0.1 is in the third column, whose top is at -1 + 3 * 0.5
+        assert!((i.point.0 .0[2] - 0.5).abs() < per_precision(1e-12, 1e-5));
+        assert!((i.uv.0 - 0.2).abs() < per_precision(1e-9, 1e-4));
+        assert!((i.dpdu.0.magnitude() - 0.5).abs() < per_precision(1e-12, 1e-5));
+    }
+
+    #[test]
+    fn vox() {
+        let chunk = |id: &[u8], content: Vec<u8>, children: Vec<u8>| {
+            let mut c = id.to_vec();
+            c.extend((content.len() as i32).to_le_bytes());
+            c.extend((children.len() as i32).to_le_bytes());
+            c.extend(content);
+            c.extend(children);
+            c
+        };
+        let ints = |v: &[i32]| v.iter().flat_map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut xyzi = ints(&[2]);
+        xyzi.extend([0, 0, 0, 1, 1, 2, 3, 2]);
+        let mut rgba = vec![255, 0, 0, 255, 0, 0, 255, 255];
+        rgba.resize(1024, 0);
+        let mut children = chunk(b"SIZE", ints(&[2, 3, 4]), Vec::new());
+        children.extend(chunk(b"XYZI", xyzi, Vec::new()));
+        children.extend(chunk(b"nTRN", vec![0; 5], Vec::new()));
+        children.extend(chunk(b"RGBA", rgba, Vec::new()));
+        let mut file = b"VOX ".to_vec();
+        file.extend(ints(&[150]));
+        file.extend(chunk(b"MAIN", Vec::new(), children));
+
+        let grid = parse_vox(file.as_slice()).unwrap();
+        assert_eq!([2, 3, 4], grid.size);
+        assert_eq!(1, grid.get([0, 0, 0]));
+        assert_eq!(2, grid.get([1, 2, 3]));
+        assert_eq!(0, grid.get([1, 1, 1]));
+        assert_eq!(Color(1.0, 0.0, 0.0), grid.palette[1]);
+        assert_eq!(Color(0.0, 0.0, 1.0), grid.palette[2]);
+
+        assert!(parse_vox(&b"VOX \x96\0\0\0"[..]).is_err());
+        assert!(parse_vox(&file[..file.len() - 10]).is_err());
+        assert!(parse_vox(&b"PNG ...."[..]).is_err());
+    }
+
+    #[test]
+    fn raw() {
+        let data = [0u8, 10, 0, 0, 0, 0, 0, 255];
+        let grid = parse_raw(&data[..], [2, 2, 2]).unwrap();
+        assert_eq!(10, grid.get([1, 0, 0]));
+        assert_eq!(255, grid.get([1, 1, 1]));
+        assert_eq!(WHITE, grid.palette[255]);
+        assert!(parse_raw(&data[..], [2, 2, 3]).is_err());
+        assert!(parse_raw(&data[..], [usize::MAX, 2, 1]).is_err());
+    }
+}
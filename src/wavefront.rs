@@ -0,0 +1,514 @@
+//! The wavefront renderer: rays traced a bounce at a time, in batches
+//! sorted along a Morton curve so that rays traced one after another walk
+//! the same parts of the acceleration structure. That pays where tracing
+//! waits on memory, as in scenes of many objects or large meshes: through a
+//! cloud of 100,000 spheres it renders about twice as fast as the recursive
+//! renderer. The demo scene of a few spheres in a box spends its time in
+//! shading rather than traversal, and there all it gains is from asking
+//! about 40% fewer queries, mostly shadow rays it need not trace; batching
+//! costs nearly as much again. Taking the best of three runs, it is 1.1 to
+//! 1.2 times as fast as the recursive renderer over 1 to 3 bounces and 0.9
+//! times over 4, about the same overall (see `examples/wavefront.rs`).
+
+use std::str::FromStr;
+
+use rayon::prelude::*;
+use smallvec::SmallVec;
+
+use crate::aa::AntiAliasing;
+use crate::packet::Packet;
+use crate::*;
+
+/// How `Scene::render` goes about tracing its rays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Renderer {
+    /// Each camera ray followed to the end before the next, with the shadow
+    /// and reflection rays it spawns traced as shading comes to them.
+    #[default]
+    Recursive,
+    /// Rays traced a bounce at a time, in large batches sorted so that rays
+    /// next to each other start near each other and point the same way.
+    /// Shading a batch only asks for shadow rays, which are traced together
+    /// once it is done, and only where the light would make a difference.
+    Wavefront,
+}
+
+impl FromStr for Renderer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Renderer, String> {
+        match s {
+            "recursive" => Ok(Renderer::Recursive),
+            "wavefront" => Ok(Renderer::Wavefront),
+            _ => Err(format!(
+                "unknown renderer '{}': expected recursive or wavefront",
+                s
+            )),
+        }
+    }
+}
+
+/// About how many paths from the camera are traced together. Larger batches
+/// sort into more coherent runs, but hold more memory.
+const WAVE: usize = 1 << 16;
+
+/// Rays handed to the acceleration structure at a time, as a packet.
+const PACKET: usize = 64;
+
+/// Bits per coordinate in the Morton code of a ray: six coordinates, three
+/// for the origin and three for the direction, fill 30 bits, which leaves
+/// room beside them for the ray's index.
+const MORTON_BITS: u32 = 5;
+
+/// A ray on its way, and the path from the camera it extends.
+#[derive(Clone, Copy, Debug)]
+struct PathRay {
+    /// Index of the path within the wave.
+    path: usize,
+    ray: Ray,
+    /// How much of the light coming back along the ray reaches the camera.
+    weight: Color,
+}
+
+/// Light from one light at one hit, waiting on its shadow rays.
+#[derive(Clone, Copy, Debug)]
+struct LightSample {
+    path: usize,
+    /// What reaches the camera along the path if nothing blocks the light.
+    color: Color,
+    /// Shadow rays toward the light, and how many of them are blocked.
+    total: usize,
+    shaded: usize,
+}
+
+/// A shadow ray, the sample it decides, and how many of the sample's rays
+/// it stands for: those aimed at the same point are traced only once.
+#[derive(Clone, Copy, Debug)]
+struct ShadowRay {
+    sample: usize,
+    ray: Ray,
+    distance: Float,
+    count: usize,
+}
+
+/// What shading a run of hits asks for next.
+#[derive(Debug, Default)]
+struct Shaded {
+    samples: Vec<LightSample>,
+    shadow_rays: Vec<ShadowRay>,
+    /// Rays reflected or refracted, for the next bounce.
+    spawned: Vec<PathRay>,
+}
+
+impl Scene {
+    /// Colors of the pixels, row by row, traced a wave of paths at a time,
+    /// and each wave a bounce at a time.
+    pub(crate) fn render_wavefront(&self, tree: &ObjectTree) -> Vec<Color> {
+        let aa = AntiAliasing::create(Self::ANTI_ALIASING);
+        let count = aa.offsets().len();
+        let width = self.imgx as usize;
+        let per_wave = (WAVE / count).max(1);
+
+        // camera rays leave from one point, so taking the pixels in Morton
+        // order is taking the rays in the order of their directions
+        let pixels = morton_pixels(self.imgx, self.imgy);
+        let mut colors = vec![BLACK; pixels.len()];
+        for wave in pixels.chunks(per_wave) {
+            let mut rays: Vec<PathRay> = wave
+                .iter()
+                .enumerate()
+                .flat_map(|(k, p)| {
+                    let (x, y) = ((p % width) as Float, (p / width) as Float);
+                    aa.offsets()
+                        .iter()
+                        .enumerate()
+                        .map(move |(s, (xx, yy))| PathRay {
+                            path: k * count + s,
+                            ray: self.pixel_ray(xx + x, yy + y),
+                            weight: WHITE,
+                        })
+                })
+                .collect();
+
+            let mut radiance = vec![BLACK; wave.len() * count];
+            for bounce in 1..=self.depth {
+                if rays.is_empty() {
+                    break;
+                }
+                if bounce > 1 {
+                    rays = morton_order(rays.iter().map(|r| &r.ray))
+                        .into_iter()
+                        .map(|k| rays[k])
+                        .collect();
+                }
+                rays = self.bounce(tree, &rays, bounce < self.depth, &mut radiance);
+            }
+
+            for (p, samples) in wave.iter().zip(radiance.chunks(count)) {
+                let mut color = BLACK;
+                for c in samples {
+                    color += *c;
+                }
+                color *= 1.0 / count as Float;
+                colors[*p] = color;
+            }
+        }
+        colors
+    }
+
+    /// Finds and shades what `rays` hit, adding the light they see to
+    /// `radiance`, and returns the rays they spawn for the next bounce,
+    /// unless it is the last.
+    fn bounce(
+        &self,
+        tree: &ObjectTree,
+        rays: &[PathRay],
+        spawn: bool,
+        radiance: &mut [Color],
+    ) -> Vec<PathRay> {
+        // each packet's hits are shaded while they are at hand, which asks
+        // for shadow rays rather than tracing them
+        let mut parts: Vec<Shaded> = rays
+            .par_chunks(PACKET)
+            .map(|chunk| {
+                let packet = Packet::new(chunk.iter().map(|r| r.ray).collect());
+                let hits = tree.closest_intersections(&packet);
+                self.shade_run(chunk, &hits, spawn)
+            })
+            .collect();
+
+        parts.par_iter_mut().for_each(|part| {
+            for s in &part.shadow_rays {
+                if tree.occluded(&s.ray, s.distance) {
+                    part.samples[s.sample].shaded += s.count;
+                }
+            }
+        });
+
+        for sample in parts.iter().flat_map(|part| &part.samples) {
+            if sample.shaded == sample.total {
+                continue;
+            }
+            let unblocked = 1.0 - sample.shaded as Float / sample.total as Float;
+            radiance[sample.path] += unblocked * sample.color;
+        }
+        parts.into_iter().flat_map(|part| part.spawned).collect()
+    }
+
+    /// Shades the hits of a run of rays: works out how much light each
+    /// light would bring, with the shadow rays to find out whether it does,
+    /// and the reflected and refracted rays.
+    fn shade_run(
+        &self,
+        rays: &[PathRay],
+        hits: &[Option<(&Object, Intersection)>],
+        spawn: bool,
+    ) -> Shaded {
+        let mut positions: SmallVec<[_; Self::MAX_LIGHT_POINTS]> = SmallVec::new();
+        // what every light needs of each hit, worked out once
+        let surfaces: SmallVec<[_; PACKET]> = rays
+            .iter()
+            .zip(hits)
+            .filter_map(|(r, hit)| {
+                let (object, i) = hit.as_ref()?;
+                let weight = Self::transmittance(&object.material, i) * r.weight;
+                let diffuse_color = Self::diffuse_color(object, i);
+                Some((r, *object, i, weight, i.facing_normal(), diffuse_color))
+            })
+            .collect();
+        let mut shaded = Shaded {
+            samples: Vec::with_capacity(surfaces.len() * self.lights.len()),
+            shadow_rays: Vec::with_capacity(surfaces.len() * self.lights.len()),
+            spawned: Vec::with_capacity(if spawn { surfaces.len() } else { 0 }),
+        };
+
+        // a light at a time, so that rays toward it from neighbouring hits
+        // go together
+        for l in &self.lights {
+            for (r, object, i, weight, normal, diffuse_color) in &surfaces {
+                let (response, light_distance) =
+                    Self::light_response(&r.ray, &object.material, i, *normal, *diffuse_color, l);
+                // no shadow can take away what is not there
+                if response == BLACK {
+                    continue;
+                }
+                let sample = shaded.samples.len();
+                let first_ray = shaded.shadow_rays.len();
+                Self::shadow_targets(i, l, &mut positions);
+                for (n, p) in positions.iter().enumerate() {
+                    if let Some(m) = positions[..n].iter().position(|q| q == p) {
+                        shaded.shadow_rays[first_ray + m].count += 1;
+                        continue;
+                    }
+                    let (ray, distance) = i.spawn_ray_to(*p);
+                    shaded.shadow_rays.push(ShadowRay {
+                        sample,
+                        ray,
+                        distance,
+                        count: 1,
+                    });
+                }
+                shaded.samples.push(LightSample {
+                    path: r.path,
+                    color: *weight * Self::direct_light(l, light_distance, response, 1.0),
+                    total: positions.len(),
+                    shaded: 0,
+                });
+            }
+        }
+
+        if spawn {
+            for (r, object, i, weight, ..) in &surfaces {
+                for (ray, secondary) in Self::secondary_rays(&r.ray, &object.material, i) {
+                    shaded.spawned.push(PathRay {
+                        path: r.path,
+                        ray,
+                        weight: secondary * *weight,
+                    });
+                }
+            }
+        }
+        shaded
+    }
+}
+
+/// Order in which to trace `rays` so that they follow a Morton curve through
+/// their origins and directions together: rays that start close by and
+/// point alike come one after another, and walk the same parts of the
+/// acceleration structure. Rays with equal codes keep their order.
+fn morton_order<'a>(rays: impl Iterator<Item = &'a Ray> + Clone) -> Vec<usize> {
+    let (min, max) = rays.clone().fold(
+        ([Float::INFINITY; 3], [Float::NEG_INFINITY; 3]),
+        |(min, max), r| {
+            let o = r.0 .0 .0;
+            (
+                [0, 1, 2].map(|a| min[a].min(o[a])),
+                [0, 1, 2].map(|a| max[a].max(o[a])),
+            )
+        },
+    );
+    let cells = ((1u32 << MORTON_BITS) - 1) as Float;
+    let quantize = |v: Float, min: Float, max: Float| {
+        let extent = max - min;
+        if extent > 0.0 {
+            ((v - min) / extent * cells).clamp(0.0, cells) as u64
+        } else {
+            0
+        }
+    };
+    let mut keys: Vec<u64> = rays
+        .enumerate()
+        .map(|(k, Ray(origin, direction))| {
+            let (o, d) = (origin.0 .0, direction.0 .0);
+            let coordinates = [
+                quantize(o[0], min[0], max[0]),
+                quantize(o[1], min[1], max[1]),
+                quantize(o[2], min[2], max[2]),
+                quantize(d[0], -1.0, 1.0),
+                quantize(d[1], -1.0, 1.0),
+                quantize(d[2], -1.0, 1.0),
+            ];
+            // each coordinate's bits spread six apart, the origin's on top
+            let mut code = 0;
+            for (shift, c) in (0..6).rev().zip(coordinates) {
+                code |= spread(c) << shift;
+            }
+            debug_assert!(k <= u32::MAX as usize);
+            code << 32 | k as u64
+        })
+        .collect();
+    radix_sort(&mut keys);
+    keys.into_iter().map(|key| key as u32 as usize).collect()
+}
+
+/// Digits of the codes sorted at a time by `radix_sort`.
+const RADIX_BITS: u32 = 10;
+
+/// Sorts the Morton keys by their codes, keeping rays with equal codes in
+/// the order of their indices. A radix sort of the codes, lowest digit
+/// first, keeps that order by itself, and takes a few passes over the keys
+/// rather than a comparison sort's many.
+fn radix_sort(keys: &mut Vec<u64>) {
+    let code_bits = 6 * MORTON_BITS;
+    let mut sorted = vec![0; keys.len()];
+    for shift in (0..code_bits).step_by(RADIX_BITS as usize) {
+        let digit = |key: u64| (key >> (32 + shift) & ((1 << RADIX_BITS) - 1)) as usize;
+        let mut starts = [0; 1 << RADIX_BITS];
+        for key in keys.iter() {
+            starts[digit(*key)] += 1;
+        }
+        let mut start = 0;
+        for s in starts.iter_mut() {
+            (*s, start) = (start, start + *s);
+        }
+        for key in keys.iter() {
+            let d = digit(*key);
+            sorted[starts[d]] = *key;
+            starts[d] += 1;
+        }
+        std::mem::swap(keys, &mut sorted);
+    }
+}
+
+/// Indices of the pixels of a `width` by `height` image, along a Morton
+/// curve: each quarter of the image, then each quarter of those, and so on.
+fn morton_pixels(width: u32, height: u32) -> Vec<usize> {
+    let bits = u32::BITS - width.max(height).saturating_sub(1).leading_zeros();
+    (0..1u64 << (2 * bits))
+        .filter_map(|code| {
+            let (x, y) = (0..bits).fold((0, 0), |(x, y), b| {
+                (
+                    x | (code >> (2 * b) & 1) << b,
+                    y | (code >> (2 * b + 1) & 1) << b,
+                )
+            });
+            (x < width as u64 && y < height as u64).then_some((y * width as u64 + x) as usize)
+        })
+        .collect()
+}
+
+/// The low `MORTON_BITS` bits of `v`, moved six places apart.
+fn spread(v: u64) -> u64 {
+    SPREAD[v as usize]
+}
+
+/// `spread` of every value it is given, worked out ahead.
+const SPREAD: [u64; 1 << MORTON_BITS] = {
+    let mut table = [0; 1 << MORTON_BITS];
+    let mut v = 0;
+    while v < table.len() {
+        let mut bit = 0;
+        while bit < MORTON_BITS {
+            table[v] |= (v as u64 >> bit & 1) << (6 * bit);
+            bit += 1;
+        }
+        v += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
+
+    fn scene(renderer: Renderer, depth: usize) -> Scene {
+        let material = |diffuse_color, reflectivity| Material {
+            reflectivity,
+            ..Material::opaque(diffuse_color)
+        };
+        let sphere = |x, y, radius, color| {
+            Object::new(
+                Sphere {
+                    center: Point(Vec3([x, y, radius - 1.0])),
+                    radius,
+                },
+                material(color, 0.5),
+            )
+        };
+        Scene {
+            camera: Camera {
+                ray: Ray::from_points(Point(Vec3([-4.0, -3.0, 2.0])), Point::origin()),
+                up: Direction(Vec3([0.0, 0.0, 1.0])),
+                w_fov_degrees: 60.0,
+            },
+            imgx: 64,
+            imgy: 48,
+            objects: vec![
+                Object::new(
+                    Plane {
+                        point: Point(Vec3([0.0, 0.0, -1.0])),
+                        normal: Direction(Vec3([0.0, 0.0, 1.0])),
+                    },
+                    material(0.5 * WHITE, 0.7),
+                ),
+                sphere(0.0, 0.0, 1.0, RED),
+                sphere(1.5, -0.5, 0.5, GREEN),
+                Object::new(
+                    Sphere {
+                        center: Point(Vec3([1.0, 1.5, -0.6])),
+                        radius: 0.4,
+                    },
+                    Material {
+                        conductor: Some(crate::optics::Conductor::GOLD),
+                        ..material(0.1 * WHITE, 0.0)
+                    },
+                ),
+                sphere(-1.0, 1.5, 0.7, BLUE),
+                // tinted glass in front, for rays that go through
+                Object::new(
+                    Sphere {
+                        center: Point(Vec3([-0.5, -1.5, -0.4])),
+                        radius: 0.6,
+                    },
+                    Material {
+                        diffuse_color: BLACK,
+                        transparency: 1.0,
+                        refractive_index: 1.5,
+                        absorption: Color(0.2, 0.5, 1.5),
+                        ..material(BLACK, 0.0)
+                    },
+                ),
+            ],
+            lights: vec![
+                Light {
+                    point: Point(Vec3([-2.0, -2.0, 3.0])),
+                    color: WHITE,
+                    intensity: 0.7,
+                    radius: 0.05,
+                },
+                Light {
+                    point: Point(Vec3([2.0, -1.0, 1.0])),
+                    color: WHITE,
+                    intensity: 0.4,
+                    radius: 0.0,
+                },
+            ],
+            accelerator: accel::Kind::Bvh,
+            renderer,
+            depth,
+        }
+    }
+
+    #[test]
+    fn matches_recursive() {
+        for depth in 1..=4 {
+            let (recursive, _) = scene(Renderer::Recursive, depth).render_image();
+            let (wavefront, _) = scene(Renderer::Wavefront, depth).render_image();
+            if depth == 1 {
+                // the same sums in the same order
+                assert_eq!(recursive, wavefront);
+            } else {
+                // reflections are weighted before they are added up, rather
+                // than after, which may round the other way
+                for (a, b) in recursive.pixels().zip(wavefront.pixels()) {
+                    for (a, b) in a.0.iter().zip(b.0) {
+                        assert!(a.abs_diff(b) <= 1, "{:?} against {:?}", a, b);
+                    }
+                }
+            }
+        }
+        assert_eq!(Ok(Renderer::Wavefront), "wavefront".parse());
+        assert!("breadth".parse::<Renderer>().is_err());
+    }
+
+    #[test]
+    fn morton_order_groups_neighbours() {
+        // rays from the corners of a cube toward the middle, shuffled
+        let rays: Vec<Ray> = (0..800)
+            .map(|k| {
+                let corner = (k * 7) % 8;
+                let origin = Vec3([0, 1, 2].map(|a| (corner >> a & 1) as Float));
+                let jitter = (k as Float * 0.37).sin() * 0.01;
+                Ray::from_points(Point(origin), Point(Vec3([0.5 + jitter, 0.5, 0.5])))
+            })
+            .collect();
+        let rays: Vec<Ray> = morton_order(rays.iter())
+            .into_iter()
+            .map(|k| rays[k])
+            .collect();
+        let runs = rays.windows(2).filter(|w| w[0].0 != w[1].0).count();
+        assert_eq!(7, runs);
+    }
+}
@@ -0,0 +1,584 @@
+use smallvec::SmallVec;
+use std::io;
+use std::ops::Range;
+
+use crate::aabb::Aabb;
+use crate::accel::{Accelerator, Stats, Traversal};
+use crate::bvh::{primitive_area, Bvh, NodeKind, MAX_LEAF, REFIT_LIMIT, TRAVERSAL_COST};
+use crate::cache::{Array, Plain, Reader, Writer};
+use crate::packet::Packet;
+use crate::simd::{slabs4, Float4};
+use crate::*;
+
+#[derive(Clone, Copy, Debug)]
+enum Child {
+    Empty,
+    Node(usize),
+    /// Index into the list of leaves.
+    Leaf(usize),
+}
+
+impl Child {
+    /// The child as it is kept in a node, and in cache files: 0 for none,
+    /// odd numbers for nodes and even ones for leaves.
+    fn code(self) -> u32 {
+        let code = match self {
+            Child::Empty => 0,
+            Child::Node(index) => 2 * index + 1,
+            Child::Leaf(l) => 2 * l + 2,
+        };
+        code.try_into()
+            .expect("too many nodes for 32 bit child codes")
+    }
+
+    fn decode(code: u32) -> Child {
+        match code as usize {
+            0 => Child::Empty,
+            c if c % 2 == 1 => Child::Node(c / 2),
+            c => Child::Leaf(c / 2 - 1),
+        }
+    }
+}
+
+/// Up to `N` children with their boxes, one lane per child, so that all of
+/// them are tested against a ray together.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+struct Node<const N: usize> {
+    min: [[Float; N]; 3],
+    max: [[Float; N]; 3],
+    /// Each child's `Child::code`.
+    children: [u32; N],
+}
+
+// SAFETY: trees are only made or read with `N` of 4 or 8, for which the
+// children end on a float boundary, so there is no padding
+unsafe impl<const N: usize> Plain for Node<N> {}
+
+impl<const N: usize> Node<N> {
+    fn child(&self, lane: usize) -> Child {
+        Child::decode(self.children[lane])
+    }
+
+    fn bounds(&self, lane: usize) -> Aabb {
+        Aabb {
+            min: Point(Vec3([0, 1, 2].map(|axis| self.min[axis][lane]))),
+            max: Point(Vec3([0, 1, 2].map(|axis| self.max[axis][lane]))),
+        }
+    }
+
+    /// Box around the children that are there.
+    fn union(&self) -> Aabb {
+        (0..N)
+            .filter(|lane| !matches!(self.child(*lane), Child::Empty))
+            .fold(Aabb::empty(), |b, lane| b.union(&self.bounds(lane)))
+    }
+
+    fn empty() -> Node<N> {
+        // inside out, so that unused lanes are never entered
+        Node {
+            min: [[Float::INFINITY; N]; 3],
+            max: [[Float::NEG_INFINITY; N]; 3],
+            children: [0; N],
+        }
+    }
+}
+
+/// Bounding volume hierarchy with 4 or 8 children per node, made by
+/// collapsing the levels of a binary [`Bvh`]. A ray visits fewer, bigger
+/// nodes, and tests each one's children with SIMD.
+#[derive(Clone, Debug)]
+pub struct WideBvh<const N: usize> {
+    nodes: Array<Node<N>>,
+    /// Each leaf's range in `indices`.
+    leaves: Array<[u32; 2]>,
+    indices: Array<u32>,
+    bounds: Option<Aabb>,
+    /// Surface area cost when built, which refits are held against.
+    built_cost: Float,
+}
+
+impl<const N: usize> WideBvh<N> {
+    pub fn new(bounds: &[Aabb]) -> WideBvh<N> {
+        const { assert!(N == 4 || N == 8, "nodes are 4 or 8 wide") };
+        assert!(
+            u32::try_from(bounds.len()).is_ok(),
+            "too many primitives for 32 bit indices"
+        );
+        let binary = Bvh::new(bounds);
+        let (mut nodes, mut leaves) = (Vec::new(), Vec::new());
+        if let Some(root) = binary.nodes.first() {
+            let children = match root.kind {
+                NodeKind::Leaf { .. } => SmallVec::from_slice(&[0]),
+                NodeKind::Interior { .. } => open::<N>(&binary, 0),
+            };
+            collapse(&mut nodes, &mut leaves, &binary, &children);
+        }
+        let indices = binary.indices.iter().map(|i| *i as u32).collect::<Vec<_>>();
+        let mut tree = WideBvh {
+            nodes: nodes.into(),
+            leaves: leaves.into(),
+            indices: indices.into(),
+            bounds: binary.bounds(),
+            built_cost: 0.0,
+        };
+        tree.built_cost = tree.cost(bounds);
+        tree
+    }
+
+    /// Surface area cost of the tree over `bounds`, as `Bvh` measures it,
+    /// with each child box weighed alike whether it is tested alone or with
+    /// its siblings.
+    fn cost(&self, bounds: &[Aabb]) -> Float {
+        let mut total = 0.0;
+        for node in self.nodes.iter() {
+            for lane in 0..N {
+                let weight = match node.child(lane) {
+                    Child::Empty => continue,
+                    Child::Node(_) => TRAVERSAL_COST,
+                    Child::Leaf(l) => self.leaf(l).len() as Float,
+                };
+                total += node.bounds(lane).surface_area() * weight;
+            }
+        }
+        total / primitive_area(bounds)
+    }
+
+    /// Writes the tree for the cache.
+    pub(crate) fn write(&self, w: &mut Writer) {
+        w.u32(N as u32);
+        w.array(&self.nodes);
+        w.array(&self.leaves);
+        w.array(&self.indices);
+        w.floats(&[self.built_cost]);
+    }
+
+    /// Reads back what `write` wrote, for a tree over `primitives` boxes.
+    /// Everything is checked, so that a damaged file cannot send a query
+    /// out of bounds or around in circles.
+    pub(crate) fn read(r: &mut Reader, primitives: usize) -> io::Result<WideBvh<N>> {
+        const { assert!(N == 4 || N == 8, "nodes are 4 or 8 wide") };
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_owned());
+        if r.u32()? as usize != N {
+            return Err(invalid("tree of another width"));
+        }
+        let nodes: Array<Node<N>> = r.array()?;
+        let leaves: Array<[u32; 2]> = r.array()?;
+        let indices: Array<u32> = r.array()?;
+        if indices.len() != primitives {
+            return Err(invalid("tree over another number of primitives"));
+        }
+        if indices.iter().any(|i| *i as usize >= primitives) {
+            return Err(invalid("index out of range"));
+        }
+        for [start, end] in leaves.iter().map(|l| l.map(|i| i as usize)) {
+            if start > end || end - start > MAX_LEAF || end > indices.len() {
+                return Err(invalid("bad leaf"));
+            }
+        }
+        // children always come after their parents
+        for (parent, node) in nodes.iter().enumerate() {
+            for lane in 0..N {
+                let fine = match node.child(lane) {
+                    Child::Empty => true,
+                    Child::Node(index) => index > parent && index < nodes.len(),
+                    Child::Leaf(l) => l < leaves.len(),
+                };
+                if !fine {
+                    return Err(invalid("bad child"));
+                }
+            }
+        }
+        if nodes.is_empty() != (primitives == 0) {
+            return Err(invalid("tree with no root"));
+        }
+        let built_cost = r.float()?;
+        let bounds = nodes.first().map(Node::union);
+        Ok(WideBvh {
+            nodes,
+            leaves,
+            indices,
+            bounds,
+            built_cost,
+        })
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_mapped(&self) -> bool {
+        self.nodes.is_mapped() && self.leaves.is_mapped() && self.indices.is_mapped()
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// The primitives in a leaf, at most [`crate::bvh::MAX_LEAF`] of them.
+    pub fn leaf(&self, leaf: usize) -> &[u32] {
+        let [start, end] = self.leaves[leaf];
+        &self.indices[start as usize..end as usize]
+    }
+
+    /// Lanes of a node whose children are entered before `max_distance`,
+    /// nearest last.
+    fn entered(
+        &self,
+        index: usize,
+        origin: &[Float; 3],
+        inverse: &[Float; 3],
+        max_distance: Float,
+    ) -> SmallVec<[(Float, usize); 8]> {
+        let node = &self.nodes[index];
+        let mut entered: SmallVec<[(Float, usize); 8]> = SmallVec::new();
+        for chunk in 0..N / 4 {
+            let (min, max) = (lanes(&node.min, chunk), lanes(&node.max, chunk));
+            let (hits, t) = slabs4::<Float4>(min, max, origin, inverse, max_distance);
+            for (lane, t) in t.into_iter().enumerate() {
+                let lane = 4 * chunk + lane;
+                if hits & (1 << (lane % 4)) != 0 && !matches!(node.child(lane), Child::Empty) {
+                    entered.push((t, lane));
+                }
+            }
+        }
+        entered.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        entered
+    }
+
+    /// Visits the leaves the ray enters before `max_distance`, near to far.
+    /// `leaf(l, max)` returns the distance to a hit in leaf `l` only if that
+    /// is below `max`; the last such distance is returned.
+    pub fn closest_leaf(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        traversal: &mut Traversal,
+        mut leaf: impl FnMut(usize, Float) -> Option<Float>,
+    ) -> Option<Float> {
+        let origin = r.0 .0 .0;
+        let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+        let mut closest = None;
+        let mut max = max_distance;
+        let mut stack: SmallVec<[(Float, Child); 64]> = SmallVec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0.0, Child::Node(0)));
+        }
+        while let Some((t, child)) = stack.pop() {
+            if t > max {
+                continue;
+            }
+            traversal.nodes += 1;
+            match child {
+                Child::Node(index) => stack.extend(
+                    self.entered(index, &origin, &inverse, max)
+                        .into_iter()
+                        .map(|(t, lane)| (t, self.nodes[index].child(lane))),
+                ),
+                Child::Leaf(l) => {
+                    if let Some(distance) = leaf(l, max) {
+                        max = distance;
+                        closest = Some(distance);
+                    }
+                }
+                Child::Empty => {}
+            }
+        }
+        closest
+    }
+
+    /// Whether `leaf` holds for any leaf the ray enters before
+    /// `max_distance`, stopping at the first one.
+    pub fn any_leaf(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        traversal: &mut Traversal,
+        mut leaf: impl FnMut(usize) -> bool,
+    ) -> bool {
+        let origin = r.0 .0 .0;
+        let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+        let mut stack: SmallVec<[Child; 64]> = SmallVec::new();
+        if !self.nodes.is_empty() {
+            stack.push(Child::Node(0));
+        }
+        while let Some(child) = stack.pop() {
+            traversal.nodes += 1;
+            match child {
+                Child::Node(index) => stack.extend(
+                    self.entered(index, &origin, &inverse, max_distance)
+                        .into_iter()
+                        .map(|(_, lane)| self.nodes[index].child(lane)),
+                ),
+                Child::Leaf(l) => {
+                    if leaf(l) {
+                        return true;
+                    }
+                }
+                Child::Empty => {}
+            }
+        }
+        false
+    }
+}
+
+/// The `chunk`th four lanes of each axis.
+fn lanes<const N: usize>(v: &[[Float; N]; 3], chunk: usize) -> [&[Float; 4]; 3] {
+    [0, 1, 2].map(|axis| v[axis][4 * chunk..4 * chunk + 4].try_into().unwrap())
+}
+
+/// Appends a node over the given nodes of the binary tree, and the subtrees
+/// below them, returning its index.
+fn collapse<const N: usize>(
+    nodes: &mut Vec<Node<N>>,
+    leaves: &mut Vec<[u32; 2]>,
+    binary: &Bvh,
+    children: &[usize],
+) -> usize {
+    let index = nodes.len();
+    nodes.push(Node::empty());
+    for (lane, child) in children.iter().enumerate() {
+        let node = &binary.nodes[*child];
+        for axis in 0..3 {
+            nodes[index].min[axis][lane] = node.bounds.min.0 .0[axis];
+            nodes[index].max[axis][lane] = node.bounds.max.0 .0[axis];
+        }
+        let child = match node.kind {
+            NodeKind::Leaf { start, end } => {
+                leaves.push([start as u32, end as u32]);
+                Child::Leaf(leaves.len() - 1)
+            }
+            NodeKind::Interior { .. } => {
+                Child::Node(collapse(nodes, leaves, binary, &open::<N>(binary, *child)))
+            }
+        };
+        nodes[index].children[lane] = child.code();
+    }
+    index
+}
+
+/// Opens up the children of an interior node of the binary tree, and then
+/// theirs, biggest surface first, until there are `N` or only leaves.
+fn open<const N: usize>(binary: &Bvh, index: usize) -> SmallVec<[usize; 8]> {
+    let NodeKind::Interior { right } = binary.nodes[index].kind else {
+        unreachable!("leaves have no children");
+    };
+    let mut children: SmallVec<[usize; 8]> = SmallVec::from_slice(&[index + 1, right]);
+    while children.len() < N {
+        let biggest = children
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| matches!(binary.nodes[**c].kind, NodeKind::Interior { .. }))
+            .max_by(|a, b| {
+                let area = |c: &usize| binary.nodes[*c].bounds.surface_area();
+                area(a.1).total_cmp(&area(b.1))
+            })
+            .map(|(k, _)| k);
+        let Some(k) = biggest else {
+            break;
+        };
+        let NodeKind::Interior { right } = binary.nodes[children[k]].kind else {
+            unreachable!();
+        };
+        children[k] += 1;
+        children.push(right);
+    }
+    children
+}
+
+impl<const N: usize> Accelerator for WideBvh<N> {
+    /// Like `Bvh::refit`, from the leaves up; nodes come before the nodes
+    /// below them.
+    fn refit(&mut self, bounds: &[Aabb]) -> bool {
+        if bounds.len() != self.indices.len() {
+            return false;
+        }
+        for index in (0..self.nodes.len()).rev() {
+            for lane in 0..N {
+                let child_bounds = match self.nodes[index].child(lane) {
+                    Child::Empty => continue,
+                    Child::Node(child) => self.nodes[child].union(),
+                    Child::Leaf(l) => self
+                        .leaf(l)
+                        .iter()
+                        .fold(Aabb::empty(), |b, i| b.union(&bounds[*i as usize])),
+                };
+                let node = &mut self.nodes.to_mut()[index];
+                for axis in 0..3 {
+                    node.min[axis][lane] = child_bounds.min.0 .0[axis];
+                    node.max[axis][lane] = child_bounds.max.0 .0[axis];
+                }
+            }
+        }
+        self.bounds = self.nodes.first().map(Node::union);
+        self.cost(bounds) <= REFIT_LIMIT * self.built_cost
+    }
+
+    fn closest_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) -> Option<Float> {
+        let mut primitives = 0;
+        let closest = self.closest_leaf(r, max_distance, traversal, |l, mut max| {
+            let mut closest = None;
+            for i in self.leaf(l) {
+                primitives += 1;
+                if let Some(distance) = hit(*i as usize, max) {
+                    max = distance;
+                    closest = Some(distance);
+                }
+            }
+            closest
+        });
+        traversal.primitives += primitives;
+        closest
+    }
+
+    fn any_hit(
+        &self,
+        r: &Ray,
+        max_distance: Float,
+        hit: &mut dyn FnMut(usize) -> bool,
+        traversal: &mut Traversal,
+    ) -> bool {
+        let mut primitives = 0;
+        let blocked = self.any_leaf(r, max_distance, traversal, |l| {
+            self.leaf(l).iter().any(|i| {
+                primitives += 1;
+                hit(*i as usize)
+            })
+        });
+        traversal.primitives += primitives;
+        blocked
+    }
+
+    /// Like `Bvh::closest_hits`, a child is entered by the range of rays
+    /// that reach it. The first of them alone, with one SIMD test of a
+    /// node's children, decides the order they are searched in.
+    fn closest_hits(
+        &self,
+        packet: &Packet,
+        max_distances: &mut [Float],
+        hit: &mut dyn FnMut(usize, usize, Float) -> Option<Float>,
+        traversal: &mut Traversal,
+    ) {
+        // children are known by their parent and lane, which hold their box
+        let mut stack: SmallVec<[(usize, usize, Range<usize>); 64]> = SmallVec::new();
+        if !self.nodes.is_empty() && !packet.is_empty() {
+            stack.extend((0..N).rev().map(|lane| (0, lane, 0..packet.len())));
+        }
+        while let Some((parent, lane, range)) = stack.pop() {
+            let bounds = self.nodes[parent].bounds(lane);
+            let child = self.nodes[parent].child(lane);
+            if matches!(child, Child::Empty) {
+                continue;
+            }
+            let Some(active) = packet.active(&bounds, range, max_distances) else {
+                continue;
+            };
+            let first = active.start;
+            traversal.nodes += 1;
+            match child {
+                Child::Node(index) => {
+                    let r = &packet.rays()[first];
+                    let origin = r.0 .0 .0;
+                    let inverse = r.1 .0 .0.map(|d| 1.0 / d);
+                    let entered = self.entered(index, &origin, &inverse, max_distances[first]);
+                    // what the first ray misses, later ones may still enter,
+                    // so it goes below the rest
+                    let missed = (0..N).filter(|lane| entered.iter().all(|(_, l)| l != lane));
+                    stack.extend(missed.map(|lane| (index, lane, active.clone())));
+                    stack.extend(
+                        entered
+                            .into_iter()
+                            .map(|(_, lane)| (index, lane, active.clone())),
+                    );
+                }
+                Child::Leaf(l) => {
+                    for (k, max) in max_distances
+                        .iter_mut()
+                        .enumerate()
+                        .take(active.end)
+                        .skip(first)
+                    {
+                        if k > first && packet.entry(k, &bounds, *max).is_none() {
+                            continue;
+                        }
+                        for i in self.leaf(l) {
+                            traversal.primitives += 1;
+                            if let Some(distance) = hit(k, *i as usize, *max) {
+                                *max = distance;
+                            }
+                        }
+                    }
+                }
+                Child::Empty => {}
+            }
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        self.bounds
+    }
+
+    fn stats(&self) -> Stats {
+        let mut stats = Stats {
+            name: if N == 4 { "bvh4" } else { "bvh8" },
+            primitives: self.indices.len(),
+            nodes: self.nodes.len(),
+            leaves: self.leaves.len(),
+            references: self.indices.len(),
+            bytes: self.nodes.len() * std::mem::size_of::<Node<N>>()
+                + self.leaves.len() * std::mem::size_of::<[u32; 2]>()
+                + self.indices.len() * std::mem::size_of::<u32>(),
+            ..Stats::default()
+        };
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push((0, 1));
+        }
+        while let Some((index, depth)) = stack.pop() {
+            stats.depth = stats.depth.max(depth);
+            for lane in 0..N {
+                match self.nodes[index].child(lane) {
+                    Child::Node(c) => stack.push((c, depth + 1)),
+                    Child::Leaf(_) => stats.depth = stats.depth.max(depth + 1),
+                    Child::Empty => {}
+                }
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn collapse() {
+        let bounds: Vec<Aabb> = (0..1000)
+            .map(|i| {
+                let p = Point(Vec3([
+                    (i % 10) as Float,
+                    (i / 10 % 10) as Float,
+                    (i / 100) as Float,
+                ]));
+                Aabb {
+                    min: p,
+                    max: p + Direction(Vec3([0.5; 3])),
+                }
+            })
+            .collect();
+        let binary = Bvh::new(&bounds).stats();
+        for stats in [
+            WideBvh::<4>::new(&bounds).stats(),
+            WideBvh::<8>::new(&bounds).stats(),
+        ] {
+            // the same leaves, under fewer, shallower nodes
+            assert_eq!(binary.leaves, stats.leaves);
+            assert!(stats.nodes < binary.nodes / 2);
+            assert!(stats.depth < binary.depth);
+        }
+    }
+}